@@ -0,0 +1,233 @@
+//! # Color spaces and conversions
+//!
+//! Colors are in sRGB or linear sRGB unless a color space is given.
+//! Conversion between color spaces goes through linear sRGB,
+//! using 3x3 matrices for primaries and transfer functions for gamma.
+//!
+//! ACEScg uses the D60 white point, so its matrices include Bradford chromatic adaptation.
+
+#![deny(missing_docs)]
+
+/// RGB color.
+pub type Rgb<T = f32> = [T; 3];
+/// RGBA color.
+pub type Rgba<T = f32> = [T; 4];
+
+/// Color space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ColorSpace {
+    /// Linear color space with sRGB primaries.
+    Linear,
+    /// sRGB color space.
+    SRGB,
+    /// Display P3 color space, with sRGB transfer function.
+    DisplayP3,
+    /// Linear color space with Display P3 primaries.
+    LinearDisplayP3,
+    /// ACEScg color space, which is linear with AP1 primaries.
+    ACEScg,
+}
+
+impl ColorSpace {
+    /// Returns `true` if the color space has linear gamma.
+    pub fn is_linear(self) -> bool {
+        use ColorSpace::*;
+
+        match self {
+            Linear | LinearDisplayP3 | ACEScg => true,
+            SRGB | DisplayP3 => false,
+        }
+    }
+}
+
+/// Stores a 3x3 matrix for converting linear colors, row by row.
+pub type ColorMatrix = [[f32; 3]; 3];
+
+/// Converts linear sRGB to linear Display P3.
+pub const SRGB_TO_DISPLAY_P3: ColorMatrix = [
+    [0.822462, 0.177538, 0.0],
+    [0.033194, 0.966806, 0.0],
+    [0.017083, 0.072397, 0.91052],
+];
+/// Converts linear Display P3 to linear sRGB.
+pub const DISPLAY_P3_TO_SRGB: ColorMatrix = [
+    [1.22494, -0.22494, 0.0],
+    [-0.042057, 1.042057, 0.0],
+    [-0.019638, -0.078636, 1.098273],
+];
+/// Converts linear sRGB to ACEScg.
+pub const SRGB_TO_ACESCG: ColorMatrix = [
+    [0.613097, 0.339523, 0.04738],
+    [0.070194, 0.916354, 0.013452],
+    [0.020616, 0.10957, 0.869815],
+];
+/// Converts ACEScg to linear sRGB.
+pub const ACESCG_TO_SRGB: ColorMatrix = [
+    [1.705051, -0.621792, -0.083259],
+    [-0.130256, 1.140805, -0.010548],
+    [-0.024003, -0.128969, 1.152972],
+];
+
+/// Multiplies linear color with color matrix.
+pub fn rgb_color_matrix(m: &ColorMatrix, c: Rgb) -> Rgb {
+    [0, 1, 2].map(|i| m[i][0] * c[0] + m[i][1] * c[1] + m[i][2] * c[2])
+}
+
+/// Converts color from linear sRGB to some color space.
+pub fn rgb_linear_to_space(c: Rgb, space: ColorSpace) -> Rgb {
+    use ColorSpace::*;
+
+    match space {
+        Linear => c,
+        SRGB => rgb_gamma_linear_to_srgb(c),
+        DisplayP3 => rgb_gamma_linear_to_srgb(rgb_color_matrix(&SRGB_TO_DISPLAY_P3, c)),
+        LinearDisplayP3 => rgb_color_matrix(&SRGB_TO_DISPLAY_P3, c),
+        ACEScg => rgb_color_matrix(&SRGB_TO_ACESCG, c),
+    }
+}
+
+/// Converts color from some color space to linear sRGB.
+pub fn rgb_space_to_linear(c: Rgb, space: ColorSpace) -> Rgb {
+    use ColorSpace::*;
+
+    match space {
+        Linear => c,
+        SRGB => rgb_gamma_srgb_to_linear(c),
+        DisplayP3 => rgb_color_matrix(&DISPLAY_P3_TO_SRGB, rgb_gamma_srgb_to_linear(c)),
+        LinearDisplayP3 => rgb_color_matrix(&DISPLAY_P3_TO_SRGB, c),
+        ACEScg => rgb_color_matrix(&ACESCG_TO_SRGB, c),
+    }
+}
+
+/// Converts color between color spaces.
+pub fn rgb_convert(c: Rgb, from: ColorSpace, to: ColorSpace) -> Rgb {
+    if from == to {c} else {rgb_linear_to_space(rgb_space_to_linear(c, from), to)}
+}
+
+/// Converts color from linear sRGB to some color space, keeping alpha.
+pub fn rgba_linear_to_space(c: Rgba, space: ColorSpace) -> Rgba {
+    let [r, g, b] = rgb_linear_to_space([c[0], c[1], c[2]], space);
+    [r, g, b, c[3]]
+}
+
+/// Converts color from some color space to linear sRGB, keeping alpha.
+pub fn rgba_space_to_linear(c: Rgba, space: ColorSpace) -> Rgba {
+    let [r, g, b] = rgb_space_to_linear([c[0], c[1], c[2]], space);
+    [r, g, b, c[3]]
+}
+
+/// Converts color between color spaces, keeping alpha.
+pub fn rgba_convert(c: Rgba, from: ColorSpace, to: ColorSpace) -> Rgba {
+    let [r, g, b] = rgb_convert([c[0], c[1], c[2]], from, to);
+    [r, g, b, c[3]]
+}
+
+/// Linear color with premultiplied alpha.
+///
+/// Color channels are multiplied by alpha,
+/// which makes blending and filtering correct at transparent edges.
+/// Premultiplied colors must be linear, since gamma does not commute with multiplication.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Premultiplied(pub Rgba);
+
+impl Premultiplied {
+    /// Converts from linear color with straight alpha.
+    pub fn from_straight(c: Rgba) -> Premultiplied {
+        Premultiplied([c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]])
+    }
+
+    /// Converts from color with straight alpha in some color space.
+    pub fn from_space(c: Rgba, space: ColorSpace) -> Premultiplied {
+        Premultiplied::from_straight(rgba_space_to_linear(c, space))
+    }
+
+    /// Converts to linear color with straight alpha.
+    ///
+    /// Fully transparent colors become transparent black.
+    pub fn to_straight(self) -> Rgba {
+        let [r, g, b, a] = self.0;
+        if a == 0.0 {return [0.0; 4]};
+        [r / a, g / a, b / a, a]
+    }
+
+    /// Converts to color with straight alpha in some color space.
+    pub fn to_space(self, space: ColorSpace) -> Rgba {
+        rgba_linear_to_space(self.to_straight(), space)
+    }
+
+    /// Alpha blending over operation.
+    pub fn over(self, b: Premultiplied) -> Premultiplied {
+        let (a, b) = (self.0, b.0);
+        let t = 1.0 - a[3];
+        Premultiplied([a[0] + b[0] * t, a[1] + b[1] * t, a[2] + b[2] * t, a[3] + b[3] * t])
+    }
+}
+
+#[inline(always)]
+fn component_srgb_to_linear(f: f32) -> f32 {
+    if f <= 0.04045 {
+        f / 12.92
+    } else {
+        ((f + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts gamma (brightness) from sRGB to linear color space.
+///
+/// sRGB is the default color space for image editors, pictures, internet etc.
+/// Linear gamma yields better results when doing math with colors.
+pub fn rgb_gamma_srgb_to_linear(c: Rgb) -> Rgb {
+    [
+        component_srgb_to_linear(c[0]),
+        component_srgb_to_linear(c[1]),
+        component_srgb_to_linear(c[2]),
+    ]
+}
+
+/// Converts gamma (brightness) from sRGB to linear color space.
+///
+/// sRGB is the default color space for image editors, pictures, internet etc.
+/// Linear gamma yields better results when doing math with colors.
+pub fn rgba_gamma_srgb_to_linear(c: Rgba) -> Rgba {
+    [
+        component_srgb_to_linear(c[0]),
+        component_srgb_to_linear(c[1]),
+        component_srgb_to_linear(c[2]),
+        c[3],
+    ]
+}
+
+#[inline(always)]
+fn component_linear_to_srgb(f: f32) -> f32 {
+    if f <= 0.0031308 {
+        f * 12.92
+    } else {
+        1.055 * f.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts gamma (brightness) of a color from linear color space to sRGB.
+///
+/// sRGB is the default color space for image editors, pictures, internet etc.
+/// Linear gamma yields better results when doing math with colors.
+pub fn rgb_gamma_linear_to_srgb(c: Rgb) -> Rgb {
+    [
+        component_linear_to_srgb(c[0]),
+        component_linear_to_srgb(c[1]),
+        component_linear_to_srgb(c[2]),
+    ]
+}
+
+/// Converts gamma (brightness) of a color from linear color space to sRGB.
+///
+/// sRGB is the default color space for image editors, pictures, internet etc.
+/// Linear gamma yields better results when doing math with colors.
+pub fn rgba_gamma_linear_to_srgb(c: Rgba) -> Rgba {
+    [
+        component_linear_to_srgb(c[0]),
+        component_linear_to_srgb(c[1]),
+        component_linear_to_srgb(c[2]),
+        c[3],
+    ]
+}
@@ -0,0 +1,90 @@
+//! Compares scalar and structure-of-arrays chunk algorithms.
+//!
+//! Run with `cargo run --release --example bench_soa`.
+
+use std::hint::black_box;
+use std::time::Instant;
+use turbine_process3d::prelude::*;
+
+const ROUNDS: u32 = 200;
+const RAYS: u32 = 32;
+
+fn main() {
+    let mut seed: u32 = 1;
+    let mut rnd = || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+    };
+    let mut chunk: Chunk<Triangle> = [([0.0; 3], [0.0; 3], [0.0; 3]); 64];
+    for tri in chunk.iter_mut() {
+        let c = [rnd(), rnd(), rnd() + 3.0];
+        *tri = (c, [c[0] + rnd(), c[1] + rnd(), c[2]], [c[0], c[1] + rnd(), c[2] + rnd()]);
+    }
+    let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+    let dim = [RAYS, RAYS];
+    let eye = [0.0; 3];
+    let dirs: Vec<Vector> = (0..RAYS * RAYS)
+        .map(|k| ray_dir(&persp, eye, [k % RAYS, k / RAYS], dim))
+        .collect();
+    let ndim = near_dim(&persp);
+    let planes: Vec<FrustumPlanes> = (0..RAYS * RAYS)
+        .map(|k| {
+            let pos = [k % RAYS, k / RAYS];
+            frustum_planes_tile(&persp, ndim, tile_pos(dim, pos, 1), tile_size(dim, pos, 1))
+        })
+        .collect();
+
+    for (name, mask) in [("full", !0_u64), ("half", 0x0f0f_0f0f_0f0f_0f0f), ("sparse", 0x0101)] {
+        println!("mask: {}", name);
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let mut hit: RayHit = None;
+            for &dir in &dirs {
+                ray_triangle_chunk_hit_update((eye, dir), black_box(&chunk), mask, 0, &mut hit);
+            }
+            black_box(hit);
+        }
+        let scalar = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let soa = triangle_chunk_soa(black_box(&chunk));
+            let mut hit: RayHit = None;
+            for &dir in &dirs {
+                ray_triangle_chunk_soa_hit_update((eye, dir), &soa, mask, 0, &mut hit);
+            }
+            black_box(hit);
+        }
+        let soa = start.elapsed();
+        println!("  ray hit       scalar {:>10.3?}  soa {:>10.3?}", scalar, soa);
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for fr in &planes {
+                black_box(frustum_planes_triangle_chunk_mask(fr, black_box(&chunk), mask));
+            }
+        }
+        let scalar = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let aabbs = triangle_chunk_aabb_soa(black_box(&chunk));
+            for fr in &planes {
+                black_box(frustum_planes_aabb_chunk_soa_mask(fr, &aabbs, mask));
+            }
+        }
+        let soa = start.elapsed();
+        println!("  frustum mask  scalar {:>10.3?}  soa {:>10.3?}", scalar, soa);
+
+        // Tile masks test each chunk once per tile, so the conversion is not shared.
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for fr in &planes {
+                black_box(frustum_planes_triangle_chunk_soa_mask(fr, black_box(&chunk), mask));
+            }
+        }
+        let soa = start.elapsed();
+        println!("  tile mask     scalar {:>10.3?}  soa {:>10.3?}", scalar, soa);
+    }
+}
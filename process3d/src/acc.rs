@@ -28,6 +28,10 @@ pub trait Acc {
     type Out;
     /// Initialize the accumulator.
     fn new(data: Self::Data) -> Self;
+    /// Get the largest tile size supported with constructor data.
+    ///
+    /// Returns `None` if the tile size is not limited.
+    fn tile_capacity(_data: &Self::Data) -> Option<u32> {None}
     /// Reset the accumulator.
     fn clear(&mut self);
     /// Update the accumulator with new data.
@@ -36,6 +40,50 @@ pub trait Acc {
     fn acc(&self, i: u32, j: u32) -> Self::Out;
 }
 
+/// Inserts color into semi-fog buffer, sorted by depth.
+fn semi_fog_ins(buf: &mut [(f32, Rgba)], buf_len: &mut u8, len: u8, mut depth: f32, mut color: Rgba) {
+    for k in 0..len {
+        let (d, c) = buf[k as usize];
+        if d == 0.0 {
+            buf[k as usize] = (depth, color);
+            return;
+        } else if d > depth {
+            buf[k as usize] = (depth, color);
+
+            // There is no need to look behind opaque colors.
+            if color[3] >= 1.0 {
+                *buf_len = k + 1;
+                return
+            };
+
+            depth = d;
+            color = c;
+        }
+    }
+}
+
+/// Composes colors in semi-fog buffer using sRGB color space.
+fn semi_fog_srgb(buf: &[(f32, Rgba)]) -> Rgba {
+    let mut color = [0.0; 4];
+    let mut fog = FogState::None;
+    for &(d, c) in buf {
+        fog.acc_alpha_blend_srgb_over(d, c, &mut color);
+    }
+    fog.acc_end_alpha_blend_srgb_over(&mut color);
+    color
+}
+
+/// Composes colors in semi-fog buffer using linear color space.
+fn semi_fog_linear(buf: &[(f32, Rgba)]) -> Rgba {
+    let mut color = [0.0; 4];
+    let mut fog = FogState::None;
+    for &(d, c) in buf {
+        fog.acc_alpha_blend_linear_over(d, c, &mut color);
+    }
+    fog.acc_end_alpha_blend_linear_over(&mut color);
+    color
+}
+
 /// Accumulator for tile rendering, using Rgba colors
 /// and minimum distance selection.
 pub struct TileRgbaMinDepthAcc<const TILE_SIZE: usize> {
@@ -52,6 +100,7 @@ impl<const TILE_SIZE: usize> Acc for TileRgbaMinDepthAcc<TILE_SIZE> {
             buf: [[(0.0, [0.0; 4]); TILE_SIZE]; TILE_SIZE],
         }
     }
+    fn tile_capacity(_: &()) -> Option<u32> {Some(TILE_SIZE as u32)}
     fn clear(&mut self) {
         self.buf = [[(0.0, [0.0; 4]); TILE_SIZE]; TILE_SIZE];
     }
@@ -86,6 +135,7 @@ impl<const TILE_SIZE: usize, const ACC: usize> Acc for TileRgbaSrgbSemiFogAcc<TI
             len: [[0; TILE_SIZE]; TILE_SIZE],
         }
     }
+    fn tile_capacity(_: &()) -> Option<u32> {Some(TILE_SIZE as u32)}
     fn clear(&mut self) {
         self.buf = [[[(0.0, [0.0; 4]); ACC]; TILE_SIZE]; TILE_SIZE];
         self.len = [[0; TILE_SIZE]; TILE_SIZE];
@@ -98,45 +148,15 @@ impl<const TILE_SIZE: usize, const ACC: usize> Acc for TileRgbaSrgbSemiFogAcc<TI
             *len += 1;
             *len
         };
-        self.ins(len, i, j, depth, color);
+        let buf = &mut self.buf[j as usize][i as usize];
+        semi_fog_ins(buf, &mut self.len[j as usize][i as usize], len, depth, color);
     }
     fn acc(&self, i: u32, j: u32) -> Rgba {
         let len = self.len[j as usize][i as usize];
-        let buf = &self.buf[j as usize][i as usize];
-        let mut color = [0.0; 4];
-        let mut fog = FogState::None;
-        for k in 0..len {
-            let (d, c) = buf[k as usize];
-            fog.acc_alpha_blend_srgb_over(d, c, &mut color);
-        }
-        fog.acc_end_alpha_blend_srgb_over(&mut color);
-        color
+        semi_fog_srgb(&self.buf[j as usize][i as usize][..len as usize])
     }
 }
 
-impl<const TILE_SIZE: usize, const ACC: usize> TileRgbaSrgbSemiFogAcc<TILE_SIZE, ACC> {
-    fn ins(&mut self, len: u8, i: u32, j: u32, mut depth: f32, mut color: Rgba) {
-        let buf = &mut self.buf[j as usize][i as usize];
-        for k in 0..len {
-            let (d, c) = buf[k as usize];
-            if d == 0.0 {
-                buf[k as usize] = (depth, color);
-                return;
-            } else if d > depth {
-                buf[k as usize] = (depth, color);
-
-                // There is no need to look behind opaque colors.
-                if color[3] >= 1.0 {
-                    self.len[j as usize][i as usize] = k + 1;
-                    return
-                };
-
-                depth = d;
-                color = c;
-            }
-        }
-    }
-}
 
 /// Accumulator for tile rendering, using Rgba colors in linear color space
 /// and semi-fog effect for alpha over blending.
@@ -159,6 +179,7 @@ impl<const TILE_SIZE: usize, const ACC: usize> Acc for TileRgbaLinearSemiFogAcc<
             len: [[0; TILE_SIZE]; TILE_SIZE],
         }
     }
+    fn tile_capacity(_: &()) -> Option<u32> {Some(TILE_SIZE as u32)}
     fn clear(&mut self) {
         self.buf = [[[(0.0, [0.0; 4]); ACC]; TILE_SIZE]; TILE_SIZE];
         self.len = [[0; TILE_SIZE]; TILE_SIZE];
@@ -171,42 +192,131 @@ impl<const TILE_SIZE: usize, const ACC: usize> Acc for TileRgbaLinearSemiFogAcc<
             *len += 1;
             *len
         };
-        self.ins(len, i, j, depth, color);
+        let buf = &mut self.buf[j as usize][i as usize];
+        semi_fog_ins(buf, &mut self.len[j as usize][i as usize], len, depth, color);
     }
     fn acc(&self, i: u32, j: u32) -> Rgba {
         let len = self.len[j as usize][i as usize];
-        let buf = &self.buf[j as usize][i as usize];
-        let mut color = [0.0; 4];
-        let mut fog = FogState::None;
-        for k in 0..len {
-            let (d, c) = buf[k as usize];
-            fog.acc_alpha_blend_linear_over(d, c, &mut color);
-        }
-        fog.acc_end_alpha_blend_linear_over(&mut color);
-        color
+        semi_fog_linear(&self.buf[j as usize][i as usize][..len as usize])
     }
 }
 
-impl<const TILE_SIZE: usize, const ACC: usize> TileRgbaLinearSemiFogAcc<TILE_SIZE, ACC> {
-    fn ins(&mut self, len: u8, i: u32, j: u32, mut depth: f32, mut color: Rgba) {
-        let buf = &mut self.buf[j as usize][i as usize];
-        for k in 0..len {
-            let (d, c) = buf[k as usize];
-            if d == 0.0 {
-                buf[k as usize] = (depth, color);
-                return;
-            } else if d > depth {
-                buf[k as usize] = (depth, color);
-
-                // There is no need to look behind opaque colors.
-                if color[3] >= 1.0 {
-                    self.len[j as usize][i as usize] = k + 1;
-                    return
-                };
-
-                depth = d;
-                color = c;
-            }
+
+/// Same as `TileRgbaMinDepthAcc`, but with tile size at runtime.
+///
+/// The constructor data is the tile size.
+/// The buffer is stored row by row on the heap.
+pub struct VecTileRgbaMinDepthAcc {
+    /// The tile size.
+    pub tile_size: u32,
+    /// Stores buffer data for the accumulator.
+    pub buf: Vec<(f32, Rgba)>,
+}
+
+impl Acc for VecTileRgbaMinDepthAcc {
+    type Data = u32;
+    type In = Rgba;
+    type Out = Rgba;
+    fn new(tile_size: u32) -> Self {
+        let n = (tile_size * tile_size) as usize;
+        Self {tile_size, buf: vec![(0.0, [0.0; 4]); n]}
+    }
+    fn tile_capacity(tile_size: &u32) -> Option<u32> {Some(*tile_size)}
+    fn clear(&mut self) {
+        self.buf.fill((0.0, [0.0; 4]));
+    }
+    fn upd(&mut self, i: u32, j: u32, depth: f32, color: Rgba) {
+        let (d, c) = &mut self.buf[(j * self.tile_size + i) as usize];
+        if *d == 0.0 || *d > depth {
+            *d = depth;
+            *c = color;
         }
     }
+    fn acc(&self, i: u32, j: u32) -> Rgba {self.buf[(j * self.tile_size + i) as usize].1}
+}
+
+/// Same as `TileRgbaSrgbSemiFogAcc`, but with tile size at runtime.
+///
+/// The constructor data is the tile size.
+/// The buffer is stored row by row on the heap.
+pub struct VecTileRgbaSrgbSemiFogAcc<const ACC: usize> {
+    /// The tile size.
+    pub tile_size: u32,
+    /// Stores buffer data of the accumulator.
+    pub buf: Vec<[(f32, Rgba); ACC]>,
+    /// Stores the buffer lengths.
+    pub len: Vec<u8>,
+}
+
+impl<const ACC: usize> Acc for VecTileRgbaSrgbSemiFogAcc<ACC> {
+    type Data = u32;
+    type In = Rgba;
+    type Out = Rgba;
+    fn new(tile_size: u32) -> Self {
+        let n = (tile_size * tile_size) as usize;
+        Self {tile_size, buf: vec![[(0.0, [0.0; 4]); ACC]; n], len: vec![0; n]}
+    }
+    fn tile_capacity(tile_size: &u32) -> Option<u32> {Some(*tile_size)}
+    fn clear(&mut self) {
+        self.buf.fill([(0.0, [0.0; 4]); ACC]);
+        self.len.fill(0);
+    }
+    fn upd(&mut self, i: u32, j: u32, depth: f32, color: Rgba) {
+        let k = (j * self.tile_size + i) as usize;
+        let len = {
+            let len = &mut self.len[k];
+            if *len as usize >= ACC {return};
+
+            *len += 1;
+            *len
+        };
+        semi_fog_ins(&mut self.buf[k], &mut self.len[k], len, depth, color);
+    }
+    fn acc(&self, i: u32, j: u32) -> Rgba {
+        let k = (j * self.tile_size + i) as usize;
+        semi_fog_srgb(&self.buf[k][..self.len[k] as usize])
+    }
+}
+
+/// Same as `TileRgbaLinearSemiFogAcc`, but with tile size at runtime.
+///
+/// The constructor data is the tile size.
+/// The buffer is stored row by row on the heap.
+pub struct VecTileRgbaLinearSemiFogAcc<const ACC: usize> {
+    /// The tile size.
+    pub tile_size: u32,
+    /// Stores buffer data of the accumulator.
+    pub buf: Vec<[(f32, Rgba); ACC]>,
+    /// Stores the buffer lengths.
+    pub len: Vec<u8>,
+}
+
+impl<const ACC: usize> Acc for VecTileRgbaLinearSemiFogAcc<ACC> {
+    type Data = u32;
+    type In = Rgba;
+    type Out = Rgba;
+    fn new(tile_size: u32) -> Self {
+        let n = (tile_size * tile_size) as usize;
+        Self {tile_size, buf: vec![[(0.0, [0.0; 4]); ACC]; n], len: vec![0; n]}
+    }
+    fn tile_capacity(tile_size: &u32) -> Option<u32> {Some(*tile_size)}
+    fn clear(&mut self) {
+        self.buf.fill([(0.0, [0.0; 4]); ACC]);
+        self.len.fill(0);
+    }
+    fn upd(&mut self, i: u32, j: u32, depth: f32, color: Rgba) {
+        let k = (j * self.tile_size + i) as usize;
+        let len = {
+            let len = &mut self.len[k];
+            if *len as usize >= ACC {return};
+
+            *len += 1;
+            *len
+        };
+        semi_fog_ins(&mut self.buf[k], &mut self.len[k], len, depth, color);
+    }
+    fn acc(&self, i: u32, j: u32) -> Rgba {
+        let k = (j * self.tile_size + i) as usize;
+        semi_fog_linear(&self.buf[k][..self.len[k] as usize])
+    }
 }
@@ -0,0 +1,328 @@
+//! # Lightmap baking
+//!
+//! Bakes ambient occlusion and direct light into texture space, e.g. for lightmaps in games.
+//!
+//! Triangles are given by a producer, with UV coordinates per triangle given by
+//! another producer using the same virtual list indices.
+//! Each triangle is rasterized in UV space, where texel centers inside the triangle
+//! are baked from the world position and geometric normal at the texel.
+//!
+//! The lit side of a triangle is given by its winding order,
+//! using the same normal as `triangle_plane`.
+//!
+//! Ambient occlusion casts cosine-weighted rays in the hemisphere around the normal.
+//! For each baked triangle, a compressed mask of the chunks within ambient occlusion distance
+//! is computed once, such that rays only visit nearby chunks.
+//! Shadow rays toward lights use a `RayQuery` over the whole producer.
+//!
+//! Random rays are seeded per texel, so results are deterministic
+//! regardless of how texels are split between threads.
+//!
+//! Texels are stored row by row from the top of the image,
+//! where UV `v` points upwards, such that `[0.0, 0.0]` is the lower left corner.
+
+use crate::{PixelPos, Point, Ray, Rgb, Rgba, Triangle, Uv, Vector};
+use crate::mask::CompressedMasks;
+use crate::produce::Produce;
+use crate::query::RayQuery;
+
+/// Triangle in UV space.
+pub type UvTriangle<T = f32> = (Uv<T>, Uv<T>, Uv<T>);
+
+/// Light used when baking.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BakeLight {
+    /// Light from a direction, e.g. the sun.
+    Directional {
+        /// The normalized direction toward the light.
+        dir: Vector,
+        /// The linear color of light.
+        color: Rgb,
+    },
+    /// Light from a point, with inverse square falloff.
+    Point {
+        /// The position of light.
+        pos: Point,
+        /// The linear color of light at unit distance.
+        color: Rgb,
+    },
+}
+
+/// Lightmap bake settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BakeSettings {
+    /// The lightmap size in texels.
+    pub size: PixelPos,
+    /// The number of ambient occlusion rays per texel (`0` to disable).
+    pub ao_samples: u32,
+    /// The maximum distance of occluders for ambient occlusion.
+    pub ao_distance: f32,
+    /// The linear ambient color, scaled by ambient occlusion.
+    pub ambient: Rgb,
+    /// Lights for direct lighting.
+    pub lights: Vec<BakeLight>,
+    /// The distance to offset ray origins along the normal, to avoid self-intersection.
+    pub bias: f32,
+    /// The random seed.
+    pub seed: u64,
+    /// The number of texels to grow baked areas into gutters.
+    pub dilation: u32,
+}
+
+impl BakeSettings {
+    /// Creates ambient occlusion bake settings, with white ambient color and no lights.
+    pub fn new(size: PixelPos) -> BakeSettings {
+        BakeSettings {
+            size,
+            ao_samples: 64,
+            ao_distance: 1.0,
+            ambient: [1.0; 3],
+            lights: vec![],
+            bias: 1e-3,
+            seed: 0,
+            dilation: 2,
+        }
+    }
+}
+
+/// Baked lightmap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lightmap {
+    /// The size in texels.
+    pub size: PixelPos,
+    /// Linear colors, stored row by row from top.
+    ///
+    /// Alpha is `1.0` for baked and dilated texels and `0.0` for empty texels.
+    pub texels: Vec<Rgba>,
+}
+
+impl Lightmap {
+    /// Writes texels in sRGB color space to an image.
+    pub fn write(&self, mut pxl: impl FnMut(PixelPos, Rgba<u8>)) {
+        use crate::color::{rgba_gamma_linear_to_srgb, rgba_to_u8};
+
+        let [w, h] = self.size;
+        for y in 0..h {
+            for x in 0..w {
+                pxl([x, y], rgba_to_u8(rgba_gamma_linear_to_srgb(self.texels[(y * w + x) as usize])));
+            }
+        }
+    }
+}
+
+/// Stores a rasterized texel.
+#[derive(Copy, Clone, Debug)]
+struct Texel {
+    index: usize,
+    pos: Point,
+    normal: Vector,
+}
+
+/// Calculates barycentric coordinates of point in UV triangle.
+///
+/// Returns `None` if the triangle is degenerate.
+pub fn uv_barycentric((a, b, c): UvTriangle, p: Uv) -> Option<Point> {
+    let det = (b[1] - c[1]) * (a[0] - c[0]) + (c[0] - b[0]) * (a[1] - c[1]);
+    if det.abs() < f32::EPSILON {return None};
+
+    let u = ((b[1] - c[1]) * (p[0] - c[0]) + (c[0] - b[0]) * (p[1] - c[1])) / det;
+    let v = ((c[1] - a[1]) * (p[0] - c[0]) + (a[0] - c[0]) * (p[1] - c[1])) / det;
+    Some([u, v, 1.0 - u - v])
+}
+
+/// Returns a cosine-weighted direction in the hemisphere around a normal.
+pub fn hemisphere_dir(normal: Vector, r: [f32; 2]) -> Vector {
+    use std::f32::consts::PI;
+    use vecmath::vec3_cross as cross;
+    use vecmath::vec3_normalized as normalized;
+
+    let helper = if normal[0].abs() < 0.9 {[1.0, 0.0, 0.0]} else {[0.0, 1.0, 0.0]};
+    let t = normalized(cross(helper, normal));
+    let b = cross(normal, t);
+    let phi = 2.0 * PI * r[0];
+    let sr = r[1].sqrt();
+    let (x, y, z) = (sr * phi.cos(), sr * phi.sin(), (1.0 - r[1]).sqrt());
+    [0, 1, 2].map(|i| t[i] * x + b[i] * y + normal[i] * z)
+}
+
+/// Rasterizes triangles in UV space, keeping the lowest triangle index per texel.
+fn bake_rasterize<P, U>(list: &P, uvs: &U, size: PixelPos) -> Vec<Option<Texel>>
+    where P: Produce<Triangle> + ?Sized, U: Produce<UvTriangle> + ?Sized
+{
+    use crate::triangle::triangle_plane;
+
+    let [w, h] = size;
+    let mut texels = vec![None; (w * h) as usize];
+    let n = list.virtual_length().min(uvs.virtual_length());
+    for off in (0..n).step_by(64) {
+        let tris = list.produce(off);
+        let uv_tris = uvs.produce(off);
+        for i in 0..(n - off).min(64) {
+            let (a, b, c) = tris[i];
+            let uv = uv_tris[i];
+            // Convert to texel coordinates, with the y-axis pointing downwards.
+            let to_px = |p: Uv| [p[0] * w as f32, (1.0 - p[1]) * h as f32];
+            let px = (to_px(uv.0), to_px(uv.1), to_px(uv.2));
+            let mi = |k: usize| px.0[k].min(px.1[k]).min(px.2[k]).floor().max(0.0) as u32;
+            let ma = |k: usize, n: u32| (px.0[k].max(px.1[k]).max(px.2[k]).ceil().max(0.0) as u32).min(n);
+            let normal = triangle_plane((a, b, c)).0;
+            if normal.iter().any(|x| x.is_nan()) {continue};
+
+            for y in mi(1)..ma(1, h) {
+                for x in mi(0)..ma(0, w) {
+                    let k = (y * w + x) as usize;
+                    if texels[k].is_some() {continue};
+                    let Some(bary) = uv_barycentric(px, [x as f32 + 0.5, y as f32 + 0.5]) else {continue};
+                    if bary.iter().any(|&t| t < 0.0) {continue};
+
+                    let pos = [0, 1, 2].map(|j| a[j] * bary[0] + b[j] * bary[1] + c[j] * bary[2]);
+                    texels[k] = Some(Texel {index: off + i, pos, normal});
+                }
+            }
+        }
+    }
+    texels
+}
+
+/// Computes mask of chunks with cached AABB within some distance of a point set AABB.
+fn bake_near_mask(query: &RayQuery, (mi, ma): (Point, Point), dist: f32) -> CompressedMasks {
+    let mut masks = CompressedMasks::new();
+    for aabb in &query.chunks {
+        let near = aabb.is_some_and(|(cmi, cma)| (0..3).all(|k| {
+            cmi[k] <= ma[k] + dist && cma[k] >= mi[k] - dist
+        }));
+        masks.push(if near {u64::MAX} else {0});
+    }
+    masks
+}
+
+/// Returns `true` if ray hits any triangle closer than maximum depth, in chunks of mask.
+fn bake_occluded(
+    query: &RayQuery,
+    chunks: &[(usize, crate::Chunk<Triangle>, u64)],
+    ray: Ray,
+    max_depth: f32,
+) -> bool {
+    use crate::ray::{ray_aabb_hit, ray_triangle_hit};
+
+    chunks.iter().any(|(off, chunk, mask)| {
+        let Some(aabb) = query.chunks[off / 64] else {return false};
+        if !ray_aabb_hit(ray, aabb).is_some_and(|t| t < max_depth) {return false};
+
+        (0..64).filter(|i| (mask >> i) & 1 == 1)
+            .any(|i| ray_triangle_hit(ray, chunk[i]).is_some_and(|t| t < max_depth))
+    })
+}
+
+/// Bakes a lightmap from triangles with UV coordinates.
+///
+/// The UV producer uses the same virtual list indices as the triangle producer.
+/// All triangles occlude, also those that are not baked.
+pub fn bake_lightmap<P, U>(list: &P, uvs: &U, settings: &BakeSettings) -> Lightmap
+    where P: Produce<Triangle> + Sync + ?Sized, U: Produce<UvTriangle> + ?Sized
+{
+    use crate::produce::init_chunk_mask;
+    use crate::math::{hash_u64, rand_f32};
+    use rayon::prelude::*;
+    use vecmath::vec3_add as add;
+    use vecmath::vec3_dot as dot;
+    use vecmath::vec3_len as len;
+    use vecmath::vec3_scale as scale;
+    use vecmath::vec3_sub as sub;
+
+    let [w, h] = settings.size;
+    let query = RayQuery::new(list);
+    let texels = bake_rasterize(list, uvs, settings.size);
+    let n = list.virtual_length();
+
+    let mut colors = vec![[0.0; 4]; (w * h) as usize];
+    colors.par_chunks_mut(w.max(1) as usize).enumerate().for_each_init(
+        // Caches the nearby chunks of the last baked triangle.
+        || (usize::MAX, vec![]),
+        |(last, chunks), (y, row)| {
+            for (x, color) in row.iter_mut().enumerate() {
+                let k = y * w as usize + x;
+                let Some(Texel {index, pos, normal}) = texels[k] else {continue};
+
+                let origin = add(pos, scale(normal, settings.bias));
+                let mut ao = 1.0;
+                if settings.ao_samples > 0 {
+                    if *last != index {
+                        let tri = list.produce(index - index % 64)[index % 64];
+                        let aabb = crate::triangle::triangle_aabb(tri);
+                        let masks = bake_near_mask(&query, aabb, settings.ao_distance);
+                        chunks.clear();
+                        for (i, word) in masks.iter() {
+                            let off = i * 64;
+                            chunks.push((off, list.produce(off), word & init_chunk_mask(n, off)));
+                        }
+                        *last = index;
+                    }
+
+                    let mut state = hash_u64(settings.seed ^ hash_u64(k as u64));
+                    let mut hits = 0;
+                    for _ in 0..settings.ao_samples {
+                        let r = [rand_f32(&mut state), rand_f32(&mut state)];
+                        let dir = hemisphere_dir(normal, r);
+                        if bake_occluded(&query, chunks, (origin, dir), settings.ao_distance) {hits += 1};
+                    }
+                    ao = 1.0 - hits as f32 / settings.ao_samples as f32;
+                }
+
+                let mut rgb = scale(settings.ambient, ao);
+                for light in &settings.lights {
+                    let (dir, dist, color) = match *light {
+                        BakeLight::Directional {dir, color} => (dir, f32::INFINITY, color),
+                        BakeLight::Point {pos: light_pos, color} => {
+                            let d = sub(light_pos, origin);
+                            let dist = len(d);
+                            (scale(d, 1.0 / dist), dist, scale(color, 1.0 / (dist * dist)))
+                        }
+                    };
+                    let cos = dot(normal, dir);
+                    if cos <= 0.0 || query.any_hit(list, (origin, dir), dist).is_some() {continue};
+
+                    rgb = add(rgb, scale(color, cos));
+                }
+                *color = [rgb[0], rgb[1], rgb[2], 1.0];
+            }
+        },
+    );
+    bake_dilate(settings.size, &mut colors, settings.dilation);
+    Lightmap {size: settings.size, texels: colors}
+}
+
+/// Grows texels with non-zero alpha into empty neighbour texels.
+///
+/// Each step fills empty texels with the average of their filled neighbours,
+/// such that texture filtering at UV seams does not bleed empty texels.
+pub fn bake_dilate(size: PixelPos, texels: &mut [Rgba], steps: u32) {
+    let [w, h] = size;
+    for _ in 0..steps {
+        let src = texels.to_vec();
+        let mut changed = false;
+        for y in 0..h as i64 {
+            for x in 0..w as i64 {
+                let k = (y * w as i64 + x) as usize;
+                if src[k][3] > 0.0 {continue};
+
+                let mut sum = [0.0; 3];
+                let mut count = 0;
+                for j in (y - 1).max(0)..(y + 2).min(h as i64) {
+                    for i in (x - 1).max(0)..(x + 2).min(w as i64) {
+                        let c = src[(j * w as i64 + i) as usize];
+                        if c[3] == 0.0 {continue};
+                        for m in 0..3 {sum[m] += c[m]}
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    let s = 1.0 / count as f32;
+                    texels[k] = [sum[0] * s, sum[1] * s, sum[2] * s, 1.0];
+                    changed = true;
+                }
+            }
+        }
+        if !changed {break};
+    }
+}
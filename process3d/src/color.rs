@@ -1,94 +1,134 @@
 //! # Helper functions for colors
+//!
+//! Color spaces and conversions are shared with `turbine_reactive` through `turbine_color`.
 
-use crate::{Rgb, Rgba};
+use crate::{PixelPos, Rgba};
 
-#[inline(always)]
-fn component_srgb_to_linear(f: f32) -> f32 {
-    if f <= 0.04045 {
-        f / 12.92
-    } else {
-        ((f + 0.055) / 1.055).powf(2.4)
-    }
-}
+pub use turbine_color::{
+    ColorSpace,
+    ColorMatrix,
+    SRGB_TO_DISPLAY_P3,
+    DISPLAY_P3_TO_SRGB,
+    SRGB_TO_ACESCG,
+    ACESCG_TO_SRGB,
+    rgb_color_matrix,
+    rgb_linear_to_space,
+    rgb_space_to_linear,
+    rgb_convert,
+    rgba_linear_to_space,
+    rgba_space_to_linear,
+    rgba_convert,
+    Premultiplied,
+    rgb_gamma_srgb_to_linear,
+    rgba_gamma_srgb_to_linear,
+    rgb_gamma_linear_to_srgb,
+    rgba_gamma_linear_to_srgb,
+};
 
-/// Converts gamma (brightness) from sRGB to linear color space.
-///
-/// sRGB is the default color space for image editors, pictures, internet etc.
-/// Linear gamma yields better results when doing math with colors.
-pub fn rgb_gamma_srgb_to_linear(c: Rgb) -> Rgb {
+/// Converts color to `f32` precision.
+pub fn rgba_to_f32(a: Rgba<u8>) -> Rgba {
     [
-        component_srgb_to_linear(c[0]),
-        component_srgb_to_linear(c[1]),
-        component_srgb_to_linear(c[2]),
+        (a[0] as f32) / 255.0,
+        (a[1] as f32) / 255.0,
+        (a[2] as f32) / 255.0,
+        (a[3] as f32) / 255.0,
     ]
 }
 
-/// Converts gamma (brightness) from sRGB to linear color space.
-///
-/// sRGB is the default color space for image editors, pictures, internet etc.
-/// Linear gamma yields better results when doing math with colors.
-pub fn rgba_gamma_srgb_to_linear(c: Rgba) -> Rgba {
+/// Converts color to `u8` precision, rounding to nearest.
+pub fn rgba_to_u8(a: Rgba) -> Rgba<u8> {
+    use crate::math::clamp;
     [
-        component_srgb_to_linear(c[0]),
-        component_srgb_to_linear(c[1]),
-        component_srgb_to_linear(c[2]),
-        c[3],
+        (clamp(a[0]) * 255.0).round() as u8,
+        (clamp(a[1]) * 255.0).round() as u8,
+        (clamp(a[2]) * 255.0).round() as u8,
+        (clamp(a[3]) * 255.0).round() as u8,
     ]
 }
 
-#[inline(always)]
-fn component_linear_to_srgb(f: f32) -> f32 {
-    if f <= 0.0031308 {
-        f * 12.92
-    } else {
-        1.055 * f.powf(1.0 / 2.4) - 0.055
-    }
+/// Dithering when quantizing colors.
+///
+/// Dithering replaces visible banding in smooth gradients with fine noise.
+/// The noise depends only on the pixel position, so it is deterministic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dither {
+    /// Ordered dithering with an 8x8 Bayer matrix.
+    Bayer,
+    /// Blue-noise dithering with a tiling 32x32 mask.
+    ///
+    /// Blue noise has less low-frequency structure than ordered dithering.
+    BlueNoise,
 }
 
-/// Converts gamma (brightness) of a color from linear color space to sRGB.
-///
-/// sRGB is the default color space for image editors, pictures, internet etc.
-/// Linear gamma yields better results when doing math with colors.
-pub fn rgb_gamma_linear_to_srgb(c: Rgb) -> Rgb {
-    [
-        component_linear_to_srgb(c[0]),
-        component_linear_to_srgb(c[1]),
-        component_linear_to_srgb(c[2]),
-    ]
+/// The size of the blue-noise dither mask.
+pub const BLUE_NOISE_SIZE: usize = 32;
+
+/// Returns the rank of pixel in the 8x8 Bayer matrix, in range `0..64`.
+pub fn bayer_rank([x, y]: PixelPos) -> u32 {
+    let mut v = 0;
+    for bit in 0..3 {
+        v = (v << 2) | ((((x ^ y) >> bit) & 1) << 1) | ((y >> bit) & 1);
+    }
+    v
 }
 
-/// Converts gamma (brightness) of a color from linear color space to sRGB.
+/// Returns the blue-noise dither mask, ranks stored row by row.
 ///
-/// sRGB is the default color space for image editors, pictures, internet etc.
-/// Linear gamma yields better results when doing math with colors.
-pub fn rgba_gamma_linear_to_srgb(c: Rgba) -> Rgba {
-    [
-        component_linear_to_srgb(c[0]),
-        component_linear_to_srgb(c[1]),
-        component_linear_to_srgb(c[2]),
-        c[3],
-    ]
+/// The mask is generated once by void-and-cluster on a torus,
+/// by repeatedly filling the largest void with a gaussian energy filter.
+pub fn blue_noise_mask() -> &'static [u16] {
+    use std::sync::OnceLock;
+
+    static MASK: OnceLock<Vec<u16>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let n = BLUE_NOISE_SIZE;
+        let sigma = 1.5_f32;
+        // Energy contributed at toroidal offset, precomputed per axis.
+        let falloff: Vec<f32> = (0..n as i32)
+            .map(|d| {
+                let d = d.min(n as i32 - d);
+                (-(d * d) as f32 / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+        let mut energy = vec![0.0_f32; n * n];
+        let mut mask = vec![u16::MAX; n * n];
+        for rank in 0..n * n {
+            // Ties are broken by lowest index, which makes the mask deterministic.
+            let (k, _) = energy.iter().enumerate()
+                .filter(|&(k, _)| mask[k] == u16::MAX)
+                .fold((0, f32::INFINITY), |(bk, be), (k, &e)| if e < be {(k, e)} else {(bk, be)});
+            mask[k] = rank as u16;
+            let (px, py) = (k % n, k / n);
+            for (j, e) in energy.iter_mut().enumerate() {
+                let (dx, dy) = ((j % n + n - px) % n, (j / n + n - py) % n);
+                *e += falloff[dx] * falloff[dy];
+            }
+        }
+        mask
+    })
 }
 
-/// Converts color to `f32` precision.
-pub fn rgba_to_f32(a: Rgba<u8>) -> Rgba {
-    [
-        (a[0] as f32) / 255.0,
-        (a[1] as f32) / 255.0,
-        (a[2] as f32) / 255.0,
-        (a[3] as f32) / 255.0,
-    ]
+/// Returns dither offset of pixel, in range `-0.5..0.5`.
+pub fn dither_offset(dither: Dither, [x, y]: PixelPos) -> f32 {
+    match dither {
+        Dither::Bayer => (bayer_rank([x % 8, y % 8]) as f32 + 0.5) / 64.0 - 0.5,
+        Dither::BlueNoise => {
+            let n = BLUE_NOISE_SIZE;
+            let rank = blue_noise_mask()[(y as usize % n) * n + x as usize % n];
+            (rank as f32 + 0.5) / (n * n) as f32 - 0.5
+        }
+    }
 }
 
-/// Converts color to `u8` precision.
-pub fn rgba_to_u8(a: Rgba) -> Rgba<u8> {
+/// Converts color to `u8` precision with dithering at pixel position.
+///
+/// Only color channels are dithered, alpha is rounded to nearest.
+pub fn rgba_to_u8_dithered(a: Rgba, dither: Dither, pos: PixelPos) -> Rgba<u8> {
     use crate::math::clamp;
-    [
-        (clamp(a[0]) * 255.0) as u8,
-        (clamp(a[1]) * 255.0) as u8,
-        (clamp(a[2]) * 255.0) as u8,
-        (clamp(a[3]) * 255.0) as u8,
-    ]
+
+    let d = dither_offset(dither, pos);
+    let q = |x: f32| (clamp(x) * 255.0 + d).round().clamp(0.0, 255.0) as u8;
+    [q(a[0]), q(a[1]), q(a[2]), (clamp(a[3]) * 255.0).round() as u8]
 }
 
 /// Alpha blending over operation in linear color space.
@@ -102,6 +142,13 @@ pub fn rgba_alpha_blending_linear_over(a: Rgba, b: Rgba) -> Rgba {
     ]
 }
 
+/// Alpha blending over operation in linear color space, returning straight alpha.
+///
+/// Colors have straight alpha, blended through `Premultiplied`.
+pub fn rgba_alpha_blending_linear_over_straight(a: Rgba, b: Rgba) -> Rgba {
+    Premultiplied::from_straight(a).over(Premultiplied::from_straight(b)).to_straight()
+}
+
 /// Alpha blending over operation in sRGB color space.
 pub fn rgba_alpha_blending_srgb_over(a: Rgba, b: Rgba) -> Rgba {
     let a = rgba_gamma_srgb_to_linear(a);
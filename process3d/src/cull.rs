@@ -0,0 +1,92 @@
+//! # Object culling
+//!
+//! Culls whole objects against tile frustums, before testing chunks of triangles.
+//!
+//! An object is a range of the virtual triangle list with precomputed bounds,
+//! e.g. a mesh or a group of voxels.
+//! When computing tile masks, object bounds are tested first:
+//!
+//! - Objects outside the tile frustum emit zero runs
+//! - Objects inside the tile frustum emit one runs
+//! - Objects that intersect the tile frustum are tested per triangle, as without objects
+//!
+//! This gives the same masks as testing every triangle,
+//! as long as each object's bounds contain all its triangles.
+//! Triangles not covered by any object are tested per triangle.
+//!
+//! Objects must be sorted by range start, without overlapping ranges.
+//! Object bounds are in the coordinates of the producer,
+//! so transform them when the producer is transformed, e.g. into camera coordinates.
+//! See `cull_objects_transform`.
+
+use std::ops::Range;
+
+use crate::{Aabb, Matrix4, Triangle};
+use crate::produce::Produce;
+
+/// Object in virtual list of triangles, with bounds used for culling.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CullObject {
+    /// The range of triangles in the virtual list.
+    pub range: Range<usize>,
+    /// The AABB that contains all triangles of the object.
+    pub aabb: Aabb,
+}
+
+impl CullObject {
+    /// Creates object from a range of triangles in producer, computing bounds.
+    ///
+    /// Returns `None` if the range is empty.
+    pub fn from_producer<T>(list: &T, range: Range<usize>) -> Option<CullObject>
+        where T: Produce<Triangle> + ?Sized
+    {
+        use crate::triangle::{aabb_union, triangle_aabb};
+
+        let end = range.end.min(list.virtual_length());
+        let mut aabb: Option<Aabb> = None;
+        let mut off = range.start;
+        while off < end {
+            let start = off - off % 64;
+            let chunk = list.produce(start);
+            for tri in &chunk[off - start..(end - start).min(64)] {
+                let b = triangle_aabb(*tri);
+                aabb = Some(aabb.map_or(b, |a| aabb_union(a, b)));
+            }
+            off = start + 64;
+        }
+        Some(CullObject {range, aabb: aabb?})
+    }
+}
+
+/// Creates objects of equal size from producer, computing bounds.
+///
+/// This is useful when the virtual list is sorted spatially, see `order::spatial_order`.
+pub fn cull_objects_uniform<T>(list: &T, size: usize) -> Vec<CullObject>
+    where T: Produce<Triangle> + ?Sized
+{
+    let n = list.virtual_length();
+    (0..n).step_by(size.max(1))
+        .filter_map(|start| CullObject::from_producer(list, start..(start + size.max(1)).min(n)))
+        .collect()
+}
+
+/// Transforms bounds of objects using a matrix, keeping a conservative AABB.
+pub fn cull_objects_transform(objects: &[CullObject], mat: &Matrix4) -> Vec<CullObject> {
+    use crate::cube::aabb_to_cube;
+    use crate::math::transform_point;
+    use crate::triangle::aabb_union;
+
+    objects.iter().map(|obj| {
+        let corners = aabb_to_cube(obj.aabb).map(|p| transform_point(mat, p));
+        let aabb = corners.iter().fold((corners[0], corners[0]), |a, &p| aabb_union(a, (p, p)));
+        CullObject {range: obj.range.clone(), aabb}
+    }).collect()
+}
+
+/// Returns bits of range within chunk at offset.
+pub fn cull_range_bits(range: &Range<usize>, offset: usize) -> u64 {
+    let ones = |k: usize| 1_u64.checked_shl(k as u32).unwrap_or(0).wrapping_sub(1);
+    let lo = range.start.saturating_sub(offset).min(64);
+    let hi = range.end.saturating_sub(offset).min(64);
+    ones(hi) & !ones(lo)
+}
@@ -0,0 +1,120 @@
+//! # Edge overlay
+//!
+//! Draws triangle edges on top of shaded colors, e.g. to show topology of meshes and voxels.
+//!
+//! The distance from a ray hit to the triangle edges is computed from barycentric coordinates.
+//! This distance is converted to pixels, such that edges have the same width in the image
+//! regardless of depth.
+//!
+//! Edges are indexed by the vertex opposite to them:
+//! Edge `0` is `bc`, edge `1` is `ca` and edge `2` is `ab`.
+//!
+//! Quads are split into two triangles by `quad_to_triangles`, which share a diagonal.
+//! When triangles of quads are stored in pairs at even and odd indices,
+//! e.g. when producing triangles from quads or voxels,
+//! the diagonal can be hidden such that only quad edges are drawn.
+
+use crate::{Ray, Rgba, Triangle, Vector};
+use crate::cam::CameraPerspective;
+
+/// The type of function that blends edge into color, using edge coverage.
+pub type EdgeBlend<'a, Color> = Box<dyn Fn(&mut Color, f32) + Sync + 'a>;
+
+/// Edge overlay settings.
+pub struct EdgeOverlay<'a, Color> {
+    /// The edge width in pixels.
+    pub width: f32,
+    /// Whether to hide the diagonal of quads produced by `quad_to_triangles`.
+    pub hide_quad_diagonal: bool,
+    /// Blends edge into color, using edge coverage in range `0.0` to `1.0`.
+    pub blend: EdgeBlend<'a, Color>,
+}
+
+impl<'a> EdgeOverlay<'a, Rgba> {
+    /// Creates edge overlay that blends colors toward an edge color.
+    pub fn rgba(width: f32, color: Rgba) -> Self {
+        EdgeOverlay {
+            width,
+            hide_quad_diagonal: false,
+            blend: Box::new(move |c, coverage| {
+                for i in 0..4 {c[i] += (color[i] - c[i]) * coverage}
+            }),
+        }
+    }
+}
+
+impl<Color> EdgeOverlay<'_, Color> {
+    /// Calculates edge coverage of ray hit at some depth,
+    /// in range `0.0` to `1.0`.
+    ///
+    /// The index is the index of the triangle in the virtual list of producer.
+    /// The pixel size is the size of a pixel at unit distance, see `edge_pixel_size`.
+    pub fn coverage(&self, ray: Ray, tri: Triangle, index: usize, depth: f32, pixel_size: f32) -> f32 {
+        let Some(bary) = triangle_barycentric(ray, tri) else {return 0.0};
+        let dist = triangle_edge_distances(tri, bary);
+        let hidden = if self.hide_quad_diagonal {Some(quad_diagonal_edge(index))} else {None};
+        let d = (0..3).filter(|&k| Some(k) != hidden)
+            .map(|k| dist[k])
+            .fold(f32::INFINITY, f32::min);
+        edge_coverage(d / (depth * pixel_size), self.width)
+    }
+}
+
+/// Calculates the size of a pixel at unit distance from the camera.
+pub fn edge_pixel_size(persp: &CameraPerspective, dim: [u32; 2]) -> f32 {
+    use crate::frustrum::near_dim;
+
+    near_dim(persp)[1] / persp.near_clip / dim[1] as f32
+}
+
+/// Calculates anti-aliased coverage of edge with some width,
+/// from distance to edge in pixels.
+pub fn edge_coverage(dist: f32, width: f32) -> f32 {
+    (0.5 * width + 0.5 - dist).clamp(0.0, 1.0)
+}
+
+/// Calculates barycentric coordinates of the point where a ray hits a triangle.
+///
+/// The coordinates are weights of the vertices `a`, `b` and `c`.
+///
+/// Returns `None` if the triangle is degenerate or parallel to the ray.
+/// The coordinates are not clamped to the triangle.
+pub fn triangle_barycentric((origin, dir): Ray, (a, b, c): Triangle) -> Option<Vector> {
+    use vecmath::vec3_cross as cross;
+    use vecmath::vec3_dot as dot;
+    use vecmath::vec3_sub as sub;
+
+    let e1 = sub(b, a);
+    let e2 = sub(c, a);
+    let p = cross(dir, e2);
+    let det = dot(e1, p);
+    if det.abs() < f32::EPSILON {return None};
+
+    let inv_det = 1.0 / det;
+    let s = sub(origin, a);
+    let u = dot(s, p) * inv_det;
+    let q = cross(s, e1);
+    let v = dot(dir, q) * inv_det;
+    Some([1.0 - u - v, u, v])
+}
+
+/// Calculates distances from point with barycentric coordinates to triangle edges.
+///
+/// The distance to edge `k` is stored at index `k`.
+pub fn triangle_edge_distances((a, b, c): Triangle, bary: Vector) -> [f32; 3] {
+    use vecmath::vec3_cross as cross;
+    use vecmath::vec3_len as len;
+    use vecmath::vec3_sub as sub;
+
+    let area2 = len(cross(sub(b, a), sub(c, a)));
+    let edges = [sub(c, b), sub(a, c), sub(b, a)];
+    // The height over an edge is twice the area divided by edge length.
+    [0, 1, 2].map(|k| bary[k].abs() * area2 / len(edges[k]))
+}
+
+/// Returns the edge of triangle that is the diagonal of a quad split by `quad_to_triangles`.
+///
+/// The first triangle of the quad is at an even index and the second one at an odd index.
+pub fn quad_diagonal_edge(index: usize) -> usize {
+    if index.is_multiple_of(2) {0} else {2}
+}
@@ -1,8 +1,19 @@
 //! # Fog algorithms
 //!
 //! This module contains functionality to process fog effects.
+//!
+//! Uniform fog is rendered between pairs of same-colored semi-transparent surfaces,
+//! using the alpha channel as density.
+//!
+//! Heterogeneous fog, e.g. smoke and clouds, uses volumes with bounds
+//! and a function that samples density and color in world space.
+//! The renderer ray-marches each volume between the entry and exit of its bounds,
+//! then adds the result to the accumulator as a pair of same-colored surfaces,
+//! with density chosen such that semi-fog accumulators compose the marched opacity.
+//! When an opaque surface is inside a volume, the pair is cut off by the accumulator,
+//! which approximates the marched volume as uniform up to the surface.
 
-use crate::Rgba;
+use crate::{Aabb, Point, Ray, Rgb, Rgba};
 
 /// Calculates effect of volumetric fog for uniform density `p` and distance.
 ///
@@ -153,3 +164,121 @@ impl FogState {
         }
     }
 }
+
+/// The type of function that samples fog volume density and linear color at a point.
+pub type FogSample<'a> = Box<dyn Fn(Point) -> (f32, Rgb) + Sync + 'a>;
+
+/// The type of function that converts linear fog color to accumulator input.
+pub type FogColor<'a, Color> = Box<dyn Fn(Rgba) -> Color + Sync + 'a>;
+
+/// The type of function that tells whether a surface color hides fog behind it.
+pub type FogOpaque<'a, Color> = Box<dyn Fn(&Color) -> bool + Sync + 'a>;
+
+/// Fog volume with varying density.
+pub struct FogVolume<'a> {
+    /// The bounds in world coordinates.
+    pub bounds: Aabb,
+    /// The number of ray marching steps between entry and exit.
+    pub steps: u32,
+    /// Samples density and linear color at a point in world coordinates.
+    ///
+    /// The density is the extinction per unit distance.
+    pub sample: FogSample<'a>,
+}
+
+impl<'a> FogVolume<'a> {
+    /// Creates a new fog volume.
+    pub fn new(
+        bounds: Aabb,
+        steps: u32,
+        sample: impl Fn(Point) -> (f32, Rgb) + Sync + 'a,
+    ) -> FogVolume<'a> {
+        FogVolume {bounds, steps, sample: Box::new(sample)}
+    }
+
+    /// Ray-marches volume between depths along ray in world coordinates.
+    ///
+    /// Returns linear color with straight alpha, where alpha is the opacity.
+    /// Samples are taken at the middle of each step, so the result is deterministic.
+    pub fn march(&self, (origin, dir): Ray, t0: f32, t1: f32) -> Rgba {
+        let steps = self.steps.max(1);
+        let dt = (t1 - t0).max(0.0) / steps as f32;
+        let mut transmittance = 1.0;
+        let mut color = [0.0; 3];
+        for k in 0..steps {
+            let t = t0 + (k as f32 + 0.5) * dt;
+            let p = [0, 1, 2].map(|i| origin[i] + dir[i] * t);
+            let (density, c) = (self.sample)(p);
+            let a = volumetric_fog_fx(density.max(0.0), dt);
+            for i in 0..3 {color[i] += transmittance * a * c[i]}
+            transmittance *= 1.0 - a;
+        }
+        let opacity = 1.0 - transmittance;
+        if opacity <= 0.0 {return [0.0; 4]};
+        [color[0] / opacity, color[1] / opacity, color[2] / opacity, opacity]
+    }
+}
+
+/// Fog volumes rendered with the same accumulator as surfaces.
+pub struct FogVolumes<'a, Color> {
+    /// The fog volumes.
+    pub volumes: Vec<FogVolume<'a>>,
+    /// Converts linear fog color to accumulator input.
+    pub color: FogColor<'a, Color>,
+    /// Tells whether a surface color is opaque.
+    ///
+    /// Marching stops at the nearest opaque surface of each pixel.
+    pub is_opaque: FogOpaque<'a, Color>,
+}
+
+impl<'a> FogVolumes<'a, Rgba> {
+    /// Creates fog volumes for accumulators in linear color space.
+    pub fn linear(volumes: Vec<FogVolume<'a>>) -> Self {
+        FogVolumes {volumes, color: Box::new(|c| c), is_opaque: Box::new(|c| c[3] >= 1.0)}
+    }
+
+    /// Creates fog volumes for accumulators in sRGB color space.
+    pub fn srgb(volumes: Vec<FogVolume<'a>>) -> Self {
+        use crate::color::rgba_gamma_linear_to_srgb;
+
+        FogVolumes {
+            volumes,
+            color: Box::new(rgba_gamma_linear_to_srgb),
+            is_opaque: Box::new(|c| c[3] >= 1.0),
+        }
+    }
+}
+
+/// Calculates the depth interval where ray in world coordinates is inside bounds.
+///
+/// Returns `None` if the ray misses the bounds or they are behind the ray.
+pub fn fog_volume_interval((origin, dir): Ray, (mi, ma): Aabb) -> Option<(f32, f32)> {
+    let mut t0 = 0.0_f32;
+    let mut t1 = f32::INFINITY;
+    for i in 0..3 {
+        let inv = 1.0 / dir[i];
+        let (a, b) = ((mi[i] - origin[i]) * inv, (ma[i] - origin[i]) * inv);
+        let (a, b) = if a <= b {(a, b)} else {(b, a)};
+        // Rays parallel to the slab give NaN, which is ignored by `max` and `min`.
+        t0 = t0.max(a);
+        t1 = t1.min(b);
+    }
+    if t0 < t1 {Some((t0, t1))} else {None}
+}
+
+/// Finds the alpha of a fog color pair at some distance,
+/// such that semi-fog accumulators compose to some opacity.
+///
+/// This inverts `volumetric_fog_alpha` with `volumetric_fog_fx`,
+/// where alpha is both density and maximum opacity.
+pub fn fog_alpha_for_opacity(opacity: f32, dist: f32) -> f32 {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let f = |a: f32| volumetric_fog_alpha(a, volumetric_fog_fx(a, dist), dist);
+    // The composed opacity increases with alpha, so bisection converges.
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..32 {
+        let mid = 0.5 * (lo + hi);
+        if f(mid) < opacity {lo = mid} else {hi = mid}
+    }
+    0.5 * (lo + hi)
+}
@@ -1,6 +1,6 @@
 //! # Frustrum algorithms
 
-use crate::{Aabb, Chunk, Plane, Point, RayHit, Triangle, Uv};
+use crate::{Aabb, Chunk, Matrix4, Plane, Point, RayHit, Triangle, Uv, Vector};
 use crate::triangle::{triangle_aabb, triangle_plane};
 use cam::CameraPerspective;
 
@@ -102,6 +102,33 @@ pub fn frustum_planes_tile(persp: &CameraPerspective, dim: Uv, tile_pos: Uv, til
     }
 }
 
+/// Transforms plane with the inverse of an affine matrix.
+///
+/// Points `p` in front of the result are those where `mat * p` is in front of the plane,
+/// e.g. a plane in camera coordinates and the view matrix gives the plane in world coordinates.
+pub fn plane_transform_inv((n, d): Plane, mat: &Matrix4) -> Plane {
+    (
+        [
+            n[0] * mat[0][0] + n[1] * mat[1][0] + n[2] * mat[2][0],
+            n[0] * mat[0][1] + n[1] * mat[1][1] + n[2] * mat[2][1],
+            n[0] * mat[0][2] + n[1] * mat[1][2] + n[2] * mat[2][2],
+        ],
+        n[0] * mat[0][3] + n[1] * mat[1][3] + n[2] * mat[2][3] + d,
+    )
+}
+
+/// Transforms frustum planes with the inverse of an affine matrix, see `plane_transform_inv`.
+pub fn frustum_planes_transform_inv(fr: &FrustumPlanes, mat: &Matrix4) -> FrustumPlanes {
+    FrustumPlanes {
+        near: plane_transform_inv(fr.near, mat),
+        far: plane_transform_inv(fr.far, mat),
+        left: plane_transform_inv(fr.left, mat),
+        right: plane_transform_inv(fr.right, mat),
+        top: plane_transform_inv(fr.top, mat),
+        bottom: plane_transform_inv(fr.bottom, mat),
+    }
+}
+
 /// Returns `true` if point is at the front of plane.
 pub fn plane_point_front((n, d): Plane, p: Point) -> bool {
     use vecmath::vec3_dot as dot;
@@ -122,6 +149,28 @@ pub fn plane_aabb_front_or_intersect(p: Plane, (mi, ma): Aabb) -> bool {
     plane_point_front(p, ma)
 }
 
+/// Returns `true` if all corners of an AABB are in front of plane.
+pub fn plane_aabb_front(p: Plane, (mi, ma): Aabb) -> bool {
+    plane_point_front(p, mi) &&
+    plane_point_front(p, [ma[0], mi[1], mi[2]]) &&
+    plane_point_front(p, [mi[0], ma[1], mi[2]]) &&
+    plane_point_front(p, [ma[0], ma[1], mi[2]]) &&
+    plane_point_front(p, [mi[0], mi[1], ma[2]]) &&
+    plane_point_front(p, [ma[0], mi[1], ma[2]]) &&
+    plane_point_front(p, [mi[0], ma[1], ma[2]]) &&
+    plane_point_front(p, ma)
+}
+
+/// Returns `true` if AABB is inside frustrum planes.
+pub fn frustum_planes_aabb_inside(fr: &FrustumPlanes, a: Aabb) -> bool {
+    plane_aabb_front(fr.near, a) &&
+    plane_aabb_front(fr.far, a) &&
+    plane_aabb_front(fr.left, a) &&
+    plane_aabb_front(fr.right, a) &&
+    plane_aabb_front(fr.top, a) &&
+    plane_aabb_front(fr.bottom, a)
+}
+
 /// Returns `true` if AABB intersects frustrum planes.
 pub fn frustum_planes_aabb_intersect(fr: &FrustumPlanes, a: Aabb) -> bool {
     plane_aabb_front_or_intersect(fr.near, a) &&
@@ -153,6 +202,101 @@ pub fn frustum_planes_triangle_chunk_mask(
     mask
 }
 
+/// Returns the corners of frustum planes, near corners first.
+///
+/// The corners of each clip plane are ordered left to right, then bottom to top.
+pub fn frustum_planes_corners(fr: &FrustumPlanes) -> [Point; 8] {
+    use vecmath::vec3_cross as cross;
+    use vecmath::vec3_dot as dot;
+
+    // Intersection of three planes.
+    let corner = |(n1, d1): Plane, (n2, d2): Plane, (n3, d3): Plane| {
+        let c23 = cross(n2, n3);
+        let c31 = cross(n3, n1);
+        let c12 = cross(n1, n2);
+        let det = dot(n1, c23);
+        [0, 1, 2].map(|i| -(d1 * c23[i] + d2 * c31[i] + d3 * c12[i]) / det)
+    };
+    std::array::from_fn(|k| corner(
+        [fr.near, fr.far][k / 4],
+        [fr.left, fr.right][k % 2],
+        [fr.bottom, fr.top][k / 2 % 2],
+    ))
+}
+
+/// Returns `true` if triangle intersects frustum planes with corners,
+/// using the separating axis theorem.
+///
+/// Unlike testing the AABB of the triangle,
+/// this does not include long diagonal triangles that pass by the frustum.
+/// Triangles that touch the frustum are included.
+pub fn frustum_planes_triangle_intersect(
+    fr: &FrustumPlanes,
+    corners: &[Point; 8],
+    (a, b, c): Triangle,
+) -> bool {
+    use vecmath::vec3_cross as cross;
+    use vecmath::vec3_dot as dot;
+    use vecmath::vec3_sub as sub;
+
+    let planes = [fr.near, fr.far, fr.left, fr.right, fr.top, fr.bottom];
+    for p in planes {
+        if !plane_point_front(p, a) && !plane_point_front(p, b) && !plane_point_front(p, c) {
+            return false;
+        }
+    }
+
+    let tri = [a, b, c];
+    let separated = |axis: Vector| {
+        let range = |ps: &[Point]| ps.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(mi, ma), &p| {
+            let t = dot(axis, p);
+            (mi.min(t), ma.max(t))
+        });
+        let (tri_min, tri_max) = range(&tri);
+        let (fr_min, fr_max) = range(corners);
+        tri_max < fr_min || fr_max < tri_min
+    };
+    if separated(cross(sub(b, a), sub(c, a))) {return false};
+
+    // Edge directions of frustum, from intersections of neighbour planes.
+    let dirs = [
+        cross(fr.near.0, fr.left.0),
+        cross(fr.near.0, fr.right.0),
+        cross(fr.near.0, fr.top.0),
+        cross(fr.near.0, fr.bottom.0),
+        cross(fr.left.0, fr.top.0),
+        cross(fr.left.0, fr.bottom.0),
+        cross(fr.right.0, fr.top.0),
+        cross(fr.right.0, fr.bottom.0),
+    ];
+    let edges = [sub(b, a), sub(c, b), sub(a, c)];
+    !edges.iter().any(|&e| dirs.iter().any(|&f| separated(cross(e, f))))
+}
+
+/// Generate bit mask for triangle chunk where triangle intersects frustum planes.
+///
+/// Uses existing bits to avoid processing triangles that are not needed.
+///
+/// Tests the AABB of triangles first, then `frustum_planes_triangle_intersect`.
+/// The result is a subset of `frustum_planes_triangle_chunk_mask`.
+pub fn frustum_planes_triangle_chunk_exact_mask(
+    fr: &FrustumPlanes,
+    chunk: &Chunk<Triangle>,
+    bits: u64
+) -> u64 {
+    use crate::soa::frustum_planes_triangle_chunk_soa_mask;
+
+    let bits = frustum_planes_triangle_chunk_soa_mask(fr, chunk, bits);
+    if bits == 0 {return 0};
+
+    let corners = frustum_planes_corners(fr);
+    chunk.iter().enumerate().fold(0, |mask, (i, &tri)| {
+        if (bits >> i) & 1 == 1 && frustum_planes_triangle_intersect(fr, &corners, tri) {
+            mask | 1 << i
+        } else {mask}
+    })
+}
+
 /// Linear transformation of depth using near and far clip distance.
 pub fn depth_linear(persp: &CameraPerspective, depth: RayHit) -> f32 {
     if let Some((t, _)) = depth {
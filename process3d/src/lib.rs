@@ -5,40 +5,74 @@
 pub use cam;
 
 pub mod acc;
+pub mod bake;
 pub mod color;
 pub mod consume;
 pub mod cube;
+pub mod cull;
+pub mod edge;
 pub mod fog;
 pub mod frustrum;
 pub mod mask;
 pub mod math;
+pub mod obj;
+pub mod order;
+pub mod pano;
+pub mod path;
+pub mod pick;
+pub mod ply;
+pub mod post;
 pub mod produce;
 pub mod profile;
 pub mod quad;
+pub mod query;
 pub mod ray;
 pub mod render;
+pub mod select;
+pub mod soa;
+pub mod stl;
+pub mod stream;
 pub mod tile;
+pub mod trace;
 pub mod triangle;
+pub mod vox;
 
 /// Default prelude.
 pub mod prelude {
     pub use crate::{
         *,
         acc::*,
+        bake::*,
         cam::*,
         color::*,
         consume::*,
         cube::*,
+        cull::*,
+        edge::*,
         fog::*,
         frustrum::*,
         math::*,
+        obj::*,
+        order::*,
+        pano::*,
+        path::*,
+        pick::*,
+        ply::*,
+        post::*,
         produce::*,
         profile::*,
         quad::*,
+        query::*,
         ray::*,
         render::*,
+        select::*,
+        soa::*,
+        stl::*,
+        stream::*,
         tile::*,
+        trace::*,
         triangle::*,
+        vox::*,
     };
 }
 
@@ -121,6 +155,44 @@ impl IndexFlag {
 mod tests {
     use super::*;
 
+    use crate::prelude::{Acc, Camera, CameraPerspective, Produce, RenderBuffers, RendererBuilder};
+
+    type TestImg = (PixelPos, Vec<Rgba<u8>>);
+
+    /// Creates a renderer builder that writes linear colors to a test image.
+    fn test_builder<'a, Prod, A>(
+        img: &'a mut TestImg,
+        list: &'a Prod,
+        persp: &'a CameraPerspective,
+        cam: &'a Camera,
+    ) -> RendererBuilder<'a, (), Prod, TestImg, A, ()>
+        where Prod: Produce<Triangle> + Sync + ?Sized, A: Acc<In = Rgba, Out = Rgba>
+    {
+        RendererBuilder::new((), list, img, persp, cam)
+            .is_transparent(|c: &Rgba| c[3] == 0.0)
+            .acc_to_linear_rgba(|c| c)
+            .size(|img: &TestImg| img.0)
+            .pxl(|img: &mut TestImg, [x, y], c| img.1[(y * img.0[0] + x) as usize] = c)
+    }
+
+    /// Renders a test image with linear colors from depth and triangle index.
+    ///
+    /// The builder is configured further by `f`.
+    fn render_test<'a, Prod, A, P: 'a>(
+        img: &'a mut TestImg,
+        list: &'a Prod,
+        persp: &'a CameraPerspective,
+        cam: &'a Camera,
+        color: impl Fn(f32, usize) -> Rgba + Sync + 'a,
+        f: impl FnOnce(RendererBuilder<'a, (), Prod, TestImg, A, ()>)
+            -> RendererBuilder<'a, (), Prod, TestImg, A, (), P>,
+    ) where Prod: Produce<Triangle> + Sync + ?Sized, A: Acc<Data = u32, In = Rgba, Out = Rgba> {
+        let builder = test_builder(img, list, persp, cam)
+            .scene_ray_color(move |_, depth, ind| (color(depth, ind), ()))
+            .acc_data(24);
+        f(builder).render(&mut RenderBuffers::new()).unwrap();
+    }
+
     #[test]
     fn test_mask() {
         let mut masks = mask::CompressedMasks::new();
@@ -711,6 +783,1402 @@ mod tests {
             color: semi_blue, distance: 5.0});
     }
 
+    #[test]
+    fn test_soa_chunk() {
+        use crate::prelude::*;
+
+        let mut seed: u32 = 1;
+        let mut rnd = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let mut chunk: Chunk<Triangle> = [([0.0; 3], [0.0; 3], [0.0; 3]); 64];
+        for tri in chunk.iter_mut() {
+            let c = [rnd(), rnd(), rnd() + 3.0];
+            *tri = (c, [c[0] + rnd(), c[1] + rnd(), c[2]], [c[0], c[1] + rnd(), c[2] + rnd()]);
+        }
+        let soa = triangle_chunk_soa(&chunk);
+        let aabbs = triangle_chunk_aabb_soa(&chunk);
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let ndim = near_dim(&persp);
+        for mask in [!0, 0x8000_0000_0000_0101, 0xf0f0_0f0f_0000_ffff, 0] {
+            for j in 0..8 {
+                for i in 0..8 {
+                    let dir = ray_dir(&persp, [0.0; 3], [i, j], [8, 8]);
+                    let ray = ([0.0; 3], dir);
+                    assert_eq!(ray_triangle_chunk_soa_hit(ray, &soa, mask),
+                               ray_triangle_chunk_hit(ray, &chunk, mask));
+                    assert_eq!(ray_triangle_chunk_soa_hit_all(ray, &soa, mask),
+                               ray_triangle_chunk_hit_all(ray, &chunk, mask));
+
+                    let tpos = tile::tile_pos([8, 8], [i, j], 1);
+                    let tsize = tile::tile_size([8, 8], [i, j], 1);
+                    let fr = frustum_planes_tile(&persp, ndim, tpos, tsize);
+                    assert_eq!(frustum_planes_aabb_chunk_soa_mask(&fr, &aabbs, mask),
+                               frustum_planes_triangle_chunk_mask(&fr, &chunk, mask));
+                    assert_eq!(frustum_planes_triangle_chunk_soa_mask(&fr, &chunk, mask),
+                               frustum_planes_triangle_chunk_mask(&fr, &chunk, mask));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        use crate::prelude::*;
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([0.5, 0.5, -3.0]);
+        let flip = [1.0; 3];
+        let data: &[Point<u8>] = &[[0, 0, 0], [0, 0, 1], [5, 5, 0]];
+
+        let hit = pick(&persp, &cam, flip, [64, 64], [33, 32], data).unwrap();
+        assert_eq!(hit.internal_offset, Some(0));
+        assert!((hit.depth - 3.0).abs() < 0.1);
+
+        let layers = pick_all(&persp, &cam, flip, [64, 64], [33, 32], data);
+        assert_eq!(layers.len(), 4);
+        assert_eq!(layers[0], hit);
+        assert_eq!(layers[1].internal_offset, Some(0));
+        assert_eq!(layers[2].internal_offset, Some(1));
+        assert_eq!(layers[3].internal_offset, Some(1));
+        assert!(layers.windows(2).all(|w| w[0].depth <= w[1].depth));
+
+        assert_eq!(pick(&persp, &cam, flip, [64, 64], [0, 63], data), None);
+        // Positions outside the image hit nothing.
+        assert_eq!(pick(&persp, &cam, flip, [64, 64], [33, 64], data), None);
+        assert!(pick_all(&persp, &cam, flip, [64, 64], [64, 32], data).is_empty());
+    }
+
+    #[test]
+    fn test_ray_query() {
+        use crate::prelude::*;
+
+        let mut data: Vec<Point<u8>> = vec![];
+        for z in 0..3 {
+            for y in 0..20 {
+                for x in 0..20 {
+                    if (x + y * 3 + z * 7) % 5 == 0 {data.push([x * 2, y * 2, z * 4])};
+                }
+            }
+        }
+        let list: &[Point<u8>] = &data;
+        let query = RayQuery::new(list);
+        let mut all = mask::CompressedMasks::new();
+        all.push_ones(Produce::<Triangle>::virtual_length(list) as u64);
+        let mut n_hits = 0;
+        for k in 0..100 {
+            let eye = [20.0, 20.0, -10.0];
+            let target = [(k % 10) as f32 * 4.0 + 0.5, (k / 10) as f32 * 4.0 + 0.3, 6.0];
+            let ray = (eye, vecmath::vec3_normalized(vecmath::vec3_sub(target, eye)));
+            let expected = ray_triangle_chunk_iter_hit(ray, chunk_iter(list, &all));
+            assert_eq!(query.closest_hit(list, ray), expected);
+            if expected.is_some() {n_hits += 1};
+
+            let hits = query.all_hits(list, ray);
+            assert_eq!(hits.first().copied().map(|h| h.0), expected.map(|h| h.0));
+            let any = query.any_hit(list, ray, f32::INFINITY);
+            assert_eq!(any.is_some(), expected.is_some());
+            assert_eq!(query.any_hit(list, ray, 0.0), None);
+        }
+        assert!(n_hits > 10);
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([20.0, 20.0, -30.0]);
+        let view = view_matrix(&cam, [1.0; 3]);
+        let prod = TransformProducer {matrix: view, inner: list};
+        // The ray query is built in world coordinates and reused for any camera.
+        let mut a = vec![mask::CompressedMasks::new(); 16];
+        let mut b = vec![mask::CompressedMasks::new(); 16];
+        masks(&persp, [32, 32], 8, &prod, &mut a);
+        masks_with_query(&persp, [32, 32], 8, &prod, QueryView {query: &query, view}, &mut b);
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.len(), b.len());
+            assert!(a.iter().eq(b.iter()));
+        }
+
+        // Rendering with the ray query gives the same image.
+        let render = |query: Option<&RayQuery>| {
+            let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaMinDepthAcc, _>(&mut img, list, &persp, &cam,
+                |depth, ind| [ind as f32 / 256.0, depth / 60.0, 0.0, 1.0], |b| {
+                    let b = b.acc_data(12).tile_size(12);
+                    match query {
+                        Some(query) => b.ray_query(query),
+                        None => b,
+                    }
+                });
+            img.1
+        };
+        let expected = render(None);
+        assert!(expected.iter().any(|c| c[3] == 255));
+        assert_eq!(render(Some(&query)), expected);
+    }
+
+    #[test]
+    fn test_producer_combinators() {
+        use crate::prelude::*;
+        use crate::mask::CompressedMasks;
+
+        let a: Vec<Triangle> = (0..70).map(|i| ([i as f32, 0.0, 0.0], [0.0; 3], [0.0; 3])).collect();
+        let b: &[Point<u8>] = &[[0, 0, 0], [1, 0, 0]];
+        let b = &TransformProducer {matrix: mat4_id(), inner: b};
+
+        let concat = ConcatProducer::new(vec![(&a as &dyn Produce<Triangle>, 1), (b, 2), (&a, 3)]);
+        assert_eq!(concat.virtual_length(), 70 + 24 + 70);
+        let chunk = concat.produce(64);
+        assert_eq!(chunk[5], a[69]);
+        assert_eq!(chunk[6], b.produce(0)[0]);
+        assert_eq!(chunk[30], a[0]);
+        assert_eq!(concat.produce(150)[13], a[69]);
+        assert_eq!(concat.to_internal(70 + 13), Some(70 + 1));
+        assert_eq!(concat.source(70 + 1), Some((1, 1)));
+        assert_eq!(concat.tag(70 + 1), Some(&2));
+        assert_eq!(concat.tag(94), Some(&3));
+        assert_eq!(concat.to_internal(164), None);
+
+        // Empty concatenations have no sources.
+        let empty = ConcatProducer::<dyn Produce<Triangle>, ()>::new(vec![]);
+        assert_eq!(empty.virtual_length(), 0);
+        assert_eq!(empty.source_index(0), None);
+        assert_eq!(empty.tag(0), None);
+        assert_eq!(empty.to_internal(0), None);
+
+        let transforms = [mat4_id(), [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 5.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]];
+        let instanced = InstancedProducer {transforms: &transforms, inner: &a};
+        assert_eq!(instanced.virtual_length(), 140);
+        let chunk = instanced.produce(64);
+        assert_eq!(chunk[5], a[69]);
+        assert_eq!(chunk[6].0, [0.0, 5.0, 0.0]);
+        assert_eq!(instanced.to_internal(72), Some(72));
+        assert_eq!(instanced.instance(72), Some((1, 2)));
+        assert_eq!(instanced.to_internal(140), None);
+
+        // Instances of an empty mesh are empty.
+        let none: &[Triangle] = &[];
+        let instanced = InstancedProducer {transforms: &transforms, inner: none};
+        assert_eq!(instanced.virtual_length(), 0);
+        assert_eq!(instanced.produce(0)[0], ([0.0; 3], [0.0; 3], [0.0; 3]));
+        assert_eq!(instanced.instance(0), None);
+        assert_eq!(instanced.to_internal(0), None);
+
+        let mut masks = CompressedMasks::new();
+        masks.push(0b1010);
+        masks.push(0b11);
+        let filter = FilterProducer::new(&a, &masks);
+        assert_eq!(filter.virtual_length(), 4);
+        let chunk = filter.produce(0);
+        assert_eq!([chunk[0], chunk[1], chunk[2], chunk[3]], [a[1], a[3], a[64], a[65]]);
+        assert_eq!(chunk[4], ([0.0; 3], [0.0; 3], [0.0; 3]));
+        assert_eq!(filter.to_internal(2), Some(64));
+        assert_eq!(filter.to_inner(3), Some(65));
+        assert_eq!(filter.produce(3)[0], a[65]);
+    }
+
+    #[test]
+    fn test_mesh_formats() {
+        use crate::prelude::*;
+
+        let cube: &[Point<u8>] = &[[0, 0, 0], [2, 0, 0]];
+        let n = Produce::<Triangle>::virtual_length(cube);
+        let tris: Vec<Triangle> = produce_iter(cube).map(|(_, t)| t).collect();
+
+        let mut buf = vec![];
+        obj_write(&mut buf, cube, |ind| format!("voxel{}", ind.unwrap())).unwrap();
+        let mut res: Vec<(Triangle, usize)> = vec![];
+        let groups = obj_read(&buf[..], &mut res).unwrap();
+        assert_eq!(groups, vec!["voxel0".to_string(), "voxel1".to_string()]);
+        assert_eq!(res.len(), n);
+        assert!(res.iter().zip(&tris).all(|(a, b)| a.0 == *b));
+        assert_eq!(res[12].1, 1);
+
+        let mut res: Vec<(Triangle, usize)> = vec![];
+        obj_read(&b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1/1 2/2 3/3 -1\n"[..], &mut res).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].0, ([0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]));
+
+        let mut buf = vec![];
+        stl_write_binary(&mut buf, cube, |ind| ind.unwrap() as u16).unwrap();
+        assert_eq!(buf.len(), 84 + 50 * n);
+        let mut res: Vec<(Triangle, u16)> = vec![];
+        stl_read(&buf[..], &mut res).unwrap();
+        assert!(res.iter().zip(&tris).all(|(a, b)| a.0 == *b));
+        assert_eq!(res[12].1, 1);
+
+        let mut buf = vec![];
+        stl_write_ascii(&mut buf, "cube", cube).unwrap();
+        let mut res: Vec<(Triangle, u16)> = vec![];
+        stl_read(&buf[..], &mut res).unwrap();
+        assert!(res.iter().zip(&tris).all(|(a, b)| a.0 == *b && a.1 == 0));
+        assert_eq!(res.len(), n);
+
+        for format in [PlyFormat::Ascii, PlyFormat::BinaryLittleEndian, PlyFormat::BinaryBigEndian] {
+            let mut buf = vec![];
+            ply_write(&mut buf, cube, |ind| [ind.unwrap() as u8 * 255, 0, 0, 255], format).unwrap();
+            let mut res: Vec<(Triangle, Rgba<u8>)> = vec![];
+            ply_read(&buf[..], &mut res).unwrap();
+            assert_eq!(res.len(), n);
+            assert!(res.iter().zip(&tris).all(|(a, b)| a.0 == *b));
+            assert_eq!(res[0].1, [0, 0, 0, 255]);
+            assert_eq!(res[12].1, [255, 0, 0, 255]);
+        }
+
+        // Negative and fractional indices are rejected instead of reading vertex 0.
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+            property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n";
+        for face in ["3 0 1 -1\n", "3 0 1 1.5\n", "-3 0 1 2\n"] {
+            let mut res: Vec<(Triangle, Rgba<u8>)> = vec![];
+            let err = ply_read(format!("{}{}", header, face).as_bytes(), &mut res).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+        let mut res: Vec<(Triangle, Rgba<u8>)> = vec![];
+        ply_read(format!("{}3 0 1 2\n", header).as_bytes(), &mut res).unwrap();
+        assert_eq!(res.len(), 1);
+    }
+
+    #[test]
+    fn test_vox() {
+        use crate::prelude::*;
+
+        let palette = vox_default_palette();
+        assert_eq!(palette[0], [0; 4]);
+        assert_eq!(palette[1], [255, 255, 255, 255]);
+        assert_eq!(palette[215], [0, 0, 51, 255]);
+        assert_eq!(palette[216], [238, 0, 0, 255]);
+        assert_eq!(palette[255], [17, 17, 17, 255]);
+
+        let mut palette = palette;
+        palette[3] = [1, 2, 3, 4];
+        let voxels: Vec<(Point<u8>, u8)> = vec![([0, 0, 0], 1), ([2, 1, 0], 3), ([0, 0, 5], 255)];
+        let mut buf = vec![];
+        vox_write(&mut buf, &voxels, &palette).unwrap();
+        let mut res: Vec<(Point<u8>, u8)> = vec![];
+        assert_eq!(vox_read(&buf[..], &mut res).unwrap(), palette);
+        assert_eq!(res, voxels);
+
+        let mut tris: Vec<(Triangle, u8)> = vec![];
+        vox_read(&buf[..], &mut tris).unwrap();
+        assert_eq!(tris.len(), 3 * 12);
+        assert_eq!(tris[12].1, 3);
+
+        assert!(vox_write(&mut vec![], &[([0, 0, 0], 0)], &palette).is_err());
+    }
+
+    #[test]
+    fn test_render_tile_size() {
+        use crate::prelude::*;
+
+        fn render_img<A, F>(acc_data: A::Data, tile_size: u32, render: F) -> TestImg
+            where A: Acc<In = Rgba, Out = Rgba>,
+                  F: FnOnce(Renderer<(), [Point<u8>], TestImg, A, (), NoProfile>)
+        {
+            let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.25};
+            let cam = Camera::new([2.5, 1.5, -2.0]);
+            let data: Vec<Point<u8>> = (0..27).map(|i| [i % 3 * 2, i / 3 % 3, i / 9 * 2]).collect();
+            let mut img: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+            let mut buffers = RenderBuffers::new();
+            let (renderer, _) = test_builder(&mut img, &data[..], &persp, &cam)
+                .scene_ray_color(|_, _, ind| ([(ind % 3) as f32 * 0.5, (ind % 5) as f32 * 0.25, 1.0, 1.0], ()))
+                .acc_data(acc_data)
+                .tile_size(tile_size)
+                .sub_tile_triangle_limit(8)
+                .scale_to_pre_tile_size(2)
+                .build(&mut buffers).unwrap();
+            render(renderer);
+            img
+        }
+
+        let a = render_img::<TileRgbaMinDepthAcc<12>, _>((), 12, |r| r.render::<12>());
+        assert_eq!(a.1.iter().filter(|c| c[3] == 255).count(), 1268);
+        let b = render_img::<VecTileRgbaMinDepthAcc, _>(12, 12, |r| r.render_with_tile_size(12));
+        assert_eq!(a, b);
+        let c = render_img::<VecTileRgbaMinDepthAcc, _>(24, 24, |r| r.render_with_tile_size(24));
+        assert_eq!(a, c);
+        let d = render_img::<TileRgbaLinearSemiFogAcc<12, 4>, _>((), 12, |r| r.render::<12>());
+        let e = render_img::<VecTileRgbaLinearSemiFogAcc<4>, _>(12, 12, |r| r.render_with_tile_size(12));
+        assert_eq!(d, e);
+    }
+
+    #[test]
+    #[should_panic(expected = "Tile size must be at least 1")]
+    fn test_render_zero_tile_size() {
+        use crate::prelude::*;
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.25};
+        let cam = Camera::new([2.5, 1.5, -2.0]);
+        let data: Vec<Point<u8>> = vec![[0, 0, 0]];
+        let mut img: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+        let mut buffers = RenderBuffers::new();
+        let (renderer, _) = test_builder::<_, VecTileRgbaMinDepthAcc>(&mut img, &data[..], &persp, &cam)
+            .scene_ray_color(|_, _, _| ([1.0; 4], ()))
+            .acc_data(24)
+            .build(&mut buffers).unwrap();
+        renderer.render_with_tile_size(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Accumulator tile capacity 12 is less than tile size 24")]
+    fn test_render_tile_size_over_capacity() {
+        use crate::prelude::*;
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.25};
+        let cam = Camera::new([2.5, 1.5, -2.0]);
+        let data: Vec<Point<u8>> = vec![[0, 0, 0]];
+        let mut img: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+        let mut buffers = RenderBuffers::new();
+        let (renderer, _) = test_builder::<_, VecTileRgbaMinDepthAcc>(&mut img, &data[..], &persp, &cam)
+            .scene_ray_color(|_, _, _| ([1.0; 4], ()))
+            .acc_data(12)
+            .tile_size(12)
+            .build(&mut buffers).unwrap();
+        renderer.render_with_tile_size(24);
+    }
+
+    #[test]
+    fn test_renderer_builder() {
+        use crate::prelude::*;
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.25};
+        let cam = Camera::new([2.5, 1.5, -2.0]);
+        let data: Vec<Point<u8>> = (0..27).map(|i| [i % 3 * 2, i / 3 % 3, i / 9 * 2]).collect();
+        fn builder<'a>(
+            img: &'a mut TestImg,
+            data: &'a [Point<u8>],
+            persp: &'a CameraPerspective,
+            cam: &'a Camera,
+        ) -> RendererBuilder<'a, (), [Point<u8>], TestImg, VecTileRgbaMinDepthAcc, ()> {
+            test_builder(img, data, persp, cam)
+                .scene_ray_color(|_, _, ind| ([(ind % 3) as f32 * 0.5, 1.0, 1.0, 1.0], ()))
+                .acc_data(24)
+                .sub_tile_triangle_limit(8)
+        }
+
+        let mut buffers = RenderBuffers::new();
+        let mut a: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+        builder(&mut a, &data, &persp, &cam).render(&mut buffers).unwrap();
+        assert_eq!(buffers.compr_masks.len(), 6);
+        assert_eq!(buffers.sub_compr_masks.len(), 2);
+        assert_eq!(a.1.iter().filter(|c| c[3] == 255).count(), 1268);
+
+        let mut b: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+        let mut renders = 0;
+        builder(&mut b, &data, &persp, &cam).sub_masks(false).pre_masks(false)
+            .profile(&mut renders, |n, _| *n += 1, |_, _, _| {})
+            .render(&mut buffers).unwrap();
+        assert_eq!(renders, 1);
+        assert_eq!(a, b);
+
+        // Shaders and callbacks can capture their parameters.
+        let mut c: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+        let tint = [0.0, 0.5, 1.0];
+        let mut written = 0;
+        builder(&mut c, &data, &persp, &cam)
+            .shader(move |color, _| for i in 0..3 {color[i] *= tint[i]})
+            .pxl(|img, [x, y], c| {
+                written += 1;
+                img.1[(y * img.0[0] + x) as usize] = c;
+            })
+            .render(&mut buffers).unwrap();
+        assert_eq!(written, 2 * 2000);
+        assert!(c.1.iter().all(|c| c[0] == 0));
+        assert_eq!(c.1.iter().filter(|c| c[3] == 255).count(), 1268);
+
+        let mut img: TestImg = ([50, 40], vec![[0; 4]; 2000]);
+        let err = builder(&mut img, &data, &persp, &cam).tile_size(20).build(&mut buffers).err().unwrap();
+        assert_eq!(err, RendererError::UnsupportedSubTileSize(20));
+        assert_eq!(err.to_string(), "Tile size 20 is not supported by adaptive sub-tiling, expected 24");
+        assert!(builder(&mut img, &data, &persp, &cam).tile_size(20).sub_masks(false).validate().is_ok());
+        assert_eq!(builder(&mut img, &data, &persp, &cam).acc_limit(0).validate(), Err(RendererError::ZeroAccLimit));
+        let err = builder(&mut img, &data, &persp, &cam).acc_data(12).validate().err().unwrap();
+        assert_eq!(err, RendererError::AccTileCapacity {capacity: 12, tile_size: 24});
+        assert_eq!(err.to_string(), "Accumulator supports tiles up to size 12, but tile size is 24");
+        assert!(builder(&mut img, &data, &persp, &cam).acc_data(12).tile_size(12).validate().is_ok());
+        let err = test_builder::<_, TileRgbaMinDepthAcc<12>>(&mut img, &data[..], &persp, &cam)
+            .scene_ray_color(|_, _, _| ([1.0; 4], ()))
+            .acc_data(())
+            .validate();
+        assert_eq!(err, Err(RendererError::AccTileCapacity {capacity: 12, tile_size: 24}));
+        let err = RendererBuilder::<_, _, TestImg, VecTileRgbaMinDepthAcc, ()>
+            ::new((), &data[..], &mut img, &persp, &cam).validate();
+        assert_eq!(err, Err(RendererError::Missing("scene_ray_color")));
+    }
+
+    #[test]
+    fn test_edge_overlay() {
+        use crate::prelude::*;
+
+        let tri = ([0.0, 0.0, 1.0], [2.0, 0.0, 1.0], [0.0, 2.0, 1.0]);
+        let bary = triangle_barycentric(([0.5, 0.25, 0.0], [0.0, 0.0, 1.0]), tri).unwrap();
+        assert_eq!(bary, [0.625, 0.25, 0.125]);
+        let dist = triangle_edge_distances(tri, bary);
+        assert!((dist[0] - 1.25 / 2.0_f32.sqrt()).abs() < 1e-6);
+        assert_eq!([dist[1], dist[2]], [0.5, 0.25]);
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([0.5, 0.5, -1.5]);
+        let data: &[Point<u8>] = &[[0, 0, 0]];
+        let render = |hide_quad_diagonal| {
+            let mut img: TestImg = ([48, 48], vec![[0; 4]; 48 * 48]);
+            let mut edges = EdgeOverlay::rgba(2.0, [1.0, 0.0, 0.0, 1.0]);
+            edges.hide_quad_diagonal = hide_quad_diagonal;
+            render_test::<_, VecTileRgbaMinDepthAcc, _>(&mut img, data, &persp, &cam,
+                |_, _| [1.0; 4], |b| b.edges(edges));
+            img.1
+        };
+        let red = |img: &[Rgba<u8>]| img.iter().filter(|c| c[0] > 0 && c[1] == 0).count();
+        let with_diagonal = render(false);
+        let without_diagonal = render(true);
+        // The near face covers the middle of the image.
+        assert!(without_diagonal[24 * 48 + 24][1] >= 254);
+        assert!(with_diagonal[24 * 48 + 24][1] < 254);
+        assert!(red(&without_diagonal) > 0);
+        assert!(red(&with_diagonal) > red(&without_diagonal));
+    }
+
+    #[test]
+    fn test_selection_highlight() {
+        use crate::prelude::*;
+
+        let mut set = mask::CompressedMasks::new();
+        set.push(0b10);
+        let words = selection_words(&set);
+        assert!(!selection_contains(&words, 0));
+        assert!(selection_contains(&words, 1));
+        assert!(!selection_contains(&words, 64));
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([2.0, 0.5, -3.0]);
+        let data: &[Point<u8>] = &[[0, 0, 0], [3, 0, 0]];
+        let render = |selection: Option<Selection>| {
+            let mut img: TestImg = ([48, 48], vec![[0; 4]; 48 * 48]);
+            render_test::<_, VecTileRgbaMinDepthAcc, _>(&mut img, data, &persp, &cam,
+                |_, _| [1.0; 4], |b| match selection {
+                    Some(selection) => b.selection(selection),
+                    None => b,
+                });
+            img.1
+        };
+        let plain = render(None);
+        let outlined = render(Some(Selection::outline(&set, [1.0, 0.0, 0.0, 1.0], 2.0)));
+        let white = |c: &Rgba<u8>| c[1] > 0;
+        let red = |c: &Rgba<u8>| c[0] > 0 && c[1] == 0;
+        // Selected and unselected pixels are kept, the outline is drawn outside.
+        assert_eq!(plain.iter().filter(|c| white(c)).count(), outlined.iter().filter(|c| white(c)).count());
+        assert!(plain.iter().zip(&outlined).all(|(a, b)| !white(a) || a == b));
+        let outline: Vec<usize> = (0..48 * 48).filter(|&k| red(&outlined[k])).collect();
+        assert!(!outline.is_empty());
+        // The selected voxel is to the right in the image.
+        assert!(outline.iter().all(|&k| k % 48 >= 24));
+
+        let mut selection = Selection::outline(&set, [1.0, 0.0, 0.0, 1.0], 2.0);
+        selection.outline = None;
+        selection.fill = Some([0.0, 0.0, 1.0, 0.5]);
+        let filled = render(Some(selection));
+        let tinted = filled.iter().filter(|c| c[0] > 0 && c[0] < 250).count();
+        assert!(tinted > 0);
+        assert_eq!(tinted + filled.iter().filter(|c| c[0] >= 250).count(),
+            plain.iter().filter(|c| white(c)).count());
+    }
+
+    #[test]
+    fn test_post_process() {
+        use crate::prelude::*;
+
+        let k = gaussian_kernel(1.0);
+        assert_eq!(k.len(), 7);
+        assert!((k.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+
+        let lut = ColorLut::identity(5).unwrap();
+        let c = lut.lookup([0.3, 0.6, 0.9]);
+        for i in 0..3 {assert!((c[i] - [0.3, 0.6, 0.9][i]).abs() < 1e-5)}
+        // Tables need at least two entries per channel and `size³` colors.
+        assert_eq!(ColorLut::identity(1), None);
+        assert_eq!(ColorLut::new(2, vec![[0.0; 3]; 7]), None);
+        assert_eq!(ColorLut::new(2, lut.data()[..8].to_vec()).map(|lut| lut.size()), Some(2));
+
+        // A single bright pixel in the middle of a dark frame.
+        let dim = [9, 7];
+        let mut frame = vec![[0.0, 0.0, 0.0, 1.0]; 63];
+        frame[3 * 9 + 4] = [4.0, 4.0, 4.0, 1.0];
+        let src = frame.clone();
+
+        let mut blurred = src.clone();
+        post_process(dim, &mut blurred, &[PostEffect::GaussianBlur {sigma: 1.0}]);
+        // Blur keeps total energy and is symmetric.
+        let sum = |f: &[Rgba]| f.iter().map(|c| c[0]).sum::<f32>();
+        assert!((sum(&blurred) - 4.0).abs() < 1e-4);
+        assert_eq!(blurred[3 * 9 + 3], blurred[3 * 9 + 5]);
+        assert!(blurred[3 * 9 + 4][0] < 4.0);
+
+        let mut bloom = src.clone();
+        post_process(dim, &mut bloom, &[PostEffect::Bloom {threshold: 1.0, sigma: 1.0, intensity: 1.0}]);
+        assert!(bloom[3 * 9 + 3][0] > 0.0);
+        assert!(bloom[3 * 9 + 4][0] > 4.0);
+
+        let mut vignette = vec![[1.0; 4]; 63];
+        post_process(dim, &mut vignette, &[PostEffect::Vignette {strength: 0.5, radius: 0.2}]);
+        assert!(vignette[0][0] < vignette[3 * 9 + 4][0]);
+        assert_eq!(vignette[3 * 9 + 4], [1.0; 4]);
+
+        let mut sharpen = src.clone();
+        post_process(dim, &mut sharpen, &[PostEffect::Sharpen {amount: 1.0, sigma: 1.0}]);
+        assert!(sharpen[3 * 9 + 4][0] > 4.0);
+        assert!(sharpen[3 * 9 + 3][0] < 0.0);
+
+        let mut grade = src.clone();
+        post_process(dim, &mut grade, &[PostEffect::ColorGrade(ColorLut::identity(2).unwrap())]);
+        assert_eq!(grade[3 * 9 + 4], [1.0, 1.0, 1.0, 1.0]);
+
+        let mut aberration = vec![[0.0, 0.0, 0.0, 1.0]; 63];
+        for y in 0..7 {aberration[y * 9 + 8] = [1.0, 1.0, 1.0, 1.0]}
+        post_process(dim, &mut aberration, &[PostEffect::ChromaticAberration {shift: 1.0}]);
+        // Red is shifted outwards and blue inwards, at the right edge of the frame.
+        assert!(aberration[3 * 9 + 8][0] < 1.0);
+        assert!(aberration[3 * 9 + 7][2] > 0.0);
+        assert_eq!(aberration[3 * 9 + 7][1], 0.0);
+
+        // Results do not depend on thread scheduling.
+        let chain = [
+            PostEffect::Bloom {threshold: 1.0, sigma: 1.5, intensity: 0.5},
+            PostEffect::Sharpen {amount: 0.5, sigma: 1.0},
+            PostEffect::Vignette {strength: 0.3, radius: 0.5},
+        ];
+        let mut a = src.clone();
+        post_process(dim, &mut a, &chain);
+        let mut b = src.clone();
+        rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap()
+            .install(|| post_process(dim, &mut b, &chain));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_color_spaces() {
+        use crate::color::*;
+        use ColorSpace::*;
+
+        let close = |a: Rgba, b: Rgba| (0..4).all(|i| (a[i] - b[i]).abs() < 1e-4);
+        let c = [0.2, 0.5, 0.8, 0.5];
+        for space in [Linear, SRGB, DisplayP3, LinearDisplayP3, ACEScg] {
+            assert!(close(rgba_space_to_linear(rgba_linear_to_space(c, space), space), c));
+            assert!(close(rgba_convert(rgba_convert(c, SRGB, space), space, SRGB), c));
+        }
+        // White stays white in all color spaces with D65 white point.
+        assert!(close(rgba_convert([1.0; 4], SRGB, DisplayP3), [1.0; 4]));
+        assert!(close(rgba_linear_to_space([1.0; 4], ACEScg), [1.0; 4]));
+        // Pure sRGB red is inside the wider P3 gamut.
+        let red = rgba_convert([1.0, 0.0, 0.0, 1.0], Linear, LinearDisplayP3);
+        assert!(red[0] < 1.0 && red[1] > 0.0);
+
+        let p = Premultiplied::from_straight(c);
+        assert_eq!(p.0, [0.1, 0.25, 0.4, 0.5]);
+        assert!(close(p.to_straight(), c));
+        assert_eq!(Premultiplied([0.0; 4]).to_straight(), [0.0; 4]);
+        let over = p.over(p);
+        assert!(close(over.0, [0.15, 0.375, 0.6, 0.75]));
+        // Blending returns premultiplied colors, unless straight alpha is requested.
+        assert!(close(rgba_alpha_blending_linear_over(c, c), over.0));
+        assert!(close(rgba_alpha_blending_linear_over_straight(c, c), [0.2, 0.5, 0.8, 0.75]));
+    }
+
+    #[test]
+    fn test_dither() {
+        use crate::color::*;
+
+        assert_eq!(rgba_to_u8([0.5, 1.0, 0.0, 0.999]), [128, 255, 0, 255]);
+
+        let mut ranks: Vec<u32> = (0..64).map(|k| bayer_rank([k % 8, k / 8])).collect();
+        assert_eq!(&ranks[..2], &[0, 32]);
+        ranks.sort();
+        assert_eq!(ranks, (0..64).collect::<Vec<_>>());
+
+        let mask = blue_noise_mask();
+        let mut sorted = mask.to_vec();
+        sorted.sort();
+        assert_eq!(sorted, (0..1024).collect::<Vec<u16>>());
+
+        for dither in [Dither::Bayer, Dither::BlueNoise] {
+            // Deterministic per pixel position and tiling.
+            assert_eq!(dither_offset(dither, [3, 5]), dither_offset(dither, [3 + 32, 5 + 64]));
+            // A flat color between two levels is dithered into both,
+            // with the average preserved.
+            let v = 100.25 / 255.0;
+            let sum: u32 = (0..32 * 32)
+                .map(|k| rgba_to_u8_dithered([v, v, v, 1.0], dither, [k % 32, k / 32]))
+                .inspect(|c| assert!(c[0] == 100 || c[0] == 101))
+                .map(|c| c[0] as u32)
+                .sum();
+            assert_eq!(sum, 100 * 1024 + 256);
+        }
+        assert_eq!(rgba_to_u8_dithered([1.0, 0.0, 1.0, 1.0], Dither::BlueNoise, [7, 7]), [255, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_panorama() {
+        use crate::prelude::*;
+
+        for face in CubeFace::ALL {
+            let [_, _, forward] = face.basis();
+            let (f, pos) = CubeFace::from_dir(forward, 8);
+            assert_eq!(f, face);
+            assert_eq!(pos, [4.0, 4.0]);
+        }
+        // The top of the front face is toward up.
+        assert!(CubeFace::from_dir([0.0, 0.9, 1.0], 8).1[1] < 4.0);
+        let dim = [64, 32];
+        assert_eq!(cube_face_size_for_equirect(dim), 16);
+        let d = equirect_dir([48, 16], dim);
+        assert!(d[0] > 0.99);
+
+        let cam = Camera::new([6.5, 0.5, 6.5]);
+        let [left, right] = stereo_cameras(&cam, 0.1);
+        assert_eq!(left.position, [6.45, 0.5, 6.5]);
+        assert_eq!(right.position, [6.55, 0.5, 6.5]);
+
+        // Red voxel to the right, green to the left and blue in front.
+        let data: &[Point<u8>] = &[[12, 0, 6], [0, 0, 6], [6, 0, 12]];
+        let colors = [[1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]];
+        let face_size = cube_face_size_for_equirect(dim);
+        let faces = render_cube_faces(&cam, 0.1, 100.0, face_size, |_, cam, persp, img| {
+            let s = face_size;
+            RendererBuilder::<_, _, _, VecTileRgbaMinDepthAcc, _>
+                ::new((), data, img, persp, cam)
+                .scene_ray_color(|_, _, ind| (colors[ind], ()))
+                .is_transparent(|c: &Rgba| c[3] == 0.0)
+                .acc_to_linear_rgba(|c| c)
+                .size(move |_| [s, s])
+                .pxl(move |img, [x, y], c| img[(y * s + x) as usize] = c)
+                .acc_data(24)
+                .render(&mut RenderBuffers::new())
+                .unwrap();
+        });
+        let center = |face: CubeFace| faces[face.index()][(8 * face_size + 8) as usize];
+        assert_eq!(center(CubeFace::Right), [255, 0, 0, 255]);
+        assert_eq!(center(CubeFace::Left), [0, 255, 0, 255]);
+        assert_eq!(center(CubeFace::Front), [0, 0, 255, 255]);
+        assert_eq!(center(CubeFace::Back)[3], 0);
+
+        let img = equirect_from_cube_faces(&faces, face_size, dim);
+        assert_eq!(img.len(), 64 * 32);
+        assert_eq!(img[16 * 64 + 48], [255, 0, 0, 255]);
+        assert_eq!(img[16 * 64 + 16], [0, 255, 0, 255]);
+        assert_eq!(img[16 * 64 + 32], [0, 0, 255, 255]);
+        assert_eq!(img[16 * 64][3], 0);
+
+        // Sampling at the border between faces blends both faces.
+        let face_colors: [Vec<Rgba>; 6] = std::array::from_fn(|k| vec![[k as f32, 0.0, 0.0, 1.0]; 64]);
+        let c = cube_faces_sample(&face_colors, 8, [1.0, 0.0, 1.0]);
+        let (front, right) = (CubeFace::Front.index() as f32, CubeFace::Right.index() as f32);
+        assert!((c[0] - 0.5 * (front + right)).abs() < 1e-5);
+        assert_eq!(cube_faces_sample(&face_colors, 8, [0.0, 0.0, 1.0])[0], front);
+
+        // Stereo panoramas offset the eye perpendicular to each direction.
+        let (origin, dir) = equirect_stereo_ray(&cam, [32, 16], dim, -0.05);
+        assert!((origin[0] - 6.45).abs() < 3e-3 && (origin[2] - 6.5).abs() < 3e-3);
+        assert!(dir[2] > 0.99);
+        let (origin, dir) = equirect_stereo_ray(&cam, [48, 16], dim, -0.05);
+        assert!((origin[0] - 6.5).abs() < 3e-3 && (origin[2] - 6.55).abs() < 3e-3);
+        assert!(dir[0] > 0.99);
+
+        // Stereo rays are traced by the caller, here with the depth of each hit.
+        let tris: Vec<Triangle> = produce_iter(data).map(|(_, t)| t).collect();
+        let tracer = Tracer::new(&tris, 1);
+        let trace = |eye_offset, x| tracer.hit(equirect_stereo_ray(&cam, [x, 16], dim, eye_offset))
+            .map(|hit| (hit.internal_offset.unwrap() / 12, hit.depth));
+        for eye_offset in [-0.05, 0.05] {
+            assert_eq!(trace(eye_offset, 48).unwrap().0, 0);
+            assert_eq!(trace(eye_offset, 16).unwrap().0, 1);
+            assert_eq!(trace(eye_offset, 32).unwrap().0, 2);
+        }
+        // Each eye is closer to the voxel edge on its own side.
+        let depth = |eye_offset, x| trace(eye_offset, x).unwrap().1;
+        for x in [15, 31, 47] {
+            assert!(depth(0.05, x) < depth(-0.05, x));
+            assert!(depth(-0.05, x + 1) < depth(0.05, x + 1));
+        }
+    }
+
+    #[test]
+    fn test_bake_lightmap() {
+        use crate::prelude::*;
+
+        // A floor facing up, with a wall standing on it at `x = 2`.
+        let tris: &[Triangle] = &[
+            ([0.0, 0.0, 0.0], [0.0, 0.0, 4.0], [4.0, 0.0, 4.0]),
+            ([0.0, 0.0, 0.0], [4.0, 0.0, 4.0], [4.0, 0.0, 0.0]),
+            ([2.0, 0.0, 0.0], [2.0, 2.0, 0.0], [2.0, 2.0, 4.0]),
+            ([2.0, 0.0, 0.0], [2.0, 2.0, 4.0], [2.0, 0.0, 4.0]),
+        ];
+        // The floor covers the lower left quarter of the lightmap, the wall is not baked.
+        let uv = |p: Point| [p[0] / 8.0, p[2] / 8.0];
+        let uvs: Vec<UvTriangle> = tris.iter().enumerate()
+            .map(|(i, &(a, b, c))| if i < 2 {(uv(a), uv(b), uv(c))} else {([0.0; 2], [0.0; 2], [0.0; 2])})
+            .collect();
+        assert_eq!(uv_barycentric(uvs[2], [0.0; 2]), None);
+
+        let mut settings = BakeSettings::new([16, 16]);
+        settings.dilation = 0;
+        let ao = bake_lightmap(tris, &uvs[..], &settings);
+        let texel = |map: &Lightmap, x: u32, y: u32| map.texels[(y * 16 + x) as usize];
+        // Texels at `z = 2` near and away from the wall.
+        assert_eq!(texel(&ao, 0, 12)[0], 1.0);
+        assert!(texel(&ao, 3, 12)[0] < 0.8);
+        assert!(texel(&ao, 4, 12)[0] < 0.8);
+        assert_eq!(texel(&ao, 8, 12)[3], 0.0);
+        assert_eq!(texel(&ao, 3, 3)[3], 0.0);
+
+        // Deterministic regardless of threads.
+        let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap()
+            .install(|| bake_lightmap(tris, &uvs[..], &settings));
+        assert_eq!(ao, single);
+
+        // Light from the right side casts the shadow of the wall to the left.
+        settings.ao_samples = 0;
+        settings.ambient = [0.0; 3];
+        settings.lights = vec![BakeLight::Directional {
+            dir: [std::f32::consts::FRAC_1_SQRT_2, std::f32::consts::FRAC_1_SQRT_2, 0.0],
+            color: [1.0; 3],
+        }];
+        settings.dilation = 2;
+        let lit = bake_lightmap(tris, &uvs[..], &settings);
+        assert_eq!(texel(&lit, 2, 12)[0], 0.0);
+        assert!((texel(&lit, 6, 12)[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-5);
+        // Dilation fills two texels of gutter.
+        assert_eq!(texel(&lit, 9, 12), texel(&lit, 7, 12));
+        assert_eq!(texel(&lit, 10, 12)[3], 0.0);
+
+        let mut count = 0;
+        lit.write(|_, c| if c[3] == 255 {count += 1});
+        assert_eq!(count, 10 * 10);
+    }
+
+    #[test]
+    fn test_fog_volume() {
+        use crate::prelude::*;
+
+        let ray = ([0.0, 0.0, 0.0], [0.0, 0.0, 1.0]);
+        assert_eq!(fog_volume_interval(ray, ([-1.0, -1.0, 2.0], [1.0, 1.0, 5.0])), Some((2.0, 5.0)));
+        assert_eq!(fog_volume_interval(ray, ([2.0, -1.0, 2.0], [3.0, 1.0, 5.0])), None);
+        assert_eq!(fog_volume_interval(ray, ([-1.0, -1.0, -5.0], [1.0, 1.0, -2.0])), None);
+
+        // Uniform density gives the same opacity for any number of steps.
+        let uniform = |steps| FogVolume::new(([-1.0; 3], [1.0; 3]), steps, |_| (0.5, [1.0, 0.5, 0.0]));
+        let c = uniform(1).march(ray, 1.0, 3.0);
+        assert!((c[3] - volumetric_fog_fx(0.5, 2.0)).abs() < 1e-6);
+        assert!((uniform(16).march(ray, 1.0, 3.0)[3] - c[3]).abs() < 1e-5);
+        assert!((c[1] - 0.5).abs() < 1e-6);
+
+        // The fog pair composes to the marched opacity.
+        let alpha = fog_alpha_for_opacity(c[3], 2.0);
+        let mut color = [0.0; 4];
+        let mut fog = FogState::None;
+        fog.acc_alpha_blend_linear_over(1.0, [1.0, 0.5, 0.0, alpha], &mut color);
+        fog.acc_alpha_blend_linear_over(3.0, [1.0, 0.5, 0.0, alpha], &mut color);
+        fog.acc_end_alpha_blend_linear_over(&mut color);
+        assert!((color[3] - c[3]).abs() < 1e-4);
+
+        // A red wall behind fog that gets denser toward right.
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([4.0, 4.0, -3.0]);
+        let data: Vec<Point<u8>> = (0..64).map(|i| [i % 8, i / 8, 6]).collect();
+        let data = &data[..];
+        let render = |volumes: Option<FogVolumes<Rgba>>| {
+            let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, data, &persp, &cam,
+                |_, _| [1.0, 0.0, 0.0, 1.0], |b| match volumes {
+                    Some(volumes) => b.fog_volumes(volumes),
+                    None => b,
+                });
+            img.1
+        };
+        let plain = render(None);
+        let bounds = ([0.0, 0.0, 1.0], [8.0, 8.0, 3.0]);
+        let empty = render(Some(FogVolumes::linear(vec![FogVolume::new(bounds, 8, |_| (0.0, [1.0; 3]))])));
+        assert_eq!(plain, empty);
+
+        let fog = render(Some(FogVolumes::linear(vec![
+            FogVolume::new(bounds, 8, |p| (0.25 * p[0], [1.0; 3]))
+        ])));
+        let px = |img: &[Rgba<u8>], x: usize| img[16 * 32 + x];
+        assert_eq!(px(&plain, 12), [255, 0, 0, 255]);
+        assert_eq!(px(&plain, 20), [255, 0, 0, 255]);
+        assert!(px(&fog, 12)[0] > px(&fog, 12)[1]);
+        assert!(px(&fog, 12)[1] > 0);
+        assert!(px(&fog, 20)[1] > px(&fog, 12)[1]);
+        // Fog is rendered also where there are no surfaces.
+        assert_eq!(px(&plain, 4)[3], 0);
+        assert!(px(&fog, 4)[3] > 0);
+
+        // Fog behind the wall is hidden by it.
+        let uniform = |z| render(Some(FogVolumes::linear(vec![
+            FogVolume::new(([-8.0, -8.0, 1.0], [16.0, 16.0, z]), 8, |_| (0.1, [1.0; 3]))
+        ])));
+        let (front, through) = (uniform(6.0), uniform(12.0));
+        for x in [12, 20] {
+            for k in 0..4 {assert!(px(&front, x)[k].abs_diff(px(&through, x)[k]) <= 1)};
+        }
+        assert!(px(&through, 4)[3] > px(&front, 4)[3]);
+    }
+
+    #[test]
+    fn test_reflections() {
+        use crate::prelude::*;
+
+        assert!((fresnel_dielectric(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        // Total internal reflection when leaving glass at a grazing angle.
+        assert_eq!(fresnel_dielectric(0.2, 1.5), 1.0);
+        assert_eq!(refract_dir([0.98, -0.2, 0.0], [0.0, 1.0, 0.0], 1.5), None);
+        // Refraction bends toward the normal when entering glass.
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let t = refract_dir([s, -s, 0.0], [0.0, 1.0, 0.0], 1.0 / 1.5).unwrap();
+        assert!((t[0] - s / 1.5).abs() < 1e-6);
+        assert!(t[0] < s);
+        assert!(((t[0] * t[0] + t[1] * t[1]) - 1.0).abs() < 1e-6);
+        assert_eq!(reflect_dir([s, -s, 0.0], [0.0, 1.0, 0.0]), [s, s, 0.0]);
+
+        // A mirror wall in front of the camera and a red wall behind it.
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([4.0, 4.0, 3.0]);
+        let data: Vec<Point<u8>> = (0..128).map(|i| [i % 8, i / 8 % 8, if i < 64 {10} else {0}]).collect();
+        let data = &data[..];
+
+        // Glass lets most light through along the straight ray.
+        let tracer = Tracer::new(data, 4);
+        let glass = |hit: &TraceHit| if hit.index < 64 * 12 {TraceSurface::glass(1.5)}
+            else {TraceSurface::opaque([1.0, 0.0, 0.0, 1.0])};
+        let c = tracer.trace(([4.5, 4.5, 12.0], [0.0, 0.0, -1.0]), 0, &glass, &|_| [0.0; 4]);
+        assert!(c[0] > 0.9 && c[0] < 1.0);
+        // Ray directions are normalized when hitting surfaces.
+        assert_eq!(tracer.trace(([4.5, 4.5, 12.0], [0.0, 0.0, -4.0]), 0, &glass, &|_| [0.0; 4]), c);
+
+        // The broad phase is built once in world coordinates.
+        let query = RayQuery::new(data);
+
+        let render = |reflections: Option<Reflections<Rgba>>| {
+            let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, data, &persp, &cam,
+                |_, ind| if ind < 64 {[0.5, 0.5, 0.5, 1.0]} else {[1.0, 0.0, 0.0, 1.0]},
+                |b| match reflections {
+                    Some(reflections) => b.reflections(reflections).ray_query(&query),
+                    None => b,
+                });
+            img.1
+        };
+        let plain = render(None);
+        let mirror = render(Some(Reflections::rgba(2, |hit: &TraceHit| match hit.internal_offset {
+            Some(ind) if ind < 64 => TraceSurface::mirror([0.5, 0.5, 0.5, 1.0], 1.0),
+            _ => TraceSurface::opaque([1.0, 0.0, 0.0, 1.0]),
+        }, |_| [0.0; 4])));
+        let center = 16 * 32 + 16;
+        assert_ne!(plain[center], [255, 0, 0, 255]);
+        assert_eq!(mirror[center], [255, 0, 0, 255]);
+        // Surfaces get hits in world coordinates.
+        let world = render(Some(Reflections::rgba(2, |hit: &TraceHit| match hit.internal_offset {
+            Some(ind) if ind < 64 && hit.pos[2] > 9.0 =>
+                TraceSurface::mirror([0.5, 0.5, 0.5, 1.0], 1.0),
+            _ => TraceSurface::opaque([1.0, 0.0, 0.0, 1.0]),
+        }, |_| [0.0; 4])));
+        assert_eq!(world, mirror);
+    }
+
+    #[test]
+    fn test_path_trace() {
+        use crate::prelude::*;
+
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let (dir, weight) = Bsdf::metal(0.0)
+            .sample([1.0, 0.5, 0.0], [s, -s, 0.0], [0.0, 1.0, 0.0], [0.5; 3]).unwrap();
+        assert!((dir[0] - s).abs() < 1e-6 && (dir[1] - s).abs() < 1e-6);
+        assert_eq!(weight, [1.0, 0.5, 0.0]);
+        assert_eq!(path_seed(0, 1, 2), path_seed(0, 1, 2));
+        assert_ne!(path_seed(0, 1, 2), path_seed(0, 2, 1));
+
+        // A wall in front of the camera, with sky elsewhere.
+        let persp = CameraPerspective {fov: 30.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([4.0, 4.0, -3.0]);
+        let data: Vec<Point<u8>> = (0..64).map(|i| [i % 8, i / 8, 6]).collect();
+        let data = &data[..];
+        let render = |bsdf: Bsdf, settings: PathSettings, frame: &mut PathFrame| {
+            PathTracer::new(&(), data, move |_, _, _| ([0.5, 0.5, 0.5, 1.0], bsdf), |_| [1.0; 4], settings)
+                .render(&cam, &persp, frame)
+        };
+
+        // Light from the wall itself, with black sky.
+        let mut frame = PathFrame::new([8, 8]);
+        PathTracer::new(&(), data, |_, _, _| ([1.0, 0.5, 0.25, 1.0], Bsdf::emissive(1.0)),
+            |_| [0.0; 4], PathSettings::new(2)).render(&cam, &persp, &mut frame);
+        assert_eq!(frame.samples, 2);
+        assert!(frame.linear().iter().all(|&c| c == [1.0, 0.5, 0.25, 1.0]));
+
+        // Diffuse wall reflects half of the white sky, since no rays hit the scene twice.
+        let mut frame = PathFrame::new([8, 8]);
+        render(Bsdf::diffuse(), PathSettings::new(4), &mut frame);
+        assert!(frame.linear().iter().all(|&c| c == [0.5, 0.5, 0.5, 1.0]));
+
+        // Russian roulette from the first bounce keeps the expected color.
+        let settings = PathSettings {roulette_bounces: 0, ..PathSettings::new(16)};
+        let mut frame = PathFrame::new([8, 8]);
+        render(Bsdf::diffuse(), settings.clone(), &mut frame);
+        let linear = frame.linear();
+        assert!(linear.iter().any(|c| c[0] != 0.5));
+        let mean = linear.iter().map(|c| c[0]).sum::<f32>() / 64.0;
+        assert!((mean - 0.5).abs() < 0.05);
+
+        // Progressive accumulation and threads do not change the result.
+        let mut progressive = PathFrame::new([8, 8]);
+        let half = PathSettings {samples: 8, ..settings.clone()};
+        render(Bsdf::diffuse(), half.clone(), &mut progressive);
+        render(Bsdf::diffuse(), half, &mut progressive);
+        assert_eq!(progressive, frame);
+        let mut single = PathFrame::new([8, 8]);
+        rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap()
+            .install(|| render(Bsdf::diffuse(), settings, &mut single));
+        assert_eq!(single, frame);
+    }
+
+    #[test]
+    fn test_chunk_file() {
+        use crate::prelude::*;
+        use std::io::Cursor;
+
+        // A wall of 100 voxels, which does not end at a chunk boundary.
+        let data: Vec<Point<u8>> = (0..100).map(|i| [i % 10, i / 10, 6]).collect();
+        let data = &data[..];
+        let mut bytes = vec![];
+        chunk_file_write(&mut bytes, data).unwrap();
+        assert_eq!(bytes.len() as u64,
+            CHUNK_FILE_HEADER_BYTES + 19 * (CHUNK_FILE_CHUNK_BYTES + CHUNK_FILE_INDEX_BYTES) as u64);
+
+        let mut writer = ChunkWriter::new(Cursor::new(vec![])).unwrap();
+        writer.consume_all(data.iter().map(|&p| (p, 7_u8)));
+        assert_eq!(writer.materials.len(), 1200);
+        // Consumed voxels use the triangle order of cubes, which differs from voxel producers.
+        let consumed = ChunkFile::new(writer.finish().unwrap(), 4).unwrap();
+        assert_eq!(consumed.virtual_length(), 1200);
+        let aabb = |list: &dyn Produce<Triangle>| produce_iter(list)
+            .fold(([f32::MAX; 3], [f32::MIN; 3]), |(mi, ma), (_, (a, b, c))| {
+                ([0, 1, 2].map(|i| mi[i].min(a[i]).min(b[i]).min(c[i])),
+                 [0, 1, 2].map(|i| ma[i].max(a[i]).max(b[i]).max(c[i])))
+            });
+        assert_eq!(aabb(&consumed), ([0.0, 0.0, 6.0], [10.0, 10.0, 7.0]));
+        // The chunk index has the AABB of each chunk, without padding.
+        assert_eq!(consumed.chunk_aabbs().len(), 19);
+        assert_eq!(consumed.chunk_aabbs().iter().copied().reduce(aabb_union), Some(aabb(&consumed)));
+        let last: Vec<Triangle> = produce_iter(&consumed).skip(18 * 64).map(|(_, tri)| tri).collect();
+        assert_eq!(consumed.chunk_aabbs()[18], aabb(&last));
+
+        let file = ChunkFile::new(Cursor::new(bytes.clone()), 4).unwrap();
+        assert_eq!(file.virtual_length(), 1200);
+        assert_eq!(file.to_internal(1199), Some(1199));
+        assert!(produce_iter(&file).eq(produce_iter(data)));
+        assert!(file.cached() <= 4);
+        assert_eq!(file.stats().misses, 19);
+        assert!(file.stats().evictions >= 15);
+        file.produce(18 * 64);
+        assert_eq!(file.stats().hits, 1);
+
+        // Rendering streamed triangles gives the same image as in memory.
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([5.0, 5.0, -3.0]);
+        let render = |list: &(dyn Produce<Triangle> + Sync)| {
+            let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, list, &persp, &cam,
+                |depth, ind| [ind as f32 / 100.0, depth / 10.0, 0.0, 1.0], |b| b);
+            img.1
+        };
+        let tris: Vec<Triangle> = produce_iter(data).map(|(_, tri)| tri).collect();
+        file.clear_cache();
+        let expected = render(&tris);
+        assert_eq!(render(&file), expected);
+        assert!(file.cached() <= 4);
+        assert!(file.error().is_none());
+
+        // A wide wall, where the camera sees a part of it.
+        let wide: Vec<Point<u8>> = (0..400_u32).map(|i| [(i % 40) as u8, (i / 40) as u8, 6]).collect();
+        let mut wide_bytes = vec![];
+        chunk_file_write(&mut wide_bytes, &wide[..]).unwrap();
+        let file = ChunkFile::new(Cursor::new(wide_bytes), 4).unwrap();
+        assert_eq!(file.chunk_aabbs().len(), 75);
+        let view = view_matrix(&cam, [1.0; 3]);
+        let prod = &TransformProducer {matrix: view, inner: &file};
+        let mut tile_masks = pre_masks([32, 32], 16);
+        let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        // Without the chunk index, every tile reads every chunk.
+        single.install(|| masks(&persp, [32, 32], 16, prod, &mut tile_masks));
+        assert_eq!(file.stats().misses, 4 * 75);
+        let expected = tile_masks.clone();
+        // The chunk index culls chunks outside tiles, without reading them.
+        file.clear_cache();
+        let query = file.ray_query();
+        assert_eq!(file.stats().misses, 0);
+        single.install(|| masks_with_query(&persp, [32, 32], 16, prod, QueryView {query: &query, view},
+            &mut tile_masks));
+        assert!(tile_masks.iter().zip(&expected).all(|(a, b)| a.iter().eq(b.iter())));
+        // All tiles together read fewer chunks than the file has.
+        assert!(file.stats().misses < 75);
+
+        // Rendering with the chunk index gives the same image.
+        let tris: Vec<Triangle> = produce_iter(&wide[..]).map(|(_, tri)| tri).collect();
+        file.clear_cache();
+        let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+        render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, &file, &persp, &cam,
+            |depth, ind| [ind as f32 / 100.0, depth / 10.0, 0.0, 1.0], |b| b.ray_query(&query));
+        assert!(img.1.iter().any(|c| c[3] == 255));
+        assert_eq!(img.1, render(&tris));
+        assert!(file.error().is_none());
+
+        let mut bad = bytes.clone();
+        bad[0] = 0;
+        assert!(ChunkFile::new(Cursor::new(bad), 4).is_err());
+        // Triangle counts that overflow the file size are rejected.
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(ChunkFile::new(Cursor::new(huge), 4).err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+        bytes.truncate(bytes.len() - 1);
+        assert!(ChunkFile::new(Cursor::new(bytes), 4).is_err());
+    }
+
+    #[test]
+    fn test_spatial_order() {
+        use crate::prelude::*;
+
+        assert_eq!(morton_code([1, 0, 0]), 1);
+        assert_eq!(morton_code([0, 1, 0]), 2);
+        assert_eq!(morton_code([0, 0, 1]), 4);
+        assert_eq!(morton_code([3, 3, 3]), 63);
+
+        // Consecutive cells along the Hilbert curve are neighbours.
+        let mut cells: Vec<[u32; 3]> = (0..64).map(|i| [i % 4, i / 4 % 4, i / 16]).collect();
+        cells.sort_by_key(|&c| hilbert_code(c, 2));
+        assert!((0..64).all(|i| cells.iter().filter(|&&c| hilbert_code(c, 2) == i).count() == 1));
+        for w in cells.windows(2) {
+            let d: u32 = (0..3).map(|i| w[0][i].abs_diff(w[1][i])).sum();
+            assert_eq!(d, 1);
+        }
+
+        // A wall of voxels in shuffled order.
+        let mut data: Vec<Point<u8>> = (0..=255).map(|i| [i % 16, i / 16, 6]).collect();
+        data.sort_by_key(|p| hash_u64((p[0] as u64) << 8 | p[1] as u64));
+        let list = &data[..];
+        let to_internal = |off| Produce::<Triangle>::to_internal(list, off);
+        let tris: Vec<Triangle> = produce_iter(list).map(|(_, tri)| tri).collect();
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([8.0, 8.0, -2.0]);
+        let view = MaskView {cam: &cam, persp: &persp, dim: [64, 64], tile_size: 8};
+        for curve in [SpaceCurve::Morton, SpaceCurve::Hilbert] {
+            let res = spatial_reorder(list, curve, |_| (), &[view]);
+            let mut sorted = res.order.clone();
+            sorted.sort();
+            assert!(sorted.into_iter().eq(0..tris.len()));
+            assert!(res.segments_after < res.segments_before);
+
+            let reordered = ReorderProducer::new(list, res.order.clone());
+            let k = 100;
+            assert_eq!(reordered.to_internal(k), to_internal(res.order[k]));
+            assert_eq!(reordered.produce(64)[k - 64], tris[res.order[k]]);
+        }
+
+        // Groups stay contiguous, with lower groups first.
+        let voxel = |ind: Option<usize>| ind.unwrap() as u8;
+        let order = spatial_order(list, SpaceCurve::Hilbert, |ind| data[voxel(ind) as usize][0] % 2);
+        let groups: Vec<u8> = order.iter()
+            .map(|&off| data[voxel(to_internal(off)) as usize][0] % 2).collect();
+        assert!(groups.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_cull_objects() {
+        use crate::prelude::*;
+        use crate::mask::CompressedMasks;
+
+        assert_eq!(cull_range_bits(&(0..64), 0), !0);
+        assert_eq!(cull_range_bits(&(3..5), 0), 0b11000);
+        assert_eq!(cull_range_bits(&(60..70), 64), 0b111111);
+        assert_eq!(cull_range_bits(&(0..10), 64), 0);
+
+        let mut m = CompressedMasks::new();
+        m.push_repeat(0, 3);
+        m.push_repeat(!0, 2);
+        m.push_repeat(!0, 2);
+        m.push_repeat(5, 2);
+        assert_eq!(m.len(), 9);
+        assert_eq!(m.segment_count(), 4);
+        assert_eq!(m.count_ones(), 4 * 64 + 4);
+
+        // Objects off-screen, inside the view and crossing the edge of the view,
+        // with ranges that do not align with chunks.
+        let mut data: Vec<Point<u8>> = vec![];
+        data.extend((0..100).map(|i| [200 + i % 10, i / 10, 6]));
+        data.extend((0..36).map(|i| [5 + i % 6, 5 + i / 6, 30]));
+        data.extend((0..50).map(|i| [i % 50, 4, 10]));
+        data.extend((0..7).map(|i| [4 + i, 4, 8]));
+        let data = &data[..];
+        let tris: Vec<Triangle> = produce_iter(data).map(|(_, tri)| tri).collect();
+        let objects = vec![
+            CullObject::from_producer(&tris, 0..1200).unwrap(),
+            CullObject::from_producer(&tris, 1200..1632).unwrap(),
+            CullObject::from_producer(&tris, 1632..2232).unwrap(),
+        ];
+        let (mi, ma) = objects[1].aabb;
+        assert!((mi[0] - 5.0).abs() < 1e-3 && (ma[2] - 31.0).abs() < 1e-3);
+        assert_eq!(cull_objects_uniform(&tris, 1000).len(), 3);
+
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([4.0, 4.0, -3.0]);
+        let view = view_matrix(&cam, [1.0; 3]);
+        let producer = &TransformProducer {matrix: view, inner: &tris};
+        let view_objects = cull_objects_transform(&objects, &view);
+        let mut expected = pre_masks([32, 32], 8);
+        masks(&persp, [32, 32], 8, producer, &mut expected);
+        let mut culled = pre_masks([32, 32], 8);
+        masks_with_objects(&persp, [32, 32], 8, producer, &view_objects, &mut culled);
+        let mut inside = false;
+        for (a, b) in expected.iter().zip(&culled) {
+            assert_eq!(a.len(), b.len());
+            assert!(a.iter().eq(b.iter().filter(|&(_, w)| w != 0)));
+            assert!(b.segment_count() <= a.segment_count());
+            inside |= b.get(1200 / 64 + 1) == Some(!0);
+        }
+        assert!(inside);
+
+        // Rendering with object culling gives the same image.
+        let render = |objects: Option<&[CullObject]>| {
+            let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, data, &persp, &cam,
+                |depth, ind| [ind as f32 / 200.0, depth / 30.0, 0.0, 1.0], |b| match objects {
+                    Some(objects) => b.cull_objects(objects),
+                    None => b,
+                });
+            img.1
+        };
+        assert_eq!(render(Some(&objects)), render(None));
+    }
+
+    #[test]
+    fn test_shader_data() {
+        use crate::prelude::*;
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // A wall in front of the camera, with a camera that looks from above.
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let mut cam = Camera::new([4.0, 4.0, -3.0]);
+        cam.look_at([4.0, 3.0, 6.0]);
+        let data: Vec<Point<u8>> = (0..64).map(|i| [i % 8, i / 8, 6]).collect();
+        let data = &data[..];
+        let tris: Vec<Triangle> = produce_iter(data).map(|(_, tri)| tri).collect();
+        let hits = Mutex::new(vec![]);
+        let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+        render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, data, &persp, &cam,
+            |_, _| [1.0, 0.0, 0.0, 1.0], |b| b.tile_size(12).shader(|_, data| {
+                let ShaderData {hit, pixel, tile, index, view_ray, ..} = data;
+                hits.lock().unwrap().push((hit.unwrap(), pixel, tile, index, view_ray, data.world_ray(),
+                    data.world_pos(), data.normal(), data.front_face()));
+            }));
+
+        let hits = hits.into_inner().unwrap();
+        assert!(hits.len() > 100);
+        let mut pixels = vec![];
+        let mut near = 0;
+        for (hit, pixel, tile, index, view_ray, world_ray, world_pos, normal, front_face) in hits {
+            pixels.push(pixel);
+            assert_eq!(hit.1, index);
+            assert_eq!(tile, [pixel[0] / 12, pixel[1] / 12]);
+            assert!(view_ray.1[2] > 0.0);
+            assert!((vecmath::vec3_len(view_ray.1) - 1.0).abs() < 1e-5);
+            assert_eq!(world_ray.0, cam.position);
+            let p = [0, 1, 2].map(|k| world_ray.0[k] + hit.0 * world_ray.1[k]);
+            assert!((0..3).all(|k| (p[k] - world_pos[k]).abs() < 1e-4));
+            let t = ray_triangle_hit(world_ray, tris[index]).unwrap();
+            assert!((t - hit.0).abs() < 1e-4);
+            assert!(vecmath::vec3_dot(normal, world_ray.1) < 0.0);
+            // Hits behind the near faces of the wall are accumulated too.
+            if (world_pos[2] - 6.0).abs() < 1e-3 {
+                assert!((normal[2] + 1.0).abs() < 1e-5);
+                assert!(front_face);
+                near += 1;
+            } else if (world_pos[2] - 7.0).abs() < 1e-3 {
+                assert!(!front_face);
+            }
+            // The top of the image is above the camera target.
+            if pixel[1] >= 20 {assert!(world_pos[1] > 3.0)};
+        }
+        pixels.sort();
+        pixels.dedup();
+        assert_eq!(near, pixels.len());
+        assert_eq!(pixels.len(), img.1.iter().filter(|c| c[3] > 0).count());
+        for [x, y] in pixels {assert_eq!(img.1[((31 - y) * 32 + x) as usize], [255, 0, 0, 255])}
+
+        // Shaders that do not use hit geometry do not produce triangles.
+        struct Counted<'a>(&'a [Triangle], AtomicUsize);
+        impl Produce<Triangle> for Counted<'_> {
+            fn virtual_length(&self) -> usize {self.0.virtual_length()}
+            fn produce(&self, offset: usize) -> Chunk<Triangle> {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                self.0.produce(offset)
+            }
+            fn to_internal(&self, offset: usize) -> Option<usize> {self.0.to_internal(offset)}
+        }
+        let produced = |geometry: bool| {
+            let list = Counted(&tris, AtomicUsize::new(0));
+            let mut img: TestImg = ([32, 32], vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaLinearSemiFogAcc<8>, _>(&mut img, &list, &persp, &cam,
+                |_, _| [1.0; 4], |b| b.shader(move |color, data| {
+                    if geometry {color[0] = data.normal()[2].abs()};
+                }));
+            list.1.into_inner()
+        };
+        assert!(produced(true) > produced(false));
+    }
+
+    #[test]
+    fn test_quad_sub_tiles() {
+        use crate::prelude::*;
+
+        // A dense wall in the lower left corner and a few voxels elsewhere.
+        let mut data: Vec<Point<u8>> = (0..64).map(|i| [i % 8, i / 8, 6]).collect();
+        data.extend([[14, 10, 6], [12, 2, 6]]);
+        let data = &data[..];
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let cam = Camera::new([8.0, 8.0, -2.0]);
+        let producer = &TransformProducer {matrix: view_matrix(&cam, [1.0; 3]), inner: data};
+        let dim = [32, 32];
+        let mut tile_masks = pre_masks(dim, 32);
+        masks(&persp, dim, 32, producer, &mut tile_masks);
+
+        let quad = QuadSubTiling {min_size: 2, triangle_limit: 24};
+        let leaves = quad_sub_tiles(&persp, dim, [0, 0], 32, quad, producer, &tile_masks[0]);
+        // Leaves cover the tile without overlapping.
+        let mut covered = vec![0; 32 * 32];
+        for leaf in &leaves {
+            for j in leaf.pos[1]..leaf.pos[1] + leaf.size[1] {
+                for i in leaf.pos[0]..leaf.pos[0] + leaf.size[0] {covered[(j * 32 + i) as usize] += 1}
+            }
+            assert!(leaf.size[0] >= 2 && leaf.size[1] >= 2);
+            assert!(leaf.masks.count_ones() <= tile_masks[0].count_ones());
+            assert!(leaf.masks.count_ones() < 24 || leaf.size[0] < 4);
+        }
+        assert!(covered.iter().all(|&n| n == 1));
+        // Dense regions are refined more than sparse regions.
+        let min = leaves.iter().map(|l| l.size[0]).min().unwrap();
+        let max = leaves.iter().map(|l| l.size[0]).max().unwrap();
+        assert!(min < max);
+        let quad = QuadSubTiling {min_size: 2, triangle_limit: 10_000};
+        assert!(quad_sub_tiles(&persp, dim, [0, 0], 32, quad, producer, &tile_masks[0]).is_empty());
+
+        // Rendering with quadtree sub-tiling gives the same image, with any tile size.
+        let render = |tile_size: u32, quad: Option<u32>, sub_masks: bool| {
+            let mut img: TestImg = (dim, vec![[0; 4]; 32 * 32]);
+            render_test::<_, VecTileRgbaMinDepthAcc, _>(&mut img, data, &persp, &cam,
+                |depth, ind| [ind as f32 / 64.0, depth / 30.0, 0.0, 1.0], |b| {
+                    let b = b.acc_data(tile_size).tile_size(tile_size)
+                        .sub_masks(sub_masks).sub_tile_triangle_limit(16);
+                    match quad {
+                        Some(min_size) => b.quad_sub_tiles(min_size),
+                        None => b,
+                    }
+                });
+            img.1
+        };
+        let expected = render(24, None, false);
+        assert!(expected.iter().any(|c| c[3] == 255));
+        assert_eq!(render(24, None, true), expected);
+        assert_eq!(render(24, Some(1), true), expected);
+        assert_eq!(render(20, Some(3), true), expected);
+        // Tiles too small for equal sub-tiles.
+        assert_eq!(render(8, Some(2), true), expected);
+        assert_eq!(render(10, Some(2), true), expected);
+
+        // Renderers without a leaf buffer store leaves per frame.
+        let render_without_buffer = |sub_masks: bool| {
+            let mut img: TestImg = (dim, vec![[0; 4]; 32 * 32]);
+            let mut buffers = RenderBuffers::new();
+            let (mut renderer, tile_size) = test_builder::<_, VecTileRgbaMinDepthAcc>(&mut img, data, &persp, &cam)
+                .scene_ray_color(|_, depth, ind| ([ind as f32 / 64.0, depth / 30.0, 0.0, 1.0], ()))
+                .acc_data(10).tile_size(10).sub_masks(sub_masks).sub_tile_triangle_limit(16)
+                .quad_sub_tiles(2)
+                .build(&mut buffers).unwrap();
+            renderer.quad_compr_masks = None;
+            renderer.render_with_tile_size(tile_size);
+            img.1
+        };
+        assert_eq!(render_without_buffer(true), expected);
+        assert_eq!(render_without_buffer(false), expected);
+    }
+
+    #[test]
+    fn test_exact_masks() {
+        use crate::prelude::*;
+
+        // A tile in the lower left corner, with a diagonal triangle passing by it.
+        let persp = CameraPerspective {fov: 90.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.0};
+        let ndim = near_dim(&persp);
+        let fr = frustum_planes_tile(&persp, ndim, [-1.0, -1.0], [0.5, 0.5]);
+        let corners = frustum_planes_corners(&fr);
+        assert!(corners.iter().take(4).all(|p| (p[2] - 0.1).abs() < 1e-5));
+        assert!((corners[7][0] + 0.5 * 100.0).abs() < 1e-3 && (corners[7][1] + 0.5 * 100.0).abs() < 1e-3);
+        let diagonal: Triangle = ([-10.0, 1.0, 10.0], [1.0, -10.0, 10.0], [1.1, -9.9, 10.0]);
+        let inside: Triangle = ([-8.0, -8.0, 10.0], [-7.0, -8.0, 10.0], [-8.0, -7.0, 10.0]);
+        let crossing: Triangle = ([-20.0, -6.0, 10.0], [20.0, -6.0, 10.0], [0.0, 20.0, 10.0]);
+        let behind: Triangle = ([-8.0, -8.0, -1.0], [-7.0, -8.0, -1.0], [-8.0, -7.0, -1.0]);
+        assert!(frustum_planes_aabb_intersect(&fr, triangle_aabb(diagonal)));
+        assert!(!frustum_planes_triangle_intersect(&fr, &corners, diagonal));
+        assert!(frustum_planes_triangle_intersect(&fr, &corners, inside));
+        assert!(frustum_planes_triangle_intersect(&fr, &corners, crossing));
+        assert!(!frustum_planes_triangle_intersect(&fr, &corners, behind));
+        let mut chunk = [behind; 64];
+        chunk[..3].copy_from_slice(&[diagonal, inside, crossing]);
+        assert_eq!(frustum_planes_triangle_chunk_mask(&fr, &chunk, !0), 0b111);
+        assert_eq!(frustum_planes_triangle_chunk_exact_mask(&fr, &chunk, !0), 0b110);
+        assert_eq!(frustum_planes_triangle_chunk_exact_mask(&fr, &chunk, 0b101), 0b100);
+
+        // Thin diagonal triangles, as in a wire fence.
+        let tris: Vec<Triangle> = (0..40).flat_map(|i| {
+            let x = i as f32 * 0.5 - 10.0;
+            [([x, -8.0, 10.0], [x + 8.0, 8.0, 10.0], [x + 8.1, 8.0, 10.0]),
+             ([x + 8.0, -8.0, 11.0], [x, 8.0, 11.0], [x + 0.1, 8.0, 11.0])]
+        }).collect();
+        let cam = Camera::new([0.0; 3]);
+        let dim = [32, 32];
+        let mut aabb_masks = pre_masks(dim, 8);
+        masks(&persp, dim, 8, &tris, &mut aabb_masks);
+        let mut exact_masks = aabb_masks.clone();
+        let stats = masks_exact(&persp, dim, 8, &tris, &mut exact_masks);
+        assert_eq!(stats.aabb, aabb_masks.iter().map(|m| m.count_ones()).sum::<u64>());
+        assert_eq!(stats.exact, exact_masks.iter().map(|m| m.count_ones()).sum::<u64>());
+        assert!(stats.false_positives() > 0);
+        assert!(stats.false_positive_ratio() > 0.25 && stats.false_positive_ratio() < 1.0);
+        for (a, b) in aabb_masks.iter().zip(&exact_masks) {
+            assert!(b.iter().all(|(i, w)| a.get(i).unwrap() & w == w));
+        }
+        assert_eq!(ExactMaskStats::default().false_positive_ratio(), 0.0);
+
+        // Rendering with exact masks gives the same image.
+        let render = |exact: bool, quad: bool| {
+            let mut img: TestImg = (dim, vec![[0; 4]; 32 * 32]);
+            let mut stats = None;
+            render_test::<_, VecTileRgbaMinDepthAcc, _>(&mut img, &tris[..], &persp, &cam,
+                |depth, ind| [ind as f32 / 80.0, depth / 30.0, 0.0, 1.0], |b| {
+                    let b = if quad {b.quad_sub_tiles(2)} else {b};
+                    b.sub_tile_triangle_limit(8).exact_masks(exact)
+                        .profile(&mut stats, |_, _| {}, |s, data, _| *s = Some(data.exact_mask_stats))
+                });
+            (img.1, stats.unwrap())
+        };
+        let (expected, no_stats) = render(false, false);
+        assert!(expected.iter().any(|c| c[3] == 255));
+        assert_eq!(no_stats, None);
+        let (a, a_stats) = render(true, false);
+        assert_eq!(a, expected);
+        assert!(a_stats.unwrap().false_positives() > 0);
+        assert_eq!(render(true, true).0, expected);
+    }
+
     #[test]
     fn test_sub_tile() {
         use crate::tile::*;
@@ -67,11 +67,37 @@ impl CompressedMasks {
         }
     }
 
+    /// Push a word repeated some number of times.
+    ///
+    /// Words of all zeros or all ones extend a run in constant time.
+    pub fn push_repeat(&mut self, word: u64, count: usize) {
+        use Segment::*;
+        if count == 0 {return};
+        match word {
+            0 | 0xffffffffffffffff => {
+                self.push(word);
+                self.len_words += count - 1;
+                match self.segments.last_mut() {
+                    Some(ZeroRun(n)) | Some(OneRun(n)) => *n += count - 1,
+                    _ => unreachable!(),
+                }
+            }
+            _ => for _ in 0..count {self.push(word)},
+        }
+    }
+
     /// Get the uncompressed length (number of `u64` words).
     pub fn len(&self) -> usize {
         self.len_words
     }
 
+    /// Get the number of compressed segments.
+    ///
+    /// Fewer segments means better compression and faster iteration.
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
     /// Counts the total number of ones.
     pub fn count_ones(&self) -> u64 {
         let mut sum: u64 = 0;
@@ -120,3 +120,22 @@ pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
 
 /// Clamp to unit interval.
 pub fn clamp(a: f32) -> f32 {if a >= 1.0 {1.0} else if a <= 0.0 {0.0} else {a}}
+
+/// Hashes a 64 bit value, using the finalizer of SplitMix64.
+///
+/// This is used to derive independent random seeds, e.g. per pixel.
+pub fn hash_u64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Generates a random number in range `0.0..1.0`, updating state.
+///
+/// Uses SplitMix64, which gives the same sequence on every platform.
+pub fn rand_f32(state: &mut u64) -> f32 {
+    let z = hash_u64(*state);
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    (z >> 40) as f32 / (1_u64 << 24) as f32
+}
@@ -0,0 +1,115 @@
+//! # Wavefront OBJ format
+//!
+//! Reads triangles into any consumer and writes triangles from any producer.
+//!
+//! Materials map to OBJ groups (`g`).
+//! When reading, the material is the index of the group in the list of group names.
+//! Faces before the first group belong to the group `default`.
+//!
+//! Only vertex positions and faces are supported.
+//! Texture coordinates and normals are ignored.
+//! Faces with more than 3 vertices are split into a triangle fan.
+
+use std::io::{self, BufRead, Write};
+use std::fmt::Display;
+
+use crate::{Point, Triangle};
+use crate::consume::Consumer;
+use crate::produce::Produce;
+
+fn invalid(msg: String) -> io::Error {io::Error::new(io::ErrorKind::InvalidData, msg)}
+
+/// Reads triangles from OBJ format into consumer.
+///
+/// The material is an index into the returned list of group names.
+pub fn obj_read<R, C>(r: R, consumer: &mut C) -> io::Result<Vec<String>>
+    where R: BufRead, C: Consumer<(Triangle, usize)>
+{
+    let f = consumer.consumer();
+    let mut vertices: Vec<Point> = vec![];
+    let mut groups: Vec<String> = vec![];
+    let mut group: Option<usize> = None;
+    let mut face: Vec<Point> = vec![];
+    for (line_nr, line) in r.lines().enumerate() {
+        let line = line?;
+        let line_nr = line_nr + 1;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let mut p = [0.0; 3];
+                for x in &mut p {
+                    *x = words.next().and_then(|w| w.parse().ok()).ok_or_else(||
+                        invalid(format!("Line {}: Expected vertex coordinate", line_nr)))?;
+                }
+                vertices.push(p);
+            }
+            Some("g") => {
+                let name = words.collect::<Vec<_>>().join(" ");
+                group = Some(match groups.iter().position(|g| *g == name) {
+                    Some(ind) => ind,
+                    None => {groups.push(name); groups.len() - 1}
+                });
+            }
+            Some("f") => {
+                face.clear();
+                for w in words {
+                    // Ignore texture coordinates and normals.
+                    let ind: i64 = w.split('/').next().and_then(|w| w.parse().ok())
+                        .ok_or_else(|| invalid(format!("Line {}: Expected vertex index", line_nr)))?;
+                    // Negative indices are relative to the end of the vertex list.
+                    let ind = if ind < 0 {vertices.len() as i64 + ind} else {ind - 1};
+                    let p = usize::try_from(ind).ok().and_then(|ind| vertices.get(ind))
+                        .ok_or_else(|| invalid(format!("Line {}: Vertex index out of bounds", line_nr)))?;
+                    face.push(*p);
+                }
+                if face.len() < 3 {
+                    return Err(invalid(format!("Line {}: Expected at least 3 vertices", line_nr)));
+                }
+                let mat = match group {
+                    Some(ind) => ind,
+                    None => {
+                        groups.push("default".into());
+                        let ind = groups.len() - 1;
+                        group = Some(ind);
+                        ind
+                    }
+                };
+                for i in 1..face.len() - 1 {
+                    f(consumer, ((face[0], face[i], face[i + 1]), mat));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(groups)
+}
+
+/// Writes triangles from producer in OBJ format.
+///
+/// The group is computed from the internal address of each triangle.
+/// A new group is started whenever the group changes.
+pub fn obj_write<W, T, G>(
+    w: &mut W,
+    list: &T,
+    group: impl Fn(Option<usize>) -> G,
+) -> io::Result<()>
+    where W: Write, T: Produce<Triangle> + ?Sized, G: PartialEq + Display
+{
+    use crate::produce::produce_iter;
+
+    let mut last: Option<G> = None;
+    let mut n = 0;
+    for (off, (a, b, c)) in produce_iter(list) {
+        let g = group(list.to_internal(off));
+        if last.as_ref() != Some(&g) {
+            writeln!(w, "g {}", g)?;
+            last = Some(g);
+        }
+        for p in [a, b, c] {
+            writeln!(w, "v {} {} {}", p[0], p[1], p[2])?;
+        }
+        writeln!(w, "f {} {} {}", n + 1, n + 2, n + 3)?;
+        n += 3;
+    }
+    Ok(())
+}
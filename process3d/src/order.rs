@@ -0,0 +1,193 @@
+//! # Spatial ordering
+//!
+//! Reorders triangles along a space filling curve, for better mask compression.
+//!
+//! Compressed masks use few segments when the triangles visible in a tile
+//! are next to each other in the virtual list, such that whole chunks are either
+//! visible or culled.
+//! Sorting triangles by the curve position of their centroids keeps triangles
+//! that are near in space near in the list, regardless of input order.
+//!
+//! The Hilbert curve keeps neighbours closer than the Morton curve (Z-order),
+//! while the Morton curve is faster to compute.
+//!
+//! Triangles are sorted per group first, e.g. per material,
+//! such that each group stays contiguous.
+//!
+//! The result is a permutation of the virtual list, used with `ReorderProducer`,
+//! so internal addresses still map back to the original triangles.
+
+use crate::{PixelPos, Triangle};
+use crate::cam::{Camera, CameraPerspective};
+use crate::produce::{Produce, ReorderProducer};
+
+/// The number of bits per axis used by space filling curves.
+pub const SPACE_CURVE_BITS: u32 = 21;
+
+/// Space filling curve.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpaceCurve {
+    /// Morton curve, also known as Z-order.
+    Morton,
+    /// Hilbert curve.
+    Hilbert,
+}
+
+impl SpaceCurve {
+    /// Computes curve position of cell, using some bits per axis.
+    pub fn code(self, cell: [u32; 3], bits: u32) -> u64 {
+        match self {
+            SpaceCurve::Morton => morton_code(cell),
+            SpaceCurve::Hilbert => hilbert_code(cell, bits),
+        }
+    }
+}
+
+/// Spreads the lower 21 bits, such that there are two zero bits between each bit.
+fn spread_bits(x: u32) -> u64 {
+    let mut x = x as u64 & 0x1f_ffff;
+    x = (x | x << 32) & 0x1f_0000_0000_ffff;
+    x = (x | x << 16) & 0x1f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+/// Computes Morton code of cell, using the lower 21 bits per axis.
+pub fn morton_code([x, y, z]: [u32; 3]) -> u64 {
+    spread_bits(x) | spread_bits(y) << 1 | spread_bits(z) << 2
+}
+
+/// Computes Hilbert code of cell, using some bits per axis up to 21.
+///
+/// Uses the algorithm of John Skilling, "Programming the Hilbert curve" (2004).
+pub fn hilbert_code(cell: [u32; 3], bits: u32) -> u64 {
+    let bits = bits.clamp(1, SPACE_CURVE_BITS);
+    let m = 1_u32 << (bits - 1);
+    let mut x = cell.map(|v| v & ((m << 1) - 1));
+
+    // Inverse undo of rotations and reflections.
+    let mut q = m;
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..3 {
+            if x[i] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+
+    // Gray encode.
+    x[1] ^= x[0];
+    x[2] ^= x[1];
+    let mut t = 0;
+    let mut q = m;
+    while q > 1 {
+        if x[2] & q != 0 {t ^= q - 1};
+        q >>= 1;
+    }
+    for v in &mut x {*v ^= t}
+
+    // The first axis holds the most significant bit of each level.
+    spread_bits(x[2]) | spread_bits(x[1]) << 1 | spread_bits(x[0]) << 2
+}
+
+/// Computes permutation of virtual list that sorts triangles along space filling curve,
+/// per group of internal address.
+///
+/// Each item is an offset in the virtual list of producer.
+/// Triangles with equal group and curve position keep their order.
+pub fn spatial_order<P, G>(list: &P, curve: SpaceCurve, group: impl Fn(Option<usize>) -> G) -> Vec<usize>
+    where P: Produce<Triangle> + ?Sized, G: Ord
+{
+    use crate::produce::produce_iter;
+
+    let centroid = |(a, b, c): Triangle| [0, 1, 2].map(|i| (a[i] + b[i] + c[i]) / 3.0);
+    let mut mi = [f32::INFINITY; 3];
+    let mut ma = [f32::NEG_INFINITY; 3];
+    for (_, tri) in produce_iter(list) {
+        let p = centroid(tri);
+        for i in 0..3 {
+            if p[i].is_finite() {
+                mi[i] = mi[i].min(p[i]);
+                ma[i] = ma[i].max(p[i]);
+            }
+        }
+    }
+
+    let cells = ((1_u64 << SPACE_CURVE_BITS) - 1) as f32;
+    let mut keys: Vec<(G, u64, usize)> = produce_iter(list).map(|(off, tri)| {
+        let p = centroid(tri);
+        let cell = [0, 1, 2].map(|i| {
+            let d = ma[i] - mi[i];
+            let t = if d > 0.0 {(p[i] - mi[i]) / d} else {0.0};
+            (t.clamp(0.0, 1.0) * cells) as u32
+        });
+        (group(list.to_internal(off)), curve.code(cell, SPACE_CURVE_BITS), off)
+    }).collect();
+    keys.sort_unstable();
+    keys.into_iter().map(|(_, _, off)| off).collect()
+}
+
+/// View used to count mask segments.
+#[derive(Copy, Clone, Debug)]
+pub struct MaskView<'a> {
+    /// The camera.
+    pub cam: &'a Camera,
+    /// The camera perspective.
+    pub persp: &'a CameraPerspective,
+    /// The image size in pixels.
+    pub dim: PixelPos,
+    /// The tile size in pixels.
+    pub tile_size: u32,
+}
+
+/// Counts the compressed mask segments of all tiles, when rendering from view.
+pub fn mask_segment_count<P>(list: &P, view: &MaskView) -> usize
+    where P: Produce<Triangle> + Sync + ?Sized
+{
+    use crate::mask::CompressedMasks;
+    use crate::produce::TransformProducer;
+    use crate::render::view_matrix;
+    use crate::tile::{masks, tile_grid};
+
+    let producer = &TransformProducer {matrix: view_matrix(view.cam, [1.0; 3]), inner: list};
+    let [w, h] = tile_grid(view.dim, view.tile_size);
+    let mut tile_masks = vec![CompressedMasks::new(); (w * h) as usize];
+    masks(view.persp, view.dim, view.tile_size, producer, &mut tile_masks);
+    tile_masks.iter().map(|m| m.segment_count()).sum()
+}
+
+/// Stores result of spatial reordering.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpatialReorder {
+    /// The permutation, where each item is an offset in the original virtual list.
+    pub order: Vec<usize>,
+    /// The number of mask segments in all views before reordering.
+    pub segments_before: usize,
+    /// The number of mask segments in all views after reordering.
+    pub segments_after: usize,
+}
+
+/// Reorders triangles along space filling curve per group,
+/// counting mask segments in views before and after.
+pub fn spatial_reorder<P, G>(
+    list: &P,
+    curve: SpaceCurve,
+    group: impl Fn(Option<usize>) -> G,
+    views: &[MaskView],
+) -> SpatialReorder
+    where P: Produce<Triangle> + Sync + ?Sized, G: Ord
+{
+    let order = spatial_order(list, curve, group);
+    let reordered = ReorderProducer::new(list, order);
+    let segments_before = views.iter().map(|v| mask_segment_count(list, v)).sum();
+    let segments_after = views.iter().map(|v| mask_segment_count(&reordered, v)).sum();
+    SpatialReorder {order: reordered.order().to_vec(), segments_before, segments_after}
+}
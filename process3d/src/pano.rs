@@ -0,0 +1,236 @@
+//! # Panoramic cameras
+//!
+//! Renders cube maps and stereo pairs, and resamples equirectangular panoramas from cube maps,
+//! e.g. for skyboxes, environment maps and VR previews.
+//!
+//! A cube map is rendered as six perspective images with 90 degrees field of view,
+//! one per face, using the same renderer, producers and accumulators as any image.
+//! Since each face has a planar near plane, tile culling uses the exact frustum of the tile.
+//!
+//! Tile rendering requires a planar near plane per image,
+//! so there are no tile culling volumes for equirectangular projection,
+//! and equirectangular panoramas are not rendered directly.
+//! Instead, an equirectangular panorama is resampled from a cube map,
+//! with bilinear interpolation in linear color space,
+//! so it has the resolution of the cube map and not of the panorama.
+//! Sampling continues across face borders into the neighbour face, which avoids seams.
+//!
+//! Directions are in camera space, where `x` is right, `y` is up and `z` is forward.
+//! The view must not be flipped, so use the default `flip_xyz` when rendering faces.
+//!
+//! Stereo pairs of planar images are rendered by offsetting the camera along its right direction.
+//! Stereo panoramas need a different eye position per direction,
+//! where the eyes move on a horizontal circle (omni-directional stereo).
+//! Since this does not fit a single camera position, stereo panoramas are not rendered here.
+//! `equirect_stereo_ray` gives the ray per pixel, to be traced by the caller.
+//! VR formats usually stack the left and right eye images, top and bottom.
+
+use crate::{PixelPos, Ray, Rgba, Vector};
+use crate::cam::{Camera, CameraPerspective};
+
+/// Cube map face, named after the camera direction it looks toward.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CubeFace {
+    /// Looks toward right (`+x`).
+    Right,
+    /// Looks toward left (`-x`).
+    Left,
+    /// Looks up (`+y`), with the top of the image toward back.
+    Up,
+    /// Looks down (`-y`), with the top of the image toward forward.
+    Down,
+    /// Looks forward (`+z`).
+    Front,
+    /// Looks back (`-z`).
+    Back,
+}
+
+/// Stores images of cube map faces, in the order of `CubeFace::ALL`.
+///
+/// Each image has size `face_size x face_size`, stored row by row from top.
+pub type CubeFaces = [Vec<Rgba<u8>>; 6];
+
+impl CubeFace {
+    /// All faces, in the order of `+x`, `-x`, `+y`, `-y`, `+z` and `-z`.
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::Right, CubeFace::Left,
+        CubeFace::Up, CubeFace::Down,
+        CubeFace::Front, CubeFace::Back,
+    ];
+
+    /// Returns right, up and forward direction of face in camera space.
+    pub fn basis(self) -> [Vector; 3] {
+        use vecmath::vec3_cross as cross;
+        use CubeFace::*;
+
+        let (up, forward) = match self {
+            Right => ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+            Left => ([0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]),
+            Up => ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+            Down => ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+            Front => ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+            Back => ([0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+        };
+        [cross(up, forward), up, forward]
+    }
+
+    /// Returns camera of face, at the same position as camera.
+    pub fn camera(self, cam: &Camera) -> Camera {
+        let world = |v: Vector| [0, 1, 2]
+            .map(|i| v[0] * cam.right[i] + v[1] * cam.up[i] + v[2] * cam.forward[i]);
+        let [right, up, forward] = self.basis();
+        Camera {position: cam.position, right: world(right), up: world(up), forward: world(forward)}
+    }
+
+    /// Finds face and position in face image of direction in camera space.
+    ///
+    /// The position is in pixels of face image with some size,
+    /// where pixel centers are at half-integer positions.
+    pub fn from_dir(dir: Vector, face_size: u32) -> (CubeFace, [f32; 2]) {
+        use vecmath::vec3_dot as dot;
+        use CubeFace::*;
+
+        let [x, y, z] = dir.map(f32::abs);
+        let face = if x >= y && x >= z {
+            if dir[0] >= 0.0 {Right} else {Left}
+        } else if y >= z {
+            if dir[1] >= 0.0 {Up} else {Down}
+        } else if dir[2] >= 0.0 {Front} else {Back};
+        let [right, up, forward] = face.basis();
+        let f = dot(dir, forward);
+        let (u, v) = (dot(dir, right) / f, dot(dir, up) / f);
+        let s = face_size as f32;
+        (face, [0.5 * (u + 1.0) * s, 0.5 * (1.0 - v) * s])
+    }
+
+    /// Returns the index of face in `CubeFace::ALL`.
+    pub fn index(self) -> usize {self as usize}
+}
+
+/// Returns camera perspective for cube map faces.
+pub fn cube_face_perspective(near_clip: f32, far_clip: f32) -> CameraPerspective {
+    CameraPerspective {fov: 90.0, near_clip, far_clip, aspect_ratio: 1.0}
+}
+
+/// Returns the cube face size that matches resolution of equirectangular image.
+///
+/// Each face covers a quarter of the horizontal angle.
+pub fn cube_face_size_for_equirect(dim: PixelPos) -> u32 {
+    dim[0].div_ceil(4).max(1)
+}
+
+/// Renders cube map faces with some size.
+///
+/// The function renders one face, using the face camera and perspective,
+/// into an image of size `face_size x face_size` stored row by row from top.
+pub fn render_cube_faces(
+    cam: &Camera,
+    near_clip: f32,
+    far_clip: f32,
+    face_size: u32,
+    mut render_face: impl FnMut(CubeFace, &Camera, &CameraPerspective, &mut Vec<Rgba<u8>>),
+) -> CubeFaces {
+    let persp = cube_face_perspective(near_clip, far_clip);
+    CubeFace::ALL.map(|face| {
+        let mut img = vec![[0; 4]; (face_size * face_size) as usize];
+        render_face(face, &face.camera(cam), &persp, &mut img);
+        img
+    })
+}
+
+/// Calculates direction in camera space of pixel in equirectangular image.
+///
+/// The horizontal axis covers longitude from back, through left, forward and right.
+/// The vertical axis covers latitude from up to down.
+pub fn equirect_dir(pos: PixelPos, dim: PixelPos) -> Vector {
+    use std::f32::consts::PI;
+
+    let lon = ((pos[0] as f32 + 0.5) / dim[0] as f32 * 2.0 - 1.0) * PI;
+    let lat = (0.5 - (pos[1] as f32 + 0.5) / dim[1] as f32) * PI;
+    [lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos()]
+}
+
+/// Samples cube map faces of linear colors in direction in camera space,
+/// with bilinear interpolation.
+///
+/// Texels outside a face are taken from the neighbour face in the direction of the texel center,
+/// such that there are no seams between faces.
+pub fn cube_faces_sample(faces: &[Vec<Rgba>; 6], face_size: u32, dir: Vector) -> Rgba {
+    let (face, pos) = CubeFace::from_dir(dir, face_size);
+    let s = face_size as i32;
+    let texel = |i: i32, j: i32| {
+        if (0..s).contains(&i) && (0..s).contains(&j) {
+            return faces[face.index()][(j * s + i) as usize];
+        }
+
+        let u = (i as f32 + 0.5) / s as f32 * 2.0 - 1.0;
+        let v = 1.0 - (j as f32 + 0.5) / s as f32 * 2.0;
+        let [right, up, forward] = face.basis();
+        let dir = [0, 1, 2].map(|k| right[k] * u + up[k] * v + forward[k]);
+        let (face, pos) = CubeFace::from_dir(dir, face_size);
+        let [i, j] = pos.map(|x| (x.floor() as i32).clamp(0, s - 1));
+        faces[face.index()][(j * s + i) as usize]
+    };
+
+    let (x, y) = (pos[0] - 0.5, pos[1] - 0.5);
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let (a, b, c, d) = (texel(x0, y0), texel(x0 + 1, y0), texel(x0, y0 + 1), texel(x0 + 1, y0 + 1));
+    std::array::from_fn(|i| {
+        let top = a[i] + (b[i] - a[i]) * tx;
+        let bottom = c[i] + (d[i] - c[i]) * tx;
+        top + (bottom - top) * ty
+    })
+}
+
+/// Resamples equirectangular image from cube map faces, see `cube_faces_sample`.
+///
+/// Returns image of size `dim`, stored row by row from top.
+pub fn equirect_from_cube_faces(faces: &CubeFaces, face_size: u32, dim: PixelPos) -> Vec<Rgba<u8>> {
+    use crate::color::*;
+    use rayon::prelude::*;
+
+    let linear: [Vec<Rgba>; 6] = faces.each_ref()
+        .map(|f| f.iter().map(|&c| rgba_gamma_srgb_to_linear(rgba_to_f32(c))).collect());
+    let mut img = vec![[0; 4]; (dim[0] * dim[1]) as usize];
+    img.par_chunks_mut(dim[0].max(1) as usize).enumerate().for_each(|(y, row)| {
+        for (x, c) in row.iter_mut().enumerate() {
+            let dir = equirect_dir([x as u32, y as u32], dim);
+            let color = cube_faces_sample(&linear, face_size, dir);
+            *c = rgba_to_u8(rgba_gamma_linear_to_srgb(color));
+        }
+    });
+    img
+}
+
+/// Calculates ray in world coordinates of pixel in equirectangular image,
+/// for an eye in omni-directional stereo.
+///
+/// The eye is offset from the camera position perpendicular to the horizontal direction,
+/// with negative offset for the left eye and positive for the right eye,
+/// e.g. half the distance between eyes.
+/// The direction is normalized.
+pub fn equirect_stereo_ray(cam: &Camera, pos: PixelPos, dim: PixelPos, eye_offset: f32) -> Ray {
+    let world = |v: Vector| [0, 1, 2]
+        .map(|i| v[0] * cam.right[i] + v[1] * cam.up[i] + v[2] * cam.forward[i]);
+    let dir = equirect_dir(pos, dim);
+    let h = (dir[0] * dir[0] + dir[2] * dir[2]).sqrt();
+    // The right direction of the horizontal direction, or camera right at the poles.
+    let right = if h > 0.0 {[dir[2] / h, 0.0, -dir[0] / h]} else {[1.0, 0.0, 0.0]};
+    let offset = world(right);
+    ([0, 1, 2].map(|i| cam.position[i] + eye_offset * offset[i]), world(dir))
+}
+
+/// Returns cameras of left and right eye, with some distance between eyes.
+///
+/// This is for planar images, see `equirect_stereo_ray` for panoramas.
+pub fn stereo_cameras(cam: &Camera, eye_distance: f32) -> [Camera; 2] {
+    use vecmath::vec3_add as add;
+    use vecmath::vec3_scale as scale;
+
+    let eye = |s: f32| Camera {
+        position: add(cam.position, scale(cam.right, s * 0.5 * eye_distance)),
+        ..cam.clone()
+    };
+    [eye(-1.0), eye(1.0)]
+}
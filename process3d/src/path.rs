@@ -0,0 +1,274 @@
+//! # Path tracing
+//!
+//! Renders final-quality images with Monte Carlo path tracing, e.g. for stills.
+//!
+//! Surfaces use the same scene ray color function as the renderer,
+//! with a `Bsdf` as shader arguments, so one scene description works for both.
+//! The color is the diffuse albedo, which also tints metals and emitted light.
+//!
+//! Each path samples one lobe of the BSDF per bounce, with cosine-weighted diffuse rays.
+//! After some bounces, paths are terminated randomly by Russian roulette,
+//! with survival probability from the path throughput.
+//! Rays that miss the scene get their color from a miss function, e.g. sky.
+//!
+//! Random numbers are seeded per pixel and sample,
+//! so results are deterministic regardless of how rows are split between threads.
+//! Samples accumulate progressively in a `PathFrame`,
+//! where rendering twice with 4 samples gives the same result as rendering once with 8 samples.
+//!
+//! Rays are traced in world coordinates, using the cached broad phase of a `Tracer`.
+
+use crate::{PixelPos, Ray, Rgb, Rgba, Triangle, Vector};
+use crate::cam::{Camera, CameraPerspective};
+use crate::produce::Produce;
+use crate::render::SceneRayColor;
+use crate::trace::{TraceMiss, Tracer};
+
+/// BSDF for path tracing, mixing diffuse and specular reflection.
+///
+/// This is used as shader arguments of the scene ray color function.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bsdf {
+    /// The weight of specular reflection in range `0.0` to `1.0`, where the rest is diffuse.
+    pub specular: f32,
+    /// The roughness of specular reflection, where `0.0` is a perfect mirror.
+    pub roughness: f32,
+    /// Whether specular reflection is tinted by the surface color, as for metals.
+    pub metallic: bool,
+    /// The strength of emitted light, relative to the surface color.
+    pub emission: f32,
+}
+
+impl Default for Bsdf {
+    fn default() -> Bsdf {Bsdf::diffuse()}
+}
+
+impl Bsdf {
+    /// Creates diffuse BSDF.
+    pub fn diffuse() -> Bsdf {
+        Bsdf {specular: 0.0, roughness: 1.0, metallic: false, emission: 0.0}
+    }
+
+    /// Creates BSDF with untinted specular reflection on top of diffuse, e.g. plastic.
+    pub fn specular(specular: f32, roughness: f32) -> Bsdf {
+        Bsdf {specular, roughness, metallic: false, emission: 0.0}
+    }
+
+    /// Creates metal BSDF.
+    pub fn metal(roughness: f32) -> Bsdf {
+        Bsdf {specular: 1.0, roughness, metallic: true, emission: 0.0}
+    }
+
+    /// Creates diffuse BSDF that emits light.
+    pub fn emissive(emission: f32) -> Bsdf {
+        Bsdf {emission, ..Bsdf::diffuse()}
+    }
+
+    /// Samples reflected direction of normalized incoming direction,
+    /// with normal facing against it.
+    ///
+    /// Returns the direction and the throughput weight,
+    /// or `None` if the sampled direction is below the surface.
+    pub fn sample(&self, color: Rgb, dir: Vector, normal: Vector, r: [f32; 3]) -> Option<(Vector, Rgb)> {
+        use crate::bake::hemisphere_dir;
+        use crate::trace::reflect_dir;
+        use vecmath::vec3_dot as dot;
+        use vecmath::vec3_normalized as normalized;
+
+        if r[0] < self.specular {
+            let reflected = reflect_dir(dir, normal);
+            let rough = hemisphere_dir(reflected, [r[1], r[2]]);
+            let t = self.roughness.clamp(0.0, 1.0);
+            let out = normalized([0, 1, 2].map(|i| reflected[i] + (rough[i] - reflected[i]) * t));
+            if dot(out, normal) <= 0.0 {return None};
+
+            Some((out, if self.metallic {color} else {[1.0; 3]}))
+        } else {
+            Some((hemisphere_dir(normal, [r[1], r[2]]), color))
+        }
+    }
+}
+
+/// Path tracing settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathSettings {
+    /// The number of samples per pixel added by each render.
+    pub samples: u32,
+    /// The maximum number of bounces.
+    pub max_bounces: u32,
+    /// The number of bounces before Russian roulette starts.
+    pub roulette_bounces: u32,
+    /// The random seed.
+    pub seed: u64,
+    /// The distance to offset secondary ray origins, to avoid self-intersection.
+    pub bias: f32,
+}
+
+impl PathSettings {
+    /// Creates new settings with some samples per pixel.
+    pub fn new(samples: u32) -> PathSettings {
+        PathSettings {
+            samples,
+            max_bounces: 8,
+            roulette_bounces: 3,
+            seed: 0,
+            bias: 1e-4,
+        }
+    }
+}
+
+/// Accumulates path traced samples progressively.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathFrame {
+    /// The size in pixels.
+    pub size: PixelPos,
+    /// Sums of linear colors, stored row by row from top.
+    pub sum: Vec<Rgba>,
+    /// The number of samples per pixel so far.
+    pub samples: u32,
+}
+
+impl PathFrame {
+    /// Creates an empty frame with some size.
+    pub fn new(size: PixelPos) -> PathFrame {
+        PathFrame {size, sum: vec![[0.0; 4]; (size[0] * size[1]) as usize], samples: 0}
+    }
+
+    /// Removes all samples, e.g. when the camera moves.
+    pub fn clear(&mut self) {
+        for c in &mut self.sum {*c = [0.0; 4]}
+        self.samples = 0;
+    }
+
+    /// Returns the average linear colors, stored row by row from top.
+    pub fn linear(&self) -> Vec<Rgba> {
+        let k = 1.0 / self.samples.max(1) as f32;
+        self.sum.iter().map(|c| c.map(|x| x * k)).collect()
+    }
+
+    /// Writes average colors in sRGB color space to an image.
+    pub fn write(&self, mut pxl: impl FnMut(PixelPos, Rgba<u8>)) {
+        use crate::color::{rgba_gamma_linear_to_srgb, rgba_to_u8};
+
+        let [w, h] = self.size;
+        let linear = self.linear();
+        for y in 0..h {
+            for x in 0..w {
+                pxl([x, y], rgba_to_u8(rgba_gamma_linear_to_srgb(linear[(y * w + x) as usize])));
+            }
+        }
+    }
+}
+
+/// Derives random seed of a sample in a pixel.
+pub fn path_seed(seed: u64, pixel: usize, sample: u32) -> u64 {
+    use crate::math::hash_u64;
+
+    hash_u64(hash_u64(seed ^ hash_u64(pixel as u64)) ^ sample as u64)
+}
+
+/// Path tracer of triangles from a producer.
+pub struct PathTracer<'a, Scene, P: ?Sized> {
+    /// Scene data.
+    pub scene: &'a Scene,
+    /// Traces rays against the producer, in world coordinates.
+    pub tracer: Tracer<'a, P>,
+    /// Gets surface color and BSDF from depth and internal address.
+    pub scene_ray_color: SceneRayColor<'a, Scene, Rgba, Bsdf>,
+    /// Gives linear color of rays that miss the scene.
+    pub miss: TraceMiss<'a>,
+    /// Path tracing settings.
+    pub settings: PathSettings,
+}
+
+impl<'a, Scene, P> PathTracer<'a, Scene, P>
+    where Scene: Sync, P: Produce<Triangle> + Sync + ?Sized
+{
+    /// Creates a new path tracer, building the broad phase of producer.
+    pub fn new(
+        scene: &'a Scene,
+        list: &'a P,
+        scene_ray_color: impl Fn(&Scene, f32, usize) -> (Rgba, Bsdf) + Sync + 'a,
+        miss: impl Fn(Ray) -> Rgba + Sync + 'a,
+        settings: PathSettings,
+    ) -> Self {
+        PathTracer {
+            scene,
+            tracer: Tracer::new(list, settings.max_bounces),
+            scene_ray_color: Box::new(scene_ray_color),
+            miss: Box::new(miss),
+            settings,
+        }
+    }
+
+    /// Traces a path from ray with normalized direction, returning linear color.
+    ///
+    /// Alpha is `1.0` when the ray hits the scene, otherwise the alpha of miss color.
+    pub fn radiance(&self, mut ray: Ray, state: &mut u64) -> Rgba {
+        use crate::math::rand_f32;
+
+        let s = &self.settings;
+        let mut res = [0.0; 3];
+        let mut alpha = 1.0;
+        let mut throughput = [1.0; 3];
+        for bounce in 0..=s.max_bounces {
+            let Some(hit) = self.tracer.hit(ray) else {
+                let c = (self.miss)(ray);
+                for i in 0..3 {res[i] += throughput[i] * c[i]}
+                if bounce == 0 {alpha = c[3]};
+                break;
+            };
+            let Some(internal_offset) = hit.internal_offset else {break};
+
+            let (color, bsdf) = (self.scene_ray_color)(self.scene, hit.depth, internal_offset);
+            let color = [color[0], color[1], color[2]];
+            for i in 0..3 {res[i] += throughput[i] * color[i] * bsdf.emission}
+            if bounce == s.max_bounces {break};
+
+            let r = [rand_f32(state), rand_f32(state), rand_f32(state)];
+            let Some((dir, weight)) = bsdf.sample(color, ray.1, hit.normal, r) else {break};
+            for i in 0..3 {throughput[i] *= weight[i]}
+
+            if bounce >= s.roulette_bounces {
+                let p = throughput[0].max(throughput[1]).max(throughput[2]).min(1.0);
+                if rand_f32(state) >= p {break};
+                for x in &mut throughput {*x /= p}
+            }
+
+            let n = hit.normal;
+            ray = ([0, 1, 2].map(|i| hit.pos[i] + s.bias * n[i]), dir);
+        }
+        [res[0], res[1], res[2], alpha]
+    }
+
+    /// Renders samples from camera into frame, adding to previous samples.
+    ///
+    /// Each pixel gets rays jittered within the pixel, for anti-aliasing.
+    pub fn render(&self, cam: &Camera, persp: &CameraPerspective, frame: &mut PathFrame) {
+        use crate::frustrum::near_dim;
+        use crate::math::rand_f32;
+        use rayon::prelude::*;
+        use vecmath::vec3_normalized as normalized;
+
+        let [w, h] = frame.size;
+        let ndim = near_dim(persp);
+        let start = frame.samples;
+        let samples = self.settings.samples;
+        frame.sum.par_chunks_mut(w.max(1) as usize).enumerate().for_each(|(y, row)| {
+            for (x, sum) in row.iter_mut().enumerate() {
+                let k = y * w as usize + x;
+                for sample in start..start + samples {
+                    let mut state = path_seed(self.settings.seed, k, sample);
+                    let u = (x as f32 + rand_f32(&mut state)) / w as f32 * 2.0 - 1.0;
+                    let v = 1.0 - (y as f32 + rand_f32(&mut state)) / h as f32 * 2.0;
+                    let p = [0.5 * ndim[0] * u, 0.5 * ndim[1] * v, persp.near_clip];
+                    let dir = normalized([0, 1, 2]
+                        .map(|i| p[0] * cam.right[i] + p[1] * cam.up[i] + p[2] * cam.forward[i]));
+                    let c = self.radiance((cam.position, dir), &mut state);
+                    for i in 0..4 {sum[i] += c[i]}
+                }
+            }
+        });
+        frame.samples += samples;
+    }
+}
@@ -0,0 +1,109 @@
+//! # Camera picking
+//!
+//! Picking is used by editors to find out what is under the cursor.
+//!
+//! Instead of rendering the whole image, a mask is computed for the single pixel tile,
+//! such that only the triangles that might be hit by the ray are visited.
+//!
+//! The pixel position is in image coordinates, the same as written by `Renderer`,
+//! where the y-axis points downwards.
+
+use crate::{PixelPos, Ray, Triangle, Vector};
+use crate::cam::{Camera, CameraPerspective};
+use crate::mask::CompressedMasks;
+use crate::produce::{Produce, TransformProducer};
+
+/// Stores the result of picking.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pick {
+    /// The ray depth to the hit.
+    pub depth: f32,
+    /// The index of hit triangle in the virtual list of producer.
+    pub index: usize,
+    /// The internal address of hit triangle in producer.
+    ///
+    /// For example, when the producer generates triangles from voxels,
+    /// this value tells which voxel gets hit.
+    pub internal_offset: Option<usize>,
+}
+
+/// Calculates the pick ray and mask of a pixel in image coordinates.
+///
+/// The ray and the mask are in camera coordinates of the view transformed producer.
+///
+/// Returns `None` if the pixel is outside the image.
+pub fn pick_ray_mask<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    pos: PixelPos,
+    list: &T,
+    masks: &mut CompressedMasks,
+) -> Option<Ray> {
+    use crate::frustrum::near_dim;
+    use crate::ray::ray_dir;
+    use crate::tile::{tile_mask, tile_pos, tile_size};
+
+    if pos[0] >= dim[0] || pos[1] >= dim[1] {return None};
+    // Image coordinates are flipped vertically relative to tile coordinates.
+    let pos = [pos[0], dim[1] - pos[1] - 1];
+    let eye = [0.0; 3];
+    masks.clear();
+    tile_mask(persp, near_dim(persp), tile_pos(dim, pos, 1), tile_size(dim, pos, 1), list, masks);
+    Some((eye, ray_dir(persp, eye, pos, dim)))
+}
+
+/// Picks the closest triangle under a pixel in image coordinates.
+///
+/// `flip_xyz` should be the same as used by `Renderer`.
+///
+/// Returns `None` if nothing was hit or the pixel is outside the image.
+pub fn pick<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    cam: &Camera,
+    flip_xyz: Vector,
+    dim: PixelPos,
+    pos: PixelPos,
+    list: &T,
+) -> Option<Pick> {
+    use crate::ray::ray_triangle_chunk_iter_hit;
+    use crate::render::view_matrix;
+    use crate::triangle::chunk_iter;
+
+    let list = &TransformProducer {matrix: view_matrix(cam, flip_xyz), inner: list};
+    let mut masks = CompressedMasks::new();
+    let ray = pick_ray_mask(persp, dim, pos, list, &mut masks)?;
+    let (depth, index) = ray_triangle_chunk_iter_hit(ray, chunk_iter(list, &masks))?;
+    Some(Pick {depth, index, internal_offset: list.to_internal(index)})
+}
+
+/// Picks every triangle under a pixel in image coordinates.
+///
+/// `flip_xyz` should be the same as used by `Renderer`.
+///
+/// The layers are sorted by depth, from closest to furthest away.
+/// Triangles at the same depth are sorted by index.
+/// Returns no layers if the pixel is outside the image.
+pub fn pick_all<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    cam: &Camera,
+    flip_xyz: Vector,
+    dim: PixelPos,
+    pos: PixelPos,
+    list: &T,
+) -> Vec<Pick> {
+    use crate::ray::ray_triangle_chunk_hits;
+    use crate::render::view_matrix;
+    use crate::triangle::chunk_iter;
+
+    let list = &TransformProducer {matrix: view_matrix(cam, flip_xyz), inner: list};
+    let mut masks = CompressedMasks::new();
+    let Some(ray) = pick_ray_mask(persp, dim, pos, list, &mut masks) else {return vec![]};
+    let mut hits = vec![];
+    for (off, (chunk, mask)) in chunk_iter(list, &masks) {
+        ray_triangle_chunk_hits(ray, &chunk, mask, off, &mut hits);
+    }
+    hits.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    hits.into_iter()
+        .map(|(depth, index)| Pick {depth, index, internal_offset: list.to_internal(index)})
+        .collect()
+}
@@ -0,0 +1,319 @@
+//! # PLY format
+//!
+//! Reads triangles into any consumer and writes triangles from any producer,
+//! using either ASCII or binary PLY.
+//!
+//! Materials map to PLY vertex colors.
+//! When reading, the material of a face is the face color if there is one,
+//! otherwise the color of the first vertex of the face.
+//! Faces with more than 3 vertices are split into a triangle fan.
+//! Elements other than vertices and faces are skipped.
+
+use std::io::{self, Read, Write};
+
+use crate::{Point, Rgba, Triangle};
+use crate::consume::Consumer;
+use crate::produce::Produce;
+
+fn invalid(msg: String) -> io::Error {io::Error::new(io::ErrorKind::InvalidData, msg)}
+
+/// Converts list length or vertex index, which must be a non-negative integer.
+fn ply_index(x: f64) -> io::Result<usize> {
+    if x >= 0.0 && x.fract() == 0.0 {Ok(x as usize)}
+    else {Err(invalid(format!("Expected non-negative integer, found {}", x)))}
+}
+
+/// PLY data format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlyFormat {
+    /// Human readable text.
+    Ascii,
+    /// Binary with little endian byte order.
+    BinaryLittleEndian,
+    /// Binary with big endian byte order.
+    BinaryBigEndian,
+}
+
+/// PLY scalar type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PlyType {I8, U8, I16, U16, I32, U32, F32, F64}
+
+impl PlyType {
+    fn parse(s: &str) -> io::Result<PlyType> {
+        use PlyType::*;
+        Ok(match s {
+            "char" | "int8" => I8,
+            "uchar" | "uint8" => U8,
+            "short" | "int16" => I16,
+            "ushort" | "uint16" => U16,
+            "int" | "int32" => I32,
+            "uint" | "uint32" => U32,
+            "float" | "float32" => F32,
+            "double" | "float64" => F64,
+            _ => return Err(invalid(format!("Unknown PLY type `{}`", s))),
+        })
+    }
+
+    fn size(self) -> usize {
+        use PlyType::*;
+        match self {
+            I8 | U8 => 1,
+            I16 | U16 => 2,
+            I32 | U32 | F32 => 4,
+            F64 => 8,
+        }
+    }
+}
+
+/// PLY property, where `list` is the type of list length.
+struct PlyProperty {
+    name: String,
+    ty: PlyType,
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    props: Vec<PlyProperty>,
+}
+
+/// Reads scalar values from the body of a PLY file.
+struct PlyValues<'a> {
+    format: PlyFormat,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl PlyValues<'_> {
+    fn read(&mut self, ty: PlyType) -> io::Result<f64> {
+        use PlyType::*;
+
+        if let PlyFormat::Ascii = self.format {
+            let data = &self.data[self.pos..];
+            let start = data.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(data.len());
+            let len = data[start..].iter().position(|c| c.is_ascii_whitespace())
+                .unwrap_or(data.len() - start);
+            self.pos += start + len;
+            return std::str::from_utf8(&data[start..start + len]).ok()
+                .and_then(|w| w.parse().ok())
+                .ok_or_else(|| invalid("Expected number in ASCII PLY".into()));
+        }
+
+        let n = ty.size();
+        if self.pos + n > self.data.len() {
+            return Err(invalid("Unexpected end of binary PLY".into()));
+        }
+        let mut buf = [0; 8];
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        if let PlyFormat::BinaryBigEndian = self.format {buf[..n].reverse()};
+        Ok(match ty {
+            I8 => buf[0] as i8 as f64,
+            U8 => buf[0] as f64,
+            I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            F64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+/// Converts a color channel to `u8`, where floating point channels are in range `0.0` to `1.0`.
+fn ply_channel(ty: PlyType, x: f64) -> u8 {
+    match ty {
+        PlyType::F32 | PlyType::F64 => (x.clamp(0.0, 1.0) * 255.0).round() as u8,
+        _ => x.clamp(0.0, 255.0) as u8,
+    }
+}
+
+/// Gets the color channel index of property name.
+fn ply_channel_index(name: &str) -> Option<usize> {
+    match name {
+        "red" | "r" => Some(0),
+        "green" | "g" => Some(1),
+        "blue" | "b" => Some(2),
+        "alpha" | "a" => Some(3),
+        _ => None,
+    }
+}
+
+/// Reads triangles from ASCII or binary PLY format into consumer.
+///
+/// The material is the face color, or the color of the first vertex of the face.
+/// Colors default to opaque white.
+pub fn ply_read<R, C>(mut r: R, consumer: &mut C) -> io::Result<()>
+    where R: Read, C: Consumer<(Triangle, Rgba<u8>)>
+{
+    let mut data = vec![];
+    r.read_to_end(&mut data)?;
+
+    // Parse header.
+    let end = b"end_header";
+    let header_end = data.windows(end.len()).position(|w| w == end)
+        .ok_or_else(|| invalid("Expected `end_header` in PLY".into()))?;
+    let body_start = data[header_end..].iter().position(|&c| c == b'\n')
+        .map(|i| header_end + i + 1).unwrap_or(data.len());
+    let header = std::str::from_utf8(&data[..header_end])
+        .map_err(|_| invalid("Expected UTF-8 in PLY header".into()))?;
+    let mut lines = header.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err(invalid("Expected `ply` at start of file".into()));
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", f, ..] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(invalid(format!("Unknown PLY format `{}`", f))),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| invalid(format!("Invalid count `{}`", count)))?;
+                elements.push(PlyElement {name: name.to_string(), count, props: vec![]});
+            }
+            ["property", "list", len_ty, ty, name] => {
+                let el = elements.last_mut().ok_or_else(|| invalid("Expected element".into()))?;
+                el.props.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)?,
+                    list: Some(PlyType::parse(len_ty)?),
+                });
+            }
+            ["property", ty, name] => {
+                let el = elements.last_mut().ok_or_else(|| invalid("Expected element".into()))?;
+                el.props.push(PlyProperty {name: name.to_string(), ty: PlyType::parse(ty)?, list: None});
+            }
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid("Expected PLY format".into()))?;
+
+    // Parse body.
+    let f = consumer.consumer();
+    let mut values = PlyValues {format, data: &data[body_start..], pos: 0};
+    let mut vertices: Vec<(Point, Rgba<u8>)> = vec![];
+    let mut row: Vec<f64> = vec![];
+    let mut face: Vec<usize> = vec![];
+    for el in &elements {
+        for _ in 0..el.count {
+            let mut pos = [0.0; 3];
+            let mut color: Option<Rgba<u8>> = None;
+            face.clear();
+            for prop in &el.props {
+                row.clear();
+                if let Some(len_ty) = prop.list {
+                    let n = ply_index(values.read(len_ty)?)?;
+                    for _ in 0..n {row.push(values.read(prop.ty)?)}
+                } else {
+                    row.push(values.read(prop.ty)?);
+                }
+
+                match (&*el.name, &*prop.name) {
+                    ("vertex", "x") => pos[0] = row[0] as f32,
+                    ("vertex", "y") => pos[1] = row[0] as f32,
+                    ("vertex", "z") => pos[2] = row[0] as f32,
+                    ("face", "vertex_indices" | "vertex_index") => {
+                        for &i in &row {face.push(ply_index(i)?)}
+                    }
+                    (_, name) => if let Some(ch) = ply_channel_index(name) {
+                        let c = color.get_or_insert([255; 4]);
+                        c[ch] = ply_channel(prop.ty, row[0]);
+                    }
+                }
+            }
+
+            match &*el.name {
+                "vertex" => vertices.push((pos, color.unwrap_or([255; 4]))),
+                "face" => {
+                    if face.len() < 3 {return Err(invalid("Expected at least 3 vertices".into()))};
+                    let mut p = [[0.0; 3]; 3];
+                    let mut mat = color;
+                    for (k, &ind) in face.iter().enumerate() {
+                        let (q, c) = *vertices.get(ind).ok_or_else(||
+                            invalid(format!("Vertex index {} out of bounds", ind)))?;
+                        if mat.is_none() {mat = Some(c)};
+                        p[k.min(2)] = q;
+                        if k >= 2 {
+                            f(consumer, ((p[0], p[1], p[2]), mat.unwrap()));
+                            p[1] = p[2];
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes triangles from producer in PLY format with vertex colors.
+///
+/// The color is computed from the internal address of each triangle.
+/// Each triangle gets 3 vertices of its own.
+pub fn ply_write<W, T>(
+    w: &mut W,
+    list: &T,
+    color: impl Fn(Option<usize>) -> Rgba<u8>,
+    format: PlyFormat,
+) -> io::Result<()>
+    where W: Write, T: Produce<Triangle> + ?Sized
+{
+    use crate::produce::produce_iter;
+
+    let n = list.virtual_length();
+    let format_name = match format {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+        PlyFormat::BinaryBigEndian => "binary_big_endian",
+    };
+    writeln!(w, "ply")?;
+    writeln!(w, "format {} 1.0", format_name)?;
+    writeln!(w, "element vertex {}", n * 3)?;
+    for name in ["x", "y", "z"] {writeln!(w, "property float {}", name)?}
+    for name in ["red", "green", "blue", "alpha"] {writeln!(w, "property uchar {}", name)?}
+    writeln!(w, "element face {}", n)?;
+    writeln!(w, "property list uchar int vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for (off, (a, b, c)) in produce_iter(list) {
+        let col = color(list.to_internal(off));
+        for p in [a, b, c] {
+            match format {
+                PlyFormat::Ascii => writeln!(w, "{} {} {} {} {} {} {}",
+                    p[0], p[1], p[2], col[0], col[1], col[2], col[3])?,
+                PlyFormat::BinaryLittleEndian => {
+                    for x in p {w.write_all(&x.to_le_bytes())?}
+                    w.write_all(&col)?;
+                }
+                PlyFormat::BinaryBigEndian => {
+                    for x in p {w.write_all(&x.to_be_bytes())?}
+                    w.write_all(&col)?;
+                }
+            }
+        }
+    }
+    for i in 0..n {
+        let i = i * 3;
+        let ind = [i, i + 1, i + 2].map(|k| k as i32);
+        match format {
+            PlyFormat::Ascii => writeln!(w, "3 {} {} {}", ind[0], ind[1], ind[2])?,
+            PlyFormat::BinaryLittleEndian => {
+                w.write_all(&[3])?;
+                for k in ind {w.write_all(&k.to_le_bytes())?}
+            }
+            PlyFormat::BinaryBigEndian => {
+                w.write_all(&[3])?;
+                for k in ind {w.write_all(&k.to_be_bytes())?}
+            }
+        }
+    }
+    Ok(())
+}
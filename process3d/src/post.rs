@@ -0,0 +1,269 @@
+//! # Post-processing
+//!
+//! Image operations on frames of linear colors, before conversion to sRGB.
+//!
+//! Frames are stored row by row, with the size of the image in pixels.
+//! Post-processing effects are applied in order, where each effect reads
+//! the output of the previous one.
+//!
+//! Every output pixel is computed only from the previous frame,
+//! so results are deterministic regardless of how rows are split between threads.
+//! Positions are in frame coordinates, which are flipped vertically relative to image coordinates,
+//! but all effects are symmetric, so this makes no difference.
+
+use crate::{PixelPos, Rgb, Rgba};
+
+/// A 3D color lookup table for color grading.
+///
+/// The table is indexed by linear color, clamped to the range `0.0` to `1.0`,
+/// and interpolated trilinearly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorLut {
+    /// The number of entries per color channel.
+    size: usize,
+    /// Colors stored with red changing fastest, then green, then blue.
+    data: Vec<Rgb>,
+}
+
+impl ColorLut {
+    /// Creates a lookup table from colors stored with red changing fastest, then green, then blue.
+    ///
+    /// Returns `None` if the size is less than 2 or the data does not have `size³` colors.
+    pub fn new(size: usize, data: Vec<Rgb>) -> Option<ColorLut> {
+        if size < 2 || size.checked_pow(3) != Some(data.len()) {return None};
+        Some(ColorLut {size, data})
+    }
+
+    /// Creates a lookup table that does not change colors.
+    ///
+    /// Returns `None` if the size is less than 2.
+    pub fn identity(size: usize) -> Option<ColorLut> {
+        if size < 2 {return None};
+        let s = (size - 1) as f32;
+        let data = (0..size * size * size)
+            .map(|k| [
+                (k % size) as f32 / s,
+                (k / size % size) as f32 / s,
+                (k / (size * size)) as f32 / s,
+            ])
+            .collect();
+        ColorLut::new(size, data)
+    }
+
+    /// Gets the number of entries per color channel.
+    pub fn size(&self) -> usize {self.size}
+
+    /// Gets the colors stored with red changing fastest, then green, then blue.
+    pub fn data(&self) -> &[Rgb] {&self.data}
+
+    /// Looks up color with trilinear interpolation.
+    pub fn lookup(&self, c: Rgb) -> Rgb {
+        let n = self.size;
+        let s = (n - 1) as f32;
+        let mut i0 = [0; 3];
+        let mut t = [0.0; 3];
+        for k in 0..3 {
+            let x = c[k].clamp(0.0, 1.0) * s;
+            i0[k] = (x.floor() as usize).min(n - 2);
+            t[k] = x - i0[k] as f32;
+        }
+        let mut res = [0.0; 3];
+        for corner in 0..8 {
+            let d = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let w: f32 = (0..3).map(|k| if d[k] == 1 {t[k]} else {1.0 - t[k]}).product();
+            let ind = (i0[0] + d[0]) + (i0[1] + d[1]) * n + (i0[2] + d[2]) * n * n;
+            let v = self.data[ind];
+            for k in 0..3 {res[k] += w * v[k]}
+        }
+        res
+    }
+}
+
+/// Post-processing effect.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostEffect {
+    /// Gaussian blur with standard deviation in pixels.
+    GaussianBlur {
+        /// The standard deviation in pixels.
+        sigma: f32,
+    },
+    /// Adds blurred bright colors on top of the frame.
+    Bloom {
+        /// Colors channels above this value contribute to bloom.
+        threshold: f32,
+        /// The standard deviation of bloom blur in pixels.
+        sigma: f32,
+        /// The bloom intensity.
+        intensity: f32,
+    },
+    /// Darkens the frame toward the corners.
+    Vignette {
+        /// The amount of darkening in the corners, in range `0.0` to `1.0`.
+        strength: f32,
+        /// The relative distance from center where darkening starts,
+        /// where `1.0` is the distance to the corners.
+        radius: f32,
+    },
+    /// Color grading using a lookup table.
+    ColorGrade(ColorLut),
+    /// Sharpens the frame using unsharp masking.
+    Sharpen {
+        /// The amount of sharpening.
+        amount: f32,
+        /// The standard deviation of blur used for unsharp masking.
+        sigma: f32,
+    },
+    /// Shifts red and blue channels radially, as from a lens.
+    ChromaticAberration {
+        /// The shift in pixels at the corners.
+        ///
+        /// Red is shifted outwards and blue is shifted inwards.
+        shift: f32,
+    },
+}
+
+/// Applies post-processing effects to frame of linear colors, in order.
+pub fn post_process(dim: PixelPos, frame: &mut [Rgba], effects: &[PostEffect]) {
+    for effect in effects {post_effect(dim, frame, effect)}
+}
+
+/// Applies a post-processing effect to frame of linear colors.
+pub fn post_effect(dim: PixelPos, frame: &mut [Rgba], effect: &PostEffect) {
+    use PostEffect::*;
+
+    let [w, h] = dim;
+    let center = [0.5 * w as f32, 0.5 * h as f32];
+    let half_diag = (center[0] * center[0] + center[1] * center[1]).sqrt().max(1.0);
+    match *effect {
+        GaussianBlur {sigma} => {
+            let blurred = gaussian_blur(dim, frame, sigma);
+            frame.copy_from_slice(&blurred);
+        }
+        Bloom {threshold, sigma, intensity} => {
+            let bright: Vec<Rgba> = frame.iter()
+                .map(|c| [
+                    (c[0] - threshold).max(0.0),
+                    (c[1] - threshold).max(0.0),
+                    (c[2] - threshold).max(0.0),
+                    0.0,
+                ])
+                .collect();
+            let bloom = gaussian_blur(dim, &bright, sigma);
+            post_map(dim, frame, |x, y, c| {
+                let b = bloom[(y * w + x) as usize];
+                [c[0] + intensity * b[0], c[1] + intensity * b[1], c[2] + intensity * b[2], c[3]]
+            });
+        }
+        Vignette {strength, radius} => {
+            post_map(dim, frame, |x, y, c| {
+                let dx = x as f32 + 0.5 - center[0];
+                let dy = y as f32 + 0.5 - center[1];
+                let r = (dx * dx + dy * dy).sqrt() / half_diag;
+                let t = ((r - radius) / (1.0 - radius).max(f32::EPSILON)).clamp(0.0, 1.0);
+                let f = 1.0 - strength * t * t;
+                [c[0] * f, c[1] * f, c[2] * f, c[3]]
+            });
+        }
+        ColorGrade(ref lut) => {
+            post_map(dim, frame, |_, _, c| {
+                let [r, g, b] = lut.lookup([c[0], c[1], c[2]]);
+                [r, g, b, c[3]]
+            });
+        }
+        Sharpen {amount, sigma} => {
+            let blurred = gaussian_blur(dim, frame, sigma);
+            post_map(dim, frame, |x, y, c| {
+                let b = blurred[(y * w + x) as usize];
+                let mut res = c;
+                for i in 0..3 {res[i] = c[i] + amount * (c[i] - b[i])}
+                res
+            });
+        }
+        ChromaticAberration {shift} => {
+            let src = frame.to_vec();
+            let k = shift / half_diag;
+            post_map(dim, frame, |x, y, c| {
+                let dx = x as f32 + 0.5 - center[0];
+                let dy = y as f32 + 0.5 - center[1];
+                let r = frame_sample(dim, &src, [center[0] + dx * (1.0 - k), center[1] + dy * (1.0 - k)]);
+                let b = frame_sample(dim, &src, [center[0] + dx * (1.0 + k), center[1] + dy * (1.0 + k)]);
+                [r[0], c[1], b[2], c[3]]
+            });
+        }
+    }
+}
+
+/// Maps every pixel of frame, in parallel rows.
+///
+/// The function gets the pixel position and the previous color.
+pub fn post_map(dim: PixelPos, frame: &mut [Rgba], f: impl Fn(u32, u32, Rgba) -> Rgba + Sync) {
+    use rayon::prelude::*;
+
+    let w = dim[0] as usize;
+    if w == 0 {return};
+    frame.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+        for (x, c) in row.iter_mut().enumerate() {*c = f(x as u32, y as u32, *c)}
+    });
+}
+
+/// Samples frame at a position with bilinear interpolation,
+/// clamping to the edge of the frame.
+///
+/// Pixel centers are at half-integer positions.
+pub fn frame_sample(dim: PixelPos, frame: &[Rgba], pos: [f32; 2]) -> Rgba {
+    let [w, h] = dim;
+    let x = (pos[0] - 0.5).clamp(0.0, (w - 1) as f32);
+    let y = (pos[1] - 0.5).clamp(0.0, (h - 1) as f32);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let px = |x: u32, y: u32| frame[(y * w + x) as usize];
+    let (a, b, c, d) = (px(x0, y0), px(x1, y0), px(x0, y1), px(x1, y1));
+    let mut res = [0.0; 4];
+    for i in 0..4 {
+        let top = a[i] + (b[i] - a[i]) * tx;
+        let bottom = c[i] + (d[i] - c[i]) * tx;
+        res[i] = top + (bottom - top) * ty;
+    }
+    res
+}
+
+/// Calculates normalized gaussian kernel with standard deviation,
+/// with radius of 3 standard deviations.
+pub fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 {return vec![1.0]};
+
+    let r = (3.0 * sigma).ceil() as i32;
+    let kernel: Vec<f32> = (-r..=r)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|x| x / sum).collect()
+}
+
+/// Blurs frame with gaussian blur, clamping to the edge of the frame.
+///
+/// The blur is separable, first horizontal then vertical.
+pub fn gaussian_blur(dim: PixelPos, frame: &[Rgba], sigma: f32) -> Vec<Rgba> {
+    let [w, h] = dim;
+    let kernel = gaussian_kernel(sigma);
+    let r = (kernel.len() / 2) as i64;
+    let blur = |src: &[Rgba], dst: &mut [Rgba], (dx, dy): (i64, i64)| {
+        post_map(dim, dst, |x, y, _| {
+            let mut res = [0.0; 4];
+            for (k, &weight) in kernel.iter().enumerate() {
+                let o = k as i64 - r;
+                let sx = (x as i64 + o * dx).clamp(0, w as i64 - 1);
+                let sy = (y as i64 + o * dy).clamp(0, h as i64 - 1);
+                let c = src[(sy * w as i64 + sx) as usize];
+                for i in 0..4 {res[i] += weight * c[i]}
+            }
+            res
+        });
+    };
+    let mut tmp = vec![[0.0; 4]; frame.len()];
+    blur(frame, &mut tmp, (1, 0));
+    let mut res = vec![[0.0; 4]; frame.len()];
+    blur(&tmp, &mut res, (0, 1));
+    res
+}
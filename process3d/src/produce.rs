@@ -1,6 +1,7 @@
 //! # Produce Pattern
 
 use crate::{Aabb, Chunk, Cube, Matrix4, Point, Quad, Triangle};
+use crate::mask::CompressedMasks;
 
 /// Implemented by virtual lists that produces chunks of data.
 pub trait Produce<T> {
@@ -20,6 +21,20 @@ pub fn init_chunk_mask(len: usize, offset: usize) -> u64 {
     1_u64.checked_shl(n as u32).unwrap_or(0).wrapping_sub(1)
 }
 
+/// Iterates through all items in a virtual list, one chunk at a time.
+///
+/// Provides the offset of each item in the virtual list.
+pub fn produce_iter<'a, T, P>(list: &'a P) -> impl Iterator<Item = (usize, T)> + 'a
+    where T: Copy + 'a, P: Produce<T> + ?Sized
+{
+    let n = list.virtual_length();
+    (0..n).step_by(64).flat_map(move |off| {
+        let chunk = list.produce(off);
+        let m = (n - off).min(64);
+        (0..m).map(move |i| (off + i, chunk[i]))
+    })
+}
+
 /// Transforms chunk of triangles using a matrix.
 pub struct TransformProducer<'a, T: ?Sized> {
     /// The matrix transform.
@@ -45,6 +60,266 @@ impl<'a, T> Produce<Triangle> for TransformProducer<'a, T>
     }
 }
 
+/// Concatenates multiple producers into a single virtual list.
+///
+/// Each producer is tagged, e.g. with a material,
+/// such that scenes can be assembled from reusable parts.
+///
+/// The internal address space of each producer is offset by
+/// the start of the producer in the virtual list.
+/// This keeps internal addresses distinguishable per producer,
+/// as long as internal addresses are less than the virtual length of each producer.
+/// Use `source` to convert back to the internal address of the producer.
+pub struct ConcatProducer<'a, T: ?Sized, Tag = ()> {
+    /// The inner producers with tags.
+    sources: Vec<(&'a T, Tag)>,
+    /// The start of each producer in the virtual list.
+    starts: Vec<usize>,
+    /// The total length of the virtual list.
+    len: usize,
+}
+
+impl<'a, T: ?Sized, Tag> ConcatProducer<'a, T, Tag> {
+    /// Creates a new concatenation of producers with tags.
+    pub fn new(sources: Vec<(&'a T, Tag)>) -> Self
+        where T: Produce<Triangle>
+    {
+        let mut starts = Vec::with_capacity(sources.len());
+        let mut len = 0;
+        for (inner, _) in &sources {
+            starts.push(len);
+            len += inner.virtual_length();
+        }
+        ConcatProducer {sources, starts, len}
+    }
+
+    /// Gets the producers with tags.
+    pub fn sources(&self) -> &[(&'a T, Tag)] {&self.sources}
+
+    /// Gets the index of producer that contains an offset,
+    /// either in the virtual list or in the internal address space.
+    ///
+    /// Returns `None` if there are no producers.
+    pub fn source_index(&self, offset: usize) -> Option<usize> {
+        self.starts.partition_point(|&start| start <= offset).checked_sub(1)
+    }
+
+    /// Converts from internal address to the producer index
+    /// and the internal address of the producer.
+    pub fn source(&self, internal: usize) -> Option<(usize, usize)> {
+        let k = self.source_index(internal)?;
+        Some((k, internal - self.starts[k]))
+    }
+
+    /// Gets the tag of the producer that an internal address belongs to.
+    pub fn tag(&self, internal: usize) -> Option<&Tag> {
+        Some(&self.sources[self.source_index(internal)?].1)
+    }
+}
+
+impl<'a, T, Tag> Produce<Triangle> for ConcatProducer<'a, T, Tag>
+    where T: Produce<Triangle> + ?Sized
+{
+    #[inline(always)]
+    fn virtual_length(&self) -> usize {self.len}
+    fn produce(&self, offset: usize) -> Chunk<Triangle> {
+        let mut chunk = [Default::default(); 64];
+        let mut i = 0;
+        let mut offset = offset;
+        while i < 64 && offset < self.len {
+            let Some((k, local)) = self.source(offset) else {break};
+            let (inner, _) = &self.sources[k];
+            let n = (inner.virtual_length() - local).min(64 - i);
+            let inner_chunk = inner.produce(local);
+            chunk[i..i + n].copy_from_slice(&inner_chunk[..n]);
+            i += n;
+            offset += n;
+        }
+        chunk
+    }
+    fn to_internal(&self, offset: usize) -> Option<usize> {
+        if offset >= self.len {return None};
+        let (k, local) = self.source(offset)?;
+        self.sources[k].0.to_internal(local).map(|ind| ind + self.starts[k])
+    }
+}
+
+/// Produces instances of a triangle mesh, one per transform.
+///
+/// Instances are stored after each other in the virtual list.
+///
+/// The internal address space of each instance is offset by
+/// the start of the instance in the virtual list.
+/// Use `instance` to convert back to the internal address of the mesh.
+pub struct InstancedProducer<'a, T: ?Sized> {
+    /// The matrix transforms, one per instance.
+    ///
+    /// This transform is row-major, which is standard mathematical notation.
+    pub transforms: &'a [Matrix4],
+    /// The inner producer.
+    pub inner: &'a T,
+}
+
+impl<'a, T> InstancedProducer<'a, T>
+    where T: Produce<Triangle> + ?Sized
+{
+    /// Converts from internal address to instance index
+    /// and the internal address of the mesh.
+    ///
+    /// Returns `None` if the mesh is empty.
+    pub fn instance(&self, internal: usize) -> Option<(usize, usize)> {
+        let n = self.inner.virtual_length();
+        Some((internal.checked_div(n)?, internal % n))
+    }
+}
+
+impl<'a, T> Produce<Triangle> for InstancedProducer<'a, T>
+    where T: Produce<Triangle> + ?Sized
+{
+    #[inline(always)]
+    fn virtual_length(&self) -> usize {
+        self.inner.virtual_length() * self.transforms.len()
+    }
+    fn produce(&self, offset: usize) -> Chunk<Triangle> {
+        use crate::math::transform_triangle;
+
+        let n = self.inner.virtual_length();
+        let len = n * self.transforms.len();
+        let mut chunk = [Default::default(); 64];
+        let mut i = 0;
+        let mut offset = offset;
+        while i < 64 && offset < len {
+            let mat = &self.transforms[offset / n];
+            let local = offset % n;
+            let m = (n - local).min(64 - i);
+            let inner_chunk = self.inner.produce(local);
+            for (dst, &src) in chunk[i..i + m].iter_mut().zip(&inner_chunk[..m]) {
+                *dst = transform_triangle(mat, src);
+            }
+            i += m;
+            offset += m;
+        }
+        chunk
+    }
+    fn to_internal(&self, offset: usize) -> Option<usize> {
+        if offset >= self.virtual_length() {return None};
+        let (_, local) = self.instance(offset)?;
+        self.inner.to_internal(local).map(|ind| ind + offset - local)
+    }
+}
+
+/// Filters a producer using compressed masks over its virtual list.
+///
+/// Only the items with enabled bits are produced, in the same order.
+/// This can be used to e.g. render a selection or hide objects,
+/// without changing the internal address space.
+pub struct FilterProducer<'a, T: ?Sized> {
+    /// The inner producer.
+    inner: &'a T,
+    /// The offsets in the inner virtual list that are kept.
+    kept: Vec<usize>,
+}
+
+impl<'a, T: ?Sized> FilterProducer<'a, T> {
+    /// Creates a new filter of producer using compressed masks.
+    ///
+    /// Bits outside the virtual list of the producer are ignored.
+    pub fn new(inner: &'a T, masks: &CompressedMasks) -> Self
+        where T: Produce<Triangle>
+    {
+        let len = inner.virtual_length();
+        let mut kept = Vec::with_capacity(masks.count_ones() as usize);
+        for (i, mut w) in masks.iter() {
+            while w != 0 {
+                let off = i * 64 + w.trailing_zeros() as usize;
+                w &= w - 1;
+                if off < len {kept.push(off)};
+            }
+        }
+        FilterProducer {inner, kept}
+    }
+
+    /// Converts from the virtual list to the virtual list of the inner producer.
+    pub fn to_inner(&self, offset: usize) -> Option<usize> {
+        self.kept.get(offset).copied()
+    }
+}
+
+impl<'a, T> Produce<Triangle> for FilterProducer<'a, T>
+    where T: Produce<Triangle> + ?Sized
+{
+    #[inline(always)]
+    fn virtual_length(&self) -> usize {self.kept.len()}
+    fn produce(&self, offset: usize) -> Chunk<Triangle> {
+        let mut chunk = [Default::default(); 64];
+        let mut cache: Option<(usize, Chunk<Triangle>)> = None;
+        for (dst, &off) in chunk.iter_mut().zip(self.kept.iter().skip(offset)) {
+            let start = off / 64 * 64;
+            let inner_chunk = match &cache {
+                Some((cached, inner_chunk)) if *cached == start => inner_chunk,
+                _ => &cache.insert((start, self.inner.produce(start))).1,
+            };
+            *dst = inner_chunk[off - start];
+        }
+        chunk
+    }
+    fn to_internal(&self, offset: usize) -> Option<usize> {
+        self.inner.to_internal(*self.kept.get(offset)?)
+    }
+}
+
+/// Reorders a producer using a permutation of its virtual list.
+///
+/// The internal address space is unchanged, so internal addresses map back
+/// to the original order.
+/// Since chunks gather items from anywhere in the inner producer,
+/// collect the triangles into a new list when rendering many frames.
+pub struct ReorderProducer<'a, T: ?Sized> {
+    /// The inner producer.
+    inner: &'a T,
+    /// The offset in the inner virtual list of each item.
+    order: Vec<usize>,
+}
+
+impl<'a, T: ?Sized> ReorderProducer<'a, T> {
+    /// Creates a new reordering of producer,
+    /// where each item is an offset in the inner virtual list.
+    pub fn new(inner: &'a T, order: Vec<usize>) -> Self {
+        ReorderProducer {inner, order}
+    }
+
+    /// Gets the offset in the inner virtual list of each item.
+    pub fn order(&self) -> &[usize] {&self.order}
+
+    /// Converts from the virtual list to the virtual list of the inner producer.
+    pub fn to_inner(&self, offset: usize) -> Option<usize> {
+        self.order.get(offset).copied()
+    }
+}
+
+impl<'a, T> Produce<Triangle> for ReorderProducer<'a, T>
+    where T: Produce<Triangle> + ?Sized
+{
+    #[inline(always)]
+    fn virtual_length(&self) -> usize {self.order.len()}
+    fn produce(&self, offset: usize) -> Chunk<Triangle> {
+        let mut chunk = [Default::default(); 64];
+        let mut cache: Option<(usize, Chunk<Triangle>)> = None;
+        for (dst, &off) in chunk.iter_mut().zip(self.order.iter().skip(offset)) {
+            let start = off / 64 * 64;
+            let inner_chunk = match &cache {
+                Some((cached, inner_chunk)) if *cached == start => inner_chunk,
+                _ => &cache.insert((start, self.inner.produce(start))).1,
+            };
+            *dst = inner_chunk[off - start];
+        }
+        chunk
+    }
+    fn to_internal(&self, offset: usize) -> Option<usize> {
+        self.inner.to_internal(*self.order.get(offset)?)
+    }
+}
+
 impl<T: Default + Copy> Produce<T> for [T] {
     #[inline(always)]
     fn virtual_length(&self) -> usize {self.len()}
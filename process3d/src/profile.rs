@@ -1,8 +1,14 @@
 //! # Performance profiling
 
 use crate::mask::CompressedMasks;
+use crate::tile::ExactMaskStats;
 use crate::PixelPos;
 
+/// Profile data used when profiling is disabled.
+///
+/// This is an empty array, such that `&mut []` can be used without allocating.
+pub type NoProfile = [(); 0];
+
 /// Data that is sent to performance profiler after pre-processing.
 pub struct ProfileCompressData<'a> {
     /// Tile size.
@@ -11,6 +17,8 @@ pub struct ProfileCompressData<'a> {
     pub grid: PixelPos,
     /// Compressed masks per render tile.
     pub compr_masks: &'a [CompressedMasks],
+    /// Statistics of exact masks (`None` if exact masks are disabled).
+    pub exact_mask_stats: Option<ExactMaskStats>,
 }
 
 /// Returns the amount of seconds since UNIX EPOCH.
@@ -0,0 +1,172 @@
+//! # Ray queries
+//!
+//! Ray queries are used outside camera rendering,
+//! e.g. for line of sight, placement snapping or collision probes.
+//!
+//! A ray query caches an AABB per triangle chunk of a producer,
+//! plus a shallow hierarchy of AABBs over groups of chunks.
+//! This is used to skip chunks that can not be hit by the ray.
+//!
+//! The cached AABBs are only valid as long as the producer does not change.
+//! When the producer changes, the ray query must be rebuilt.
+//!
+//! The AABBs can also be used to skip whole chunks when computing tile masks.
+//! The ray query is built once in world coordinates,
+//! while tile frustum planes are transformed into world coordinates per frame.
+//! See `QueryView` and `tile::tile_mask_with_query`.
+
+use crate::{Aabb, Matrix4, Ray, RayHit, Triangle};
+use crate::produce::Produce;
+
+/// The number of chunks per group in the hierarchy.
+pub const GROUP_SIZE: usize = 64;
+
+/// Stores cached AABBs of triangle chunks in a producer.
+#[derive(Clone, Debug)]
+pub struct RayQuery {
+    /// The AABB per chunk of 64 triangles.
+    ///
+    /// Is `None` when the chunk is empty.
+    pub chunks: Vec<Option<Aabb>>,
+    /// The AABB per group of `GROUP_SIZE` chunks.
+    ///
+    /// Is `None` when all chunks in the group are empty.
+    pub groups: Vec<Option<Aabb>>,
+}
+
+/// A ray query in world coordinates, seen from a camera.
+///
+/// Used to compute tile masks over the view transformed producer,
+/// without rebuilding the ray query when the camera moves.
+#[derive(Copy, Clone, Debug)]
+pub struct QueryView<'a> {
+    /// The ray query, built over the producer in world coordinates.
+    pub query: &'a RayQuery,
+    /// The view matrix that transforms the producer into camera coordinates.
+    pub view: Matrix4,
+}
+
+/// Grows AABB a little bit, to be conservative about rounding errors
+/// in ray and frustum intersection tests.
+fn aabb_pad((mi, ma): Aabb) -> Aabb {
+    let s = mi.iter().chain(ma.iter()).fold(1.0_f32, |s, x| s.max(x.abs()));
+    let e = s * 1e-5;
+    (
+        [mi[0] - e, mi[1] - e, mi[2] - e],
+        [ma[0] + e, ma[1] + e, ma[2] + e],
+    )
+}
+
+impl RayQuery {
+    /// Builds a ray query over a producer.
+    pub fn new<T: Produce<Triangle> + ?Sized>(list: &T) -> RayQuery {
+        use crate::triangle::{triangle_chunk, triangle_chunk_aabb};
+
+        let n = list.virtual_length();
+        RayQuery::from_chunk_aabbs((0..n).step_by(64).map(|off| {
+            let (chunk, mask) = triangle_chunk(list, off);
+            triangle_chunk_aabb(&chunk, mask)
+        }))
+    }
+
+    /// Builds a ray query from the AABB per chunk of 64 triangles,
+    /// e.g. from the index of a chunk file, without producing triangles.
+    pub fn from_chunk_aabbs(chunks: impl IntoIterator<Item = Option<Aabb>>) -> RayQuery {
+        use crate::triangle::aabb_union;
+
+        let chunks: Vec<Option<Aabb>> = chunks.into_iter().map(|aabb| aabb.map(aabb_pad)).collect();
+        let groups = chunks.chunks(GROUP_SIZE)
+            .map(|group| group.iter().flatten().copied().reduce(aabb_union))
+            .collect();
+        RayQuery {chunks, groups}
+    }
+
+    /// Visits the chunks that might be hit by the ray, in index order.
+    ///
+    /// The visitor gets the chunk offset and the distance where the ray enters the chunk AABB.
+    /// It returns `false` to stop visiting more chunks.
+    pub fn visit(&self, ray: Ray, mut f: impl FnMut(usize, f32) -> bool) {
+        use crate::ray::ray_aabb_hit;
+
+        for (g, group) in self.groups.iter().enumerate() {
+            let Some(group) = *group else {continue};
+            if ray_aabb_hit(ray, group).is_none() {continue};
+
+            let start = g * GROUP_SIZE;
+            let end = (start + GROUP_SIZE).min(self.chunks.len());
+            for c in start..end {
+                let Some(aabb) = self.chunks[c] else {continue};
+                let Some(t) = ray_aabb_hit(ray, aabb) else {continue};
+                if !f(c * 64, t) {return};
+            }
+        }
+    }
+
+    /// Finds the closest triangle hit by the ray.
+    ///
+    /// Gives the same result as testing every chunk in the producer.
+    pub fn closest_hit<T: Produce<Triangle> + ?Sized>(&self, list: &T, ray: Ray) -> RayHit {
+        use crate::ray::ray_triangle_chunk_hit_update;
+        use crate::triangle::triangle_chunk;
+
+        let mut res: RayHit = None;
+        self.visit(ray, |off, t| {
+            // Skip chunks that start behind the closest hit so far.
+            if let Some((depth, _)) = res && t > depth {return true};
+
+            let (chunk, mask) = triangle_chunk(list, off);
+            ray_triangle_chunk_hit_update(ray, &chunk, mask, off, &mut res);
+            true
+        });
+        res
+    }
+
+    /// Finds any triangle hit by the ray closer than `max_depth`.
+    ///
+    /// This is faster than finding the closest hit,
+    /// e.g. when checking line of sight.
+    pub fn any_hit<T: Produce<Triangle> + ?Sized>(
+        &self,
+        list: &T,
+        ray: Ray,
+        max_depth: f32,
+    ) -> RayHit {
+        use crate::ray::ray_triangle_chunk_hits;
+        use crate::triangle::triangle_chunk;
+
+        let mut res: RayHit = None;
+        let mut hits = vec![];
+        self.visit(ray, |off, t| {
+            if t >= max_depth {return true};
+
+            let (chunk, mask) = triangle_chunk(list, off);
+            hits.clear();
+            ray_triangle_chunk_hits(ray, &chunk, mask, off, &mut hits);
+            res = hits.iter().copied().find(|&(depth, _)| depth < max_depth);
+            res.is_none()
+        });
+        res
+    }
+
+    /// Finds all triangles hit by the ray.
+    ///
+    /// The hits are sorted by depth, from closest to furthest away.
+    /// Triangles at the same depth are sorted by index.
+    pub fn all_hits<T: Produce<Triangle> + ?Sized>(
+        &self,
+        list: &T,
+        ray: Ray,
+    ) -> Vec<(f32, usize)> {
+        use crate::ray::ray_triangle_chunk_hits;
+        use crate::triangle::triangle_chunk;
+
+        let mut hits = vec![];
+        self.visit(ray, |off, _| {
+            let (chunk, mask) = triangle_chunk(list, off);
+            ray_triangle_chunk_hits(ray, &chunk, mask, off, &mut hits);
+            true
+        });
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        hits
+    }
+}
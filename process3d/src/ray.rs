@@ -1,6 +1,6 @@
 //! # Ray algorithms
 
-use crate::{Chunk, IndexFlag, PixelPos, Point, Ray, RayHit, RayHitAll, Triangle};
+use crate::{Aabb, Chunk, IndexFlag, PixelPos, Point, Ray, RayHit, RayHitAll, Triangle, Vector};
 use crate::frustrum::{near_dim, near_uv_pos};
 use crate::cam::CameraPerspective;
 
@@ -12,14 +12,26 @@ pub fn ray_hit_all_to_ray_hit(hit: RayHitAll) -> RayHit {
 }
 
 /// Ray triangle intersection using Möller-Trumbore algorithm.
-pub fn ray_triangle_hit((origin, direction): Ray, (a, b, c): Triangle) -> Option<f32> {
+pub fn ray_triangle_hit(ray: Ray, (a, b, c): Triangle) -> Option<f32> {
+    use vecmath::vec3_sub as sub;
+
+    ray_triangle_edges_hit(ray, a, sub(b, a), sub(c, a))
+}
+
+/// Ray triangle intersection using Möller-Trumbore algorithm,
+/// where the triangle is given by a corner and two edges from that corner.
+///
+/// This is used when the edges are pre-computed.
+pub fn ray_triangle_edges_hit(
+    (origin, direction): Ray,
+    a: Point,
+    e1: Vector,
+    e2: Vector,
+) -> Option<f32> {
     use vecmath::vec3_sub as sub;
     use vecmath::vec3_cross as cross;
     use vecmath::vec3_dot as dot;
 
-    let e1 = sub(b, a);
-    let e2 = sub(c, a);
-
     let ray_cross_e2 = cross(direction, e2);
     let det = dot(e1, ray_cross_e2);
 
@@ -91,6 +103,40 @@ pub fn ray_triangle_chunk_hit_all(
     None
 }
 
+/// Ray hits of all triangles in chunk with a mask, pushed in index order.
+///
+/// The index is offset by `off`.
+pub fn ray_triangle_chunk_hits(
+    ray: Ray,
+    chunk: &Chunk<Triangle>,
+    mut mask: u64,
+    off: usize,
+    res: &mut Vec<(f32, usize)>,
+) {
+    while let Some((t, i)) = ray_triangle_chunk_hit_all(ray, chunk, mask) {
+        // Remove the hit triangle and all triangles before it from the mask.
+        mask &= !((1_u64 << i) | ((1_u64 << i) - 1));
+        res.push((t, off + i));
+    }
+}
+
+/// Ray intersection with AABB using the slab method.
+///
+/// Returns the distance along the ray where it enters the AABB,
+/// or zero if the ray starts inside.
+pub fn ray_aabb_hit((origin, dir): Ray, (mi, ma): Aabb) -> Option<f32> {
+    let mut t_min: f32 = 0.0;
+    let mut t_max = f32::INFINITY;
+    for k in 0..3 {
+        let inv = 1.0 / dir[k];
+        let t0 = (mi[k] - origin[k]) * inv;
+        let t1 = (ma[k] - origin[k]) * inv;
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+    if t_min <= t_max {Some(t_min)} else {None}
+}
+
 /// Offset ray hit index.
 pub fn ray_hit_offset(hit: RayHit, off: usize) -> RayHit {
     if let Some((d, i)) = hit {
@@ -114,6 +160,19 @@ pub fn ray_triangle_chunk_hit_update(
     }
 }
 
+/// Filters a chunk mask by a previous hit of all triangles.
+///
+/// Returns a mask without the triangles that were visited by the ray,
+/// or zero when the ray should not progress in this chunk.
+pub fn ray_hit_all_chunk_mask(hit: RayHitAll, mask: u64, off: usize) -> u64 {
+    if let Some((_, ind)) = hit {
+        let ind = if ind.flag() {return 0} else {ind.index()};
+        if ind >= off + 64 {0} else if ind >= off {
+            !((1_u64 << (ind - off)) - 1) & mask
+        } else {mask}
+    } else {0}
+}
+
 /// Ray hit of all triangles in chunk with mask, updating hit.
 ///
 /// A new mask is pre-computed to filter out previous hits in the chunk.
@@ -124,12 +183,8 @@ pub fn ray_triangle_chunk_hit_all_update(
     off: usize,
     res: &mut RayHitAll,
 ) {
-    let mask = if let Some((_, ind)) = *res {
-        let ind = if ind.flag() {return} else {ind.index()};
-        if ind >= off + 64 {return} else if ind >= off {
-            !((1_u64 << (ind - off)) - 1) & mask
-        } else {mask}
-    } else {return};
+    let mask = ray_hit_all_chunk_mask(*res, mask, off);
+    if mask == 0 {return};
     *res = match (*res, ray_hit_offset(ray_triangle_chunk_hit_all(ray, &chunk, mask), off)) {
         (x, None) => x,
         (_, Some((tj, mj))) => Some((tj, IndexFlag::from_parts(mj, true))),
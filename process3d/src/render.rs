@@ -1,26 +1,40 @@
 //! # Rendering
 
 use crate::color::*;
+use crate::cull::*;
 use crate::ray::*;
 use crate::tile::*;
 use crate::profile::*;
 use crate::produce::*;
 use crate::math::*;
 use crate::acc::*;
+use crate::edge::*;
+use crate::fog::*;
+use crate::post::*;
+use crate::select::*;
+use crate::trace::*;
+use crate::query::{QueryView, RayQuery};
 use crate::frustrum::depth_linear;
 use crate::mask::CompressedMasks;
 use crate::cam::{Camera, CameraPerspective};
 use crate::{
     IndexFlag,
+    Matrix4,
     PixelPos,
+    Point,
+    Ray,
     RayHit,
     Rgba,
+    TilePos,
     Triangle,
     Vector,
 };
 
 /// Stores arguments for shaders.
-pub struct ShaderData<Args> {
+///
+/// Geometry of the hit in world coordinates is computed on demand by methods,
+/// such that shaders that do not use it do not pay for it.
+pub struct ShaderData<'a, Args> {
     /// The ray depth and index of graphics primitive.
     pub hit: RayHit,
     /// The depth in range `0.0` to `1.0` where `0.0` is near clip plane
@@ -35,12 +49,111 @@ pub struct ShaderData<Args> {
     pub internal_offset: Option<usize>,
     /// Customized arguments to the shader.
     pub args: Args,
+    /// The pixel position, where rows start at the bottom of image like tiles.
+    ///
+    /// The image position is `[x, h - y - 1]` for image height `h`.
+    pub pixel: PixelPos,
+    /// The tile position in the grid of tiles, where rows start at the bottom of image.
+    pub tile: TilePos,
+    /// The index of hit triangle in the virtual list of producer.
+    pub index: usize,
+    /// The ray in camera coordinates, from the eye with normalized direction.
+    pub view_ray: Ray,
+    inv_view: &'a Matrix4,
+    world_eye: Point,
+    triangle: &'a (dyn Fn() -> Triangle + 'a),
+}
+
+impl<Args> ShaderData<'_, Args> {
+    fn depth(&self) -> f32 {self.hit.map_or(0.0, |(depth, _)| depth)}
+
+    /// Gets the hit triangle in camera coordinates.
+    ///
+    /// This produces the chunk of the triangle, unless it is the last one produced.
+    pub fn triangle(&self) -> Triangle {(self.triangle)()}
+
+    /// Gets the ray in world coordinates, from the camera position.
+    ///
+    /// The direction is normalized when `flip_xyz` does not scale.
+    /// The hit position is at the same depth along both rays.
+    pub fn world_ray(&self) -> Ray {world_ray(self.inv_view, self.world_eye, self.view_ray)}
+
+    /// Gets the hit position in world coordinates.
+    pub fn world_pos(&self) -> Point {
+        let (origin, dir) = self.world_ray();
+        [0, 1, 2].map(|k| origin[k] + self.depth() * dir[k])
+    }
+
+    /// Gets the hit in world coordinates, with position and geometric normal.
+    pub fn world_hit(&self) -> TraceHit {
+        TraceHit::new(self.world_ray(), self.depth(), self.index, self.internal_offset,
+            transform_triangle(self.inv_view, self.triangle()))
+    }
+
+    /// Gets the normalized geometric normal in world coordinates, facing against the ray.
+    pub fn normal(&self) -> Vector {self.world_hit().normal}
+
+    /// Gets whether the ray hit the front face of the triangle, see `triangle_plane`.
+    pub fn front_face(&self) -> bool {self.world_hit().front_face}
+}
+
+/// Transforms ray from the eye in camera coordinates into world coordinates.
+///
+/// The hit position is at the same depth along both rays.
+fn world_ray(inv_view: &Matrix4, world_eye: Point, (_, dir): Ray) -> Ray {
+    let p = transform_point(inv_view, dir);
+    (world_eye, [0, 1, 2].map(|k| p[k] - world_eye[k]))
+}
+
+/// Calculates the row-major view transform of camera,
+/// scaling or flipping axes afterwards.
+///
+/// This transforms from world coordinates to camera coordinates
+/// used when rendering.
+pub fn view_matrix(cam: &Camera, flip_xyz: Vector) -> Matrix4 {
+    use vecmath::row_mat4_mul;
+
+    let [sx, sy, sz] = flip_xyz;
+    let flip = [
+        [sx, 0.0, 0.0, 0.0],
+        [0.0, sy, 0.0, 0.0],
+        [0.0, 0.0, sz, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    let view = mat4_transposed(cam.orthogonal());
+    row_mat4_mul(flip, view)
 }
 
 /// The type of shader.
 ///
 /// A shader might modify the default color before accumulation.
-pub type Shader<Color, Args> = fn(&mut Color, ShaderData<Args>);
+///
+/// Since this is a closure, it can capture e.g. lights, textures or time.
+pub type Shader<'a, Color, Args> = Box<dyn Fn(&mut Color, ShaderData<'_, Args>) + Sync + 'a>;
+
+/// The type of scene ray color function.
+///
+/// Gets a default ray color and shader arguments from depth and internal address.
+pub type SceneRayColor<'a, Scene, Color, Args> =
+    Box<dyn Fn(&Scene, f32, usize) -> (Color, Args) + Sync + 'a>;
+
+/// The type of function that returns `true` if color is transparent.
+pub type IsTransparent<'a, Color> = Box<dyn Fn(&Color) -> bool + Sync + 'a>;
+
+/// The type of function that produces the final color from accumulator output.
+pub type AccToLinearRgba<'a, Out> = Box<dyn Fn(Out) -> Rgba + Sync + 'a>;
+
+/// The type of function that gets the size of image in pixels.
+pub type ImageSize<'a, Img> = Box<dyn Fn(&Img) -> PixelPos + 'a>;
+
+/// The type of function that writes pixel to image.
+pub type Pxl<'a, Img> = Box<dyn FnMut(&mut Img, PixelPos, Rgba<u8>) + 'a>;
+
+/// The type of profiling function that reports render time.
+pub type ProfileRender<'a, P> = Box<dyn FnMut(&mut P, Option<f64>) + 'a>;
+
+/// The type of profiling function that reports compress data.
+pub type ProfileCompress<'a, P> = Box<dyn FnMut(&mut P, ProfileCompressData, Option<f64>) + 'a>;
 
 /// Stores data needed during rendering.
 pub struct Renderer<'a, Scene, Prod, Img, A, ShaderArgs, P>
@@ -55,25 +168,29 @@ pub struct Renderer<'a, Scene, Prod, Img, A, ShaderArgs, P>
     /// For example, if the accumulator uses linear color space,
     /// and you use sRGB color space in scene data,
     /// then you should convert to linear color space.
-    pub scene_ray_color: fn(&Scene, f32, usize) -> (A::In, ShaderArgs),
+    pub scene_ray_color: SceneRayColor<'a, Scene, A::In, ShaderArgs>,
     /// A customized shader transform prior to color accumulation.
     ///
     /// This can be used to change the color of the ray.
-    pub shader: Shader<A::In, ShaderArgs>,
+    pub shader: Shader<'a, A::In, ShaderArgs>,
     /// Returns `true` if color is transparent, `false` otherwise.
     ///
     /// This is used to filter out colors that do not contribute in accumulator.
-    pub is_transparent: fn(&A::In) -> bool,
+    pub is_transparent: IsTransparent<'a, A::In>,
     /// Produces the final color from accumulator.
-    pub acc_to_linear_rgba: fn(A::Out) -> Rgba,
+    pub acc_to_linear_rgba: AccToLinearRgba<'a, A::Out>,
     /// A producer.
     pub producer: &'a Prod,
     /// The target image.
     pub img: &'a mut Img,
     /// Get the size of the image in pixels.
-    pub size: fn(&Img) -> PixelPos,
+    pub size: ImageSize<'a, Img>,
     /// Writes pixel to image.
-    pub pxl: fn(&mut Img, PixelPos, c: Rgba<u8>),
+    ///
+    /// The image is cleared first, then rendered pixels are written tile by tile.
+    /// With selection highlight or post-processing, the tiles are gathered into a frame,
+    /// and each pixel is written exactly once instead.
+    pub pxl: Pxl<'a, Img>,
     /// Accumulator data.
     ///
     /// This is used to pre-configure the accumulator with some data.
@@ -90,6 +207,10 @@ pub struct Renderer<'a, Scene, Prod, Img, A, ShaderArgs, P>
     pub pre_compr_masks: &'a mut [CompressedMasks],
     /// Stores compressed masks for adaptive sub-tiling.
     pub sub_compr_masks: &'a mut Vec<Vec<CompressedMasks>>,
+    /// Stores leaves per render tile for quadtree sub-tiling.
+    ///
+    /// Only used with `quad_min_size`. When `None`, the leaves are stored per frame.
+    pub quad_compr_masks: Option<&'a mut Vec<Vec<QuadTile>>>,
     /// A limit on the number of triangles per tile before doing adaptive sub-tiling.
     ///
     /// Adaptive sub-tiling produces sub-tiles such that the average number of triangles
@@ -98,15 +219,25 @@ pub struct Renderer<'a, Scene, Prod, Img, A, ShaderArgs, P>
     /// Data used to store performance profiling information.
     pub profile: &'a mut P,
     /// Reports the amount of seconds taken to render (`None` if profiling is disabled).
-    pub profile_render: fn(&mut P, Option<f64>),
+    pub profile_render: ProfileRender<'a, P>,
     /// Reports profile compress data and amount of seconds (`None` if profiling is disabled).
-    pub profile_compress: fn(&mut P, ProfileCompressData, Option<f64>),
+    pub profile_compress: ProfileCompress<'a, P>,
     /// Whether to use adaptive sub-tiling.
     ///
     /// Adaptive sub-tiling splits tiles that have a large amount
     /// of triangles into sub-tiles, where the average amount of triangles
     /// is less thatn `sub_tile_triangle_limit`.
     pub sub_masks: bool,
+    /// The minimum leaf size of quadtree sub-tiling.
+    ///
+    /// When set, adaptive sub-tiling splits tiles recursively
+    /// instead of once into equal sub-tiles, see `quad_sub_tiles`.
+    pub quad_min_size: Option<u32>,
+    /// Whether to refine masks per render tile with exact triangle tests.
+    ///
+    /// This removes triangles whose AABB intersects the tile while the triangle does not,
+    /// at the cost of more pre-processing. See `masks_exact`.
+    pub exact_masks: bool,
     /// Whether to use pre-masks.
     ///
     /// This is a pre-pre-processing step where run-length compression
@@ -124,6 +255,39 @@ pub struct Renderer<'a, Scene, Prod, Img, A, ShaderArgs, P>
     pub acc_limit: u32,
     /// The scale ratio between pre-tile size and tile size.
     pub scale_to_pre_tile_size: u32,
+    /// Draws triangle edges on top of shaded colors (`None` to disable).
+    ///
+    /// Edges are blended into the color after the shader, before accumulation.
+    pub edges: Option<EdgeOverlay<'a, A::In>>,
+    /// Highlights selected objects after tile accumulation (`None` to disable).
+    pub selection: Option<Selection<'a>>,
+    /// Post-processing effects on linear colors, applied after selection highlight.
+    pub post: &'a [PostEffect],
+    /// Dithering when quantizing colors to `u8` (`None` to round to nearest).
+    ///
+    /// Dithering uses the pixel position in the image.
+    pub dither: Option<Dither>,
+    /// Fog volumes with varying density (`None` to disable).
+    ///
+    /// Volumes are ray-marched per pixel and added to the accumulator as fog pairs,
+    /// so they should be used with semi-fog accumulators.
+    pub fog_volumes: Option<FogVolumes<'a, A::In>>,
+    /// Traces reflection and refraction rays from shaded hits (`None` to disable).
+    ///
+    /// Secondary rays are blended into the color after the shader, before edges.
+    /// Rays are traced in world coordinates, using `ray_query` as broad phase when set.
+    pub reflections: Option<Reflections<'a, A::In>>,
+    /// Objects with bounds in world coordinates, culled before triangles (`None` to disable).
+    ///
+    /// See `cull` for details.
+    pub cull_objects: Option<&'a [CullObject]>,
+    /// Cached chunk AABBs of the producer in world coordinates (`None` to disable).
+    ///
+    /// Skips chunks outside tiles when computing masks, see `tile_mask_with_query`,
+    /// and is the broad phase of `reflections`.
+    /// The ray query must be built over the producer, and rebuilt when it changes.
+    /// Not used for masks when `cull_objects` is set.
+    pub ray_query: Option<&'a RayQuery>,
 }
 
 impl<Scene, Prod, Img, Accumulator, ShaderArgs, P>
@@ -137,13 +301,36 @@ Renderer<'_, Scene, Prod, Img, Accumulator, ShaderArgs, P>
     /// The tile size should be optimized for adaptive sub-tile rendering.
     /// Use `optimal_sub_tile_size`.
     pub fn render<const TILE_SIZE: usize>(self) {
+        self.render_with_tile_size(TILE_SIZE as u32)
+    }
+
+    /// Render with some render tile size chosen at runtime.
+    ///
+    /// The tile size should be optimized for adaptive sub-tile rendering.
+    /// Use `optimal_sub_tile_size`.
+    ///
+    /// Scratch buffers are allocated on the heap and reused per thread.
+    /// The accumulator must support tiles of this size,
+    /// e.g. `VecTileRgbaMinDepthAcc` with tile size as accumulator data.
+    ///
+    /// Panics if the tile size is zero or exceeds the accumulator tile capacity.
+    pub fn render_with_tile_size(self, tile_size: u32) {
+        use std::cell::RefCell;
+
+        assert!(tile_size > 0, "Tile size must be at least 1");
+        if let Some(capacity) = Accumulator::tile_capacity(&self.acc_data) {
+            assert!(capacity >= tile_size, "Accumulator tile capacity {capacity} is less than tile size {tile_size}");
+        }
+
         let Renderer {
             scene, scene_ray_color, producer,
-            img, size, pxl, acc_data, persp, cam, flip_xyz,
-            compr_masks, pre_compr_masks, sub_compr_masks,
-            sub_tile_triangle_limit, shader, profile, profile_render,
-            sub_masks, pre_masks, profile_enabled, profile_compress,
-            acc_limit, scale_to_pre_tile_size, is_transparent, acc_to_linear_rgba,
+            img, size, mut pxl, acc_data, persp, cam, flip_xyz,
+            compr_masks, pre_compr_masks, sub_compr_masks, quad_compr_masks,
+            sub_tile_triangle_limit, shader, profile, mut profile_render,
+            sub_masks, quad_min_size, exact_masks, pre_masks, profile_enabled,
+            mut profile_compress, acc_limit, scale_to_pre_tile_size,
+            is_transparent, acc_to_linear_rgba,
+            edges, selection, post, dither, fog_volumes, reflections, cull_objects, ray_query,
         } = self;
 
         let profile_without_sub_masks = !sub_masks;
@@ -151,18 +338,10 @@ Renderer<'_, Scene, Prod, Img, Accumulator, ShaderArgs, P>
 
         use rayon::prelude::*;
         use std::sync::mpsc::channel;
-        use vecmath::row_mat4_mul;
-
-        let [sx, sy, sz] = flip_xyz;
-        let flip = [
-            [sx, 0.0, 0.0, 0.0],
-            [0.0, sy, 0.0, 0.0],
-            [0.0, 0.0, sz, 0.0],
-            [0.0, 0.0, 0.0, 1.0],
-        ];
-        let view = mat4_transposed(cam.orthogonal());
-        let view = row_mat4_mul(flip, view);
 
+        let view = view_matrix(cam, flip_xyz);
+
+        let world_producer = producer;
         let producer: &TransformProducer<_> = &TransformProducer {
             matrix: view,
             inner: producer,
@@ -171,27 +350,38 @@ Renderer<'_, Scene, Prod, Img, Accumulator, ShaderArgs, P>
         let size = (size)(img);
         let [w, h] = size;
 
-        let tile_size = TILE_SIZE as u32;
         let grid = tile_grid(size, tile_size);
+        let n = (tile_size * tile_size) as usize;
+        let edge_pixel_size = edge_pixel_size(persp, size);
+        // Transforms from camera coordinates to world coordinates, for shaders and fog volumes.
+        let inv_view = vecmath::mat4_inv(view);
+        let world_eye = transform_point(&inv_view, [0.0; 3]);
+        // Traces in world coordinates, using the ray query when set,
+        // otherwise building the broad phase once per frame, shared by all threads.
+        let tracer = reflections.as_ref().map(|r| Tracer {
+            bias: r.bias,
+            ..match ray_query {
+                Some(query) => Tracer::with_query(world_producer, query, r.max_depth),
+                None => Tracer::new(world_producer, r.max_depth),
+            }
+        });
 
         let start: Option<f64> = if profile_enabled {Some(now())} else {None};
 
+        // Object bounds are transformed into camera coordinates once per frame.
+        let cull_objects = cull_objects.map(|objects| cull_objects_transform(objects, &view));
+        // The ray query stays in world coordinates, tile planes are transformed instead.
+        let query = ray_query.map(|query| QueryView {query, view});
+        let first_masks = |tile_size: u32, masks_out: &mut [CompressedMasks]| match (&cull_objects, query) {
+            (Some(objects), _) => masks_with_objects(persp, size, tile_size, producer, objects, masks_out),
+            (None, Some(query)) => masks_with_query(persp, size, tile_size, producer, query, masks_out),
+            (None, None) => masks(persp, size, tile_size, producer, masks_out),
+        };
+
         if profile_without_pre_masks {
-            masks(
-                persp,
-                size,
-                tile_size,
-                producer,
-                compr_masks,
-            );
+            first_masks(tile_size, compr_masks);
         } else {
-            masks(
-                persp,
-                size,
-                tile_size * scale_to_pre_tile_size,
-                producer,
-                pre_compr_masks,
-            );
+            first_masks(tile_size * scale_to_pre_tile_size, pre_compr_masks);
             masks_with_pre_masks(
                 persp,
                 size,
@@ -203,27 +393,55 @@ Renderer<'_, Scene, Prod, Img, Accumulator, ShaderArgs, P>
             );
         }
 
+        let exact_mask_stats = if exact_masks {
+            Some(masks_exact(persp, size, tile_size, producer, compr_masks))
+        } else {None};
+
         let koeff: u32 = sub_tile_triangle_limit;
+        let mut frame_quad_compr_masks = vec![];
+        let quad_compr_masks = quad_compr_masks.unwrap_or(&mut frame_quad_compr_masks);
         if !profile_without_sub_masks {
-            row_sub_masks(&persp, size, tile_size, grid, koeff, producer,
-                compr_masks, sub_compr_masks);
+            if let Some(min_size) = quad_min_size {
+                let quad = QuadSubTiling {min_size, triangle_limit: koeff};
+                quad_masks(persp, size, tile_size, quad, producer, compr_masks, quad_compr_masks);
+            } else {
+                row_sub_masks(&persp, size, tile_size, grid, koeff, producer,
+                    compr_masks, sub_compr_masks);
+            }
         }
 
-        profile_compress(profile, ProfileCompressData {tile_size, grid, compr_masks}, start);
+        profile_compress(profile, ProfileCompressData {tile_size, grid, compr_masks, exact_mask_stats}, start);
 
         let (tx, rx) = channel();
 
         let start: Option<f64> = if profile_enabled {Some(now())} else {None};
-        (0..grid[1]).into_par_iter().for_each_with(tx, |tx, tj| {
+        (0..grid[1]).into_par_iter().for_each_init(|| (
+            tx.clone(),
+            Accumulator::new(acc_data.clone()),
+            vec![None; n],
+            RefCell::new(None),
+            // Stores the closest visible hit per pixel for selection highlight.
+            vec![(0.0, None); if selection.is_some() {n} else {0}],
+            // Stores the nearest opaque depth per pixel for fog volumes.
+            vec![0.0; if fog_volumes.is_some() {n} else {0}],
+        ), |(tx, acc, depth_buffer, last_chunk, closest, opaque), tj| {
             let nh = (tj + 1) * tile_size;
             let th = nh.min(h) - tj * tile_size;
-            let mut depth_buffer = &mut [[None; TILE_SIZE]; TILE_SIZE];
-            let mut acc = Accumulator::new(acc_data.clone());
             let sm = &sub_compr_masks[tj as usize];
-            for (ti, val) in row_sub_tile_iter(tile_size, grid, tj, koeff, compr_masks) {
-                let masks = &compr_masks[(tj * grid[0] + ti) as usize];
+            // Quadtree sub-tiling replaces equal sub-tiles, which need not fit the tile size.
+            let quad = quad_min_size.is_some();
+            let equal = (!quad).then(|| row_sub_tile_iter(tile_size, grid, tj, koeff, compr_masks));
+            let unsplit = (0..grid[0]).filter(|_| quad).map(|ti| (ti, None));
+            for (ti, val) in equal.into_iter().flatten().chain(unsplit) {
+                let k = (tj * grid[0] + ti) as usize;
+                let masks = &compr_masks[k];
+                // Leaves are only computed with sub-tiling enabled.
+                let leaves: &[QuadTile] = match quad_compr_masks.get(k) {
+                    Some(leaves) if quad && !profile_without_sub_masks => leaves,
+                    _ => &[],
+                };
                 let triangles = masks.count_ones() as u32;
-                if triangles == 0 {continue};
+                if triangles == 0 && fog_volumes.is_none() {continue};
 
                 acc.clear();
 
@@ -231,26 +449,32 @@ Renderer<'_, Scene, Prod, Img, Accumulator, ShaderArgs, P>
                 let tw = nw.min(w) - ti * tile_size;
                 let pos = [ti * tile_size, tj * tile_size];
 
-                // Stores depth and colors that the ray accumulates.
-                let mut write = [[[0; 4]; TILE_SIZE]; TILE_SIZE];
-                *depth_buffer = [[
-                    Some((0.0, IndexFlag::from_parts(0, false))); TILE_SIZE]; TILE_SIZE];
+                // Stores linear colors that the ray accumulates.
+                let mut write = vec![[0.0; 4]; (tw * th) as usize];
+                depth_buffer.fill(Some((0.0, IndexFlag::from_parts(0, false))));
+                closest.fill((0.0, None));
+                opaque.fill(f32::INFINITY);
 
                 for _ in 0..acc_limit {
                     match (profile_without_sub_masks, val) {
+                        (false, _) if !leaves.is_empty() => {
+                            if !render_quad_tile_depth_all_flat(persp, size, pos, tile_size,
+                                producer, leaves, depth_buffer) {break};
+                        }
                         (true, _) | (false, None) => {
-                            if !render_tile_depth_all(&persp, size, pos,
-                                producer, masks, &mut depth_buffer) {break};
+                            if !render_tile_depth_all_flat(&persp, size, pos, tile_size,
+                                producer, masks, depth_buffer) {break};
                         }
                         (false, Some((st, offset))) => {
-                            if !render_row_sub_tile_depth_all(&persp, size, pos, st,
-                                producer, &sm[offset..], &mut depth_buffer) {break};
+                            let view = TileView {persp, dim: size, pos, n_tile_size: tile_size};
+                            if !render_row_sub_tile_depth_all_flat(view, st,
+                                producer, &sm[offset..], depth_buffer) {break};
                         }
                     }
 
                     for j in 0..th {
                         for i in 0..tw {
-                            let hit = &mut depth_buffer[j as usize][i as usize];
+                            let hit = &mut depth_buffer[(j * tile_size + i) as usize];
                             *hit = if let Some((depth, ind)) = *hit {
                                 if !ind.flag() {continue};
 
@@ -258,51 +482,604 @@ Renderer<'_, Scene, Prod, Img, Accumulator, ShaderArgs, P>
                                 let (mut color, args) = scene_ray_color(
                                     &scene, depth, internal_offset.unwrap());
 
+                                // Reuse the last triangle chunk, since neighbour rays
+                                // usually hit triangles in the same chunk.
+                                let ind = ind.index();
+                                let triangle = || {
+                                    let off = ind - ind % 64;
+                                    let mut last = last_chunk.borrow_mut();
+                                    if !matches!(*last, Some((o, _)) if o == off) {
+                                        *last = Some((off, producer.produce(off)));
+                                    }
+                                    last.as_ref().unwrap().1[ind % 64]
+                                };
+                                let eye = [0.0; 3];
+                                let pixel = [pos[0] + i, pos[1] + j];
+                                let ray = (eye, ray_dir(persp, eye, pixel, size));
+
                                 let hit = ray_hit_all_to_ray_hit(*hit);
                                 shader(&mut color, ShaderData {
                                     hit: hit,
                                     depth_linear: depth_linear(&persp, hit),
                                     internal_offset,
                                     args,
+                                    pixel,
+                                    tile: [ti, tj],
+                                    index: ind,
+                                    view_ray: ray,
+                                    inv_view: &inv_view,
+                                    world_eye,
+                                    triangle: &triangle,
                                 });
 
-                                if !is_transparent(&color) {acc.upd(i, j, depth, color)};
-                                Some((depth, IndexFlag::from_parts(ind.index() + 1, false)))
+                                if let (Some(reflections), Some(tracer)) = (&reflections, &tracer) {
+                                    let hit = TraceHit::new(world_ray(&inv_view, world_eye, ray), depth, ind,
+                                        internal_offset, transform_triangle(&inv_view, triangle()));
+                                    let s = (reflections.surface)(&hit);
+                                    if tracer.max_depth > 0 && s.has_secondary_rays() {
+                                        let (traced, w) = tracer.secondary(&hit, &s, 0,
+                                            &reflections.surface, &reflections.miss);
+                                        (reflections.blend)(&mut color, traced, w);
+                                    }
+                                }
+
+                                if let Some(edges) = &edges {
+                                    let coverage = edges.coverage(ray, triangle(), ind, depth, edge_pixel_size);
+                                    if coverage > 0.0 {(edges.blend)(&mut color, coverage)};
+                                }
+
+                                if let Some(fog_volumes) = &fog_volumes &&
+                                    (fog_volumes.is_opaque)(&color) {
+                                    let o = &mut opaque[(j * tile_size + i) as usize];
+                                    *o = o.min(depth);
+                                }
+
+                                if !is_transparent(&color) {
+                                    acc.upd(i, j, depth, color);
+                                    if let Some(c) = closest.get_mut((j * tile_size + i) as usize) &&
+                                        (c.1.is_none() || depth < c.0) {*c = (depth, internal_offset)};
+                                }
+                                Some((depth, IndexFlag::from_parts(ind + 1, false)))
                             } else {None}
                         }
                     }
                 }
 
+                if let Some(fog_volumes) = &fog_volumes {
+                    for j in 0..th {
+                        for i in 0..tw {
+                            let dir = ray_dir(persp, [0.0; 3], [pos[0] + i, pos[1] + j], size);
+                            let p = transform_point(&inv_view, dir);
+                            let ray = (world_eye, [0, 1, 2].map(|k| p[k] - world_eye[k]));
+                            for volume in &fog_volumes.volumes {
+                                let Some((t0, t1)) = fog_volume_interval(ray, volume.bounds)
+                                    else {continue};
+                                // Zero depth means empty in accumulators.
+                                let t0 = t0.max(persp.near_clip);
+                                // Surfaces hide the fog behind them.
+                                let t1 = t1.min(persp.far_clip).min(opaque[(j * tile_size + i) as usize]);
+                                if t0 >= t1 {continue};
+
+                                let [r, g, b, opacity] = volume.march(ray, t0, t1);
+                                if opacity <= 0.0 {continue};
+                                // Entry and exit use the same color, such that they form a fog pair.
+                                let c = [r, g, b, fog_alpha_for_opacity(opacity, t1 - t0)];
+                                acc.upd(i, j, t0, (fog_volumes.color)(c));
+                                acc.upd(i, j, t1, (fog_volumes.color)(c));
+                            }
+                        }
+                    }
+                }
+
+                let mut ids = vec![];
                 for j in 0..th {
                     for i in 0..tw {
-                        write[j as usize][i as usize] =
-                            rgba_to_u8(rgba_gamma_linear_to_srgb(acc_to_linear_rgba(acc.acc(i, j))));
+                        write[(j * tw + i) as usize] = acc_to_linear_rgba(acc.acc(i, j));
+                        if let Some(c) = closest.get((j * tile_size + i) as usize) {ids.push(c.1)};
                     }
                 }
 
-                let _ = tx.send(([ti * tile_size, tj * tile_size], write));
+                let _ = tx.send((pos, [tw, th], write, ids));
             }
         });
+        drop(tx);
 
-        for y in 0..h {
-            for x in 0..w {
-                pxl(img, [x, y], [0; 4]);
+        let to_u8 = |color: Rgba, pos: PixelPos| {
+            let color = rgba_gamma_linear_to_srgb(color);
+            match dither {
+                Some(dither) => rgba_to_u8_dithered(color, dither, pos),
+                None => rgba_to_u8(color),
             }
+        };
+
+        if selection.is_none() && post.is_empty() {
+            // Write tiles as they arrive, without allocating a frame.
+            for y in 0..h {
+                for x in 0..w {
+                    pxl(img, [x, y], [0; 4]);
+                }
+            }
+
+            for (offset, [tw, th], tile, _) in rx {
+                for j in 0..th {
+                    for i in 0..tw {
+                        let pos = [offset[0] + i, h - (offset[1] + j) - 1];
+                        pxl(img, pos, to_u8(tile[(j * tw + i) as usize], pos));
+                    }
+                }
+            }
+
+            profile_render(profile, start);
+            return;
         }
 
-        for (offset, tile) in rx {
-            for j in 0..TILE_SIZE as u32 {
-                for i in 0..TILE_SIZE as u32 {
-                    let color = tile[j as usize][i as usize];
-                    let x = offset[0] + i;
-                    let y = offset[1] + j;
-                    if x >= w || y >= h {continue};
-                    let y = h - y - 1;
-                    pxl(img, [x, y], color);
+        // Collect tiles into a frame of linear colors, for post-processing.
+        let mut frame = vec![[0.0; 4]; (w * h) as usize];
+        let mut frame_ids = vec![None; if selection.is_some() {(w * h) as usize} else {0}];
+        for (offset, [tw, th], tile, ids) in rx {
+            for j in 0..th {
+                for i in 0..tw {
+                    let k = ((offset[1] + j) * w + offset[0] + i) as usize;
+                    frame[k] = tile[(j * tw + i) as usize];
+                    if let Some(&id) = ids.get((j * tw + i) as usize) {frame_ids[k] = id};
                 }
             }
         }
 
+        if let Some(selection) = &selection {
+            selection_highlight(size, &frame_ids, &mut frame, selection);
+        }
+        post_process(size, &mut frame, post);
+
+        for y in 0..h {
+            for x in 0..w {
+                let pos = [x, h - y - 1];
+                pxl(img, pos, to_u8(frame[(y * w + x) as usize], pos));
+            }
+        }
+
         profile_render(profile, start);
     }
 }
+
+/// Stores mask buffers used by `Renderer`.
+///
+/// The buffers are allocated from image size and tile size,
+/// and can be reused between frames.
+#[derive(Clone, Debug, Default)]
+pub struct RenderBuffers {
+    /// Stores compressed masks per render tile.
+    pub compr_masks: Vec<CompressedMasks>,
+    /// Stores compressed masks for pre-pre-processing.
+    pub pre_compr_masks: Vec<CompressedMasks>,
+    /// Stores compressed masks for adaptive sub-tiling.
+    pub sub_compr_masks: Vec<Vec<CompressedMasks>>,
+    /// Stores leaves per render tile for quadtree sub-tiling.
+    pub quad_compr_masks: Vec<Vec<QuadTile>>,
+}
+
+impl RenderBuffers {
+    /// Creates empty buffers.
+    pub fn new() -> RenderBuffers {RenderBuffers::default()}
+
+    /// Resizes buffers to image size, tile size and scale to pre-tile size.
+    ///
+    /// Memory is reused when the buffers already have the right size.
+    pub fn resize(&mut self, dim: PixelPos, tile_size: u32, scale_to_pre_tile_size: u32) {
+        let [w, h] = tile_grid(dim, tile_size);
+        self.compr_masks.resize((w * h) as usize, CompressedMasks::new());
+        let [w, h] = tile_grid(dim, tile_size * scale_to_pre_tile_size);
+        self.pre_compr_masks.resize((w * h) as usize, CompressedMasks::new());
+        self.sub_compr_masks.resize(tile_grid(dim, tile_size)[1] as usize, vec![]);
+        let [w, h] = tile_grid(dim, tile_size);
+        self.quad_compr_masks.resize((w * h) as usize, vec![]);
+    }
+}
+
+/// Describes invalid renderer settings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RendererError {
+    /// A required setting was not set.
+    Missing(&'static str),
+    /// The tile size is zero.
+    ZeroTileSize,
+    /// Adaptive sub-tiling requires a tile size from `optimal_sub_tile_size`.
+    UnsupportedSubTileSize(u32),
+    /// Adaptive sub-tiling requires a triangle limit of at least 1.
+    ZeroSubTileTriangleLimit,
+    /// Pre-masks require scale to pre-tile size of at least 1.
+    ZeroPreTileScale,
+    /// The accumulation limit is zero, so nothing would be rendered.
+    ZeroAccLimit,
+    /// The accumulator supports smaller tiles than the tile size.
+    AccTileCapacity {
+        /// The largest tile size supported by the accumulator.
+        capacity: u32,
+        /// The tile size.
+        tile_size: u32,
+    },
+}
+
+impl std::fmt::Display for RendererError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use RendererError::*;
+
+        match *self {
+            Missing(name) => write!(f, "Missing renderer setting `{}`", name),
+            ZeroTileSize => write!(f, "Tile size must be at least 1"),
+            UnsupportedSubTileSize(n) => write!(f,
+                "Tile size {} is not supported by adaptive sub-tiling, expected {}",
+                n, optimal_sub_tile_size(n)),
+            ZeroSubTileTriangleLimit => write!(f,
+                "Sub-tile triangle limit must be at least 1 when using adaptive sub-tiling"),
+            ZeroPreTileScale => write!(f,
+                "Scale to pre-tile size must be at least 1 when using pre-masks"),
+            ZeroAccLimit => write!(f, "Accumulation limit must be at least 1"),
+            AccTileCapacity {capacity, tile_size} => write!(f,
+                "Accumulator supports tiles up to size {}, but tile size is {}", capacity, tile_size),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+/// The renderer and the tile size to render with, returned by `RendererBuilder::build`.
+pub type BuiltRenderer<'a, Scene, Prod, Img, A, ShaderArgs, P> =
+    (Renderer<'a, Scene, Prod, Img, A, ShaderArgs, P>, u32);
+
+/// Builds a `Renderer` with default settings.
+///
+/// Mask buffers are allocated from image size and tile size,
+/// and profiling is disabled by default.
+///
+/// Defaults:
+///
+/// - `tile_size`: 24
+/// - `sub_tile_triangle_limit`: 64
+/// - `scale_to_pre_tile_size`: 4
+/// - `acc_limit`: 64
+/// - `sub_masks`, `pre_masks`: `true`
+/// - `quad_sub_tiles`: disabled
+/// - `exact_masks`: `false`
+/// - `flip_xyz`: `[1.0; 3]`
+/// - `shader`: does nothing
+pub struct RendererBuilder<'a, Scene, Prod, Img, A, ShaderArgs, P = NoProfile>
+    where Scene: Sync, Prod: Produce<Triangle> + Sync + ?Sized, A: Acc,
+{
+    scene: Scene,
+    producer: &'a Prod,
+    img: &'a mut Img,
+    persp: &'a CameraPerspective,
+    cam: &'a Camera,
+    scene_ray_color: Option<SceneRayColor<'a, Scene, A::In, ShaderArgs>>,
+    shader: Shader<'a, A::In, ShaderArgs>,
+    is_transparent: Option<IsTransparent<'a, A::In>>,
+    acc_to_linear_rgba: Option<AccToLinearRgba<'a, A::Out>>,
+    size: Option<ImageSize<'a, Img>>,
+    pxl: Option<Pxl<'a, Img>>,
+    acc_data: Option<A::Data>,
+    flip_xyz: Vector,
+    tile_size: u32,
+    sub_tile_triangle_limit: u32,
+    profile: &'a mut P,
+    profile_render: ProfileRender<'a, P>,
+    profile_compress: ProfileCompress<'a, P>,
+    sub_masks: bool,
+    quad_min_size: Option<u32>,
+    exact_masks: bool,
+    pre_masks: bool,
+    profile_enabled: bool,
+    acc_limit: u32,
+    scale_to_pre_tile_size: u32,
+    edges: Option<EdgeOverlay<'a, A::In>>,
+    selection: Option<Selection<'a>>,
+    post: &'a [PostEffect],
+    dither: Option<Dither>,
+    fog_volumes: Option<FogVolumes<'a, A::In>>,
+    reflections: Option<Reflections<'a, A::In>>,
+    cull_objects: Option<&'a [CullObject]>,
+    ray_query: Option<&'a RayQuery>,
+}
+
+impl<'a, Scene, Prod, Img, A, ShaderArgs> RendererBuilder<'a, Scene, Prod, Img, A, ShaderArgs>
+    where Scene: Sync, Prod: Produce<Triangle> + Sync + ?Sized, A: Acc,
+{
+    /// Creates a new renderer builder.
+    pub fn new(
+        scene: Scene,
+        producer: &'a Prod,
+        img: &'a mut Img,
+        persp: &'a CameraPerspective,
+        cam: &'a Camera,
+    ) -> Self {
+        RendererBuilder {
+            scene, producer, img, persp, cam,
+            scene_ray_color: None,
+            shader: Box::new(|_, _| {}),
+            is_transparent: None,
+            acc_to_linear_rgba: None,
+            size: None,
+            pxl: None,
+            acc_data: None,
+            flip_xyz: [1.0; 3],
+            tile_size: 24,
+            sub_tile_triangle_limit: 64,
+            profile: &mut [],
+            profile_render: Box::new(|_, _| {}),
+            profile_compress: Box::new(|_, _, _| {}),
+            sub_masks: true,
+            quad_min_size: None,
+            exact_masks: false,
+            pre_masks: true,
+            profile_enabled: false,
+            acc_limit: 64,
+            scale_to_pre_tile_size: 4,
+            edges: None,
+            selection: None,
+            post: &[],
+            dither: None,
+            fog_volumes: None,
+            reflections: None,
+            cull_objects: None,
+            ray_query: None,
+        }
+    }
+}
+
+impl<'a, Scene, Prod, Img, A, ShaderArgs, P> RendererBuilder<'a, Scene, Prod, Img, A, ShaderArgs, P>
+    where Scene: Sync, Prod: Produce<Triangle> + Sync + ?Sized, A: Acc,
+{
+    /// Sets the default ray color prior to shading (required).
+    pub fn scene_ray_color(
+        self,
+        f: impl Fn(&Scene, f32, usize) -> (A::In, ShaderArgs) + Sync + 'a,
+    ) -> Self {
+        RendererBuilder {scene_ray_color: Some(Box::new(f)), ..self}
+    }
+
+    /// Sets the shader.
+    pub fn shader(self, f: impl Fn(&mut A::In, ShaderData<'_, ShaderArgs>) + Sync + 'a) -> Self {
+        RendererBuilder {shader: Box::new(f), ..self}
+    }
+
+    /// Sets the transparency test of colors (required).
+    pub fn is_transparent(self, f: impl Fn(&A::In) -> bool + Sync + 'a) -> Self {
+        RendererBuilder {is_transparent: Some(Box::new(f)), ..self}
+    }
+
+    /// Sets the conversion from accumulator output to linear color (required).
+    pub fn acc_to_linear_rgba(self, f: impl Fn(A::Out) -> Rgba + Sync + 'a) -> Self {
+        RendererBuilder {acc_to_linear_rgba: Some(Box::new(f)), ..self}
+    }
+
+    /// Sets how to get the size of the image in pixels (required).
+    pub fn size(self, f: impl Fn(&Img) -> PixelPos + 'a) -> Self {
+        RendererBuilder {size: Some(Box::new(f)), ..self}
+    }
+
+    /// Sets how to write pixels to image (required).
+    pub fn pxl(self, f: impl FnMut(&mut Img, PixelPos, Rgba<u8>) + 'a) -> Self {
+        RendererBuilder {pxl: Some(Box::new(f)), ..self}
+    }
+
+    /// Sets the accumulator data (required).
+    pub fn acc_data(self, acc_data: A::Data) -> Self {
+        RendererBuilder {acc_data: Some(acc_data), ..self}
+    }
+
+    /// Sets scale or flip of axes.
+    pub fn flip_xyz(self, flip_xyz: Vector) -> Self {
+        RendererBuilder {flip_xyz, ..self}
+    }
+
+    /// Sets the render tile size.
+    pub fn tile_size(self, tile_size: u32) -> Self {
+        RendererBuilder {tile_size, ..self}
+    }
+
+    /// Sets the limit on the number of triangles per tile before doing adaptive sub-tiling.
+    pub fn sub_tile_triangle_limit(self, sub_tile_triangle_limit: u32) -> Self {
+        RendererBuilder {sub_tile_triangle_limit, ..self}
+    }
+
+    /// Sets whether to use adaptive sub-tiling.
+    pub fn sub_masks(self, sub_masks: bool) -> Self {
+        RendererBuilder {sub_masks, ..self}
+    }
+
+    /// Sets quadtree sub-tiling with a minimum leaf size in pixels.
+    ///
+    /// This replaces splitting tiles once into equal sub-tiles,
+    /// and works with any tile size.
+    pub fn quad_sub_tiles(self, min_size: u32) -> Self {
+        RendererBuilder {quad_min_size: Some(min_size), ..self}
+    }
+
+    /// Sets whether to refine masks with exact triangle tests.
+    pub fn exact_masks(self, exact_masks: bool) -> Self {
+        RendererBuilder {exact_masks, ..self}
+    }
+
+    /// Sets whether to use pre-masks.
+    pub fn pre_masks(self, pre_masks: bool) -> Self {
+        RendererBuilder {pre_masks, ..self}
+    }
+
+    /// Sets the limit of how many accumulations per tile.
+    pub fn acc_limit(self, acc_limit: u32) -> Self {
+        RendererBuilder {acc_limit, ..self}
+    }
+
+    /// Sets the scale ratio between pre-tile size and tile size.
+    pub fn scale_to_pre_tile_size(self, scale_to_pre_tile_size: u32) -> Self {
+        RendererBuilder {scale_to_pre_tile_size, ..self}
+    }
+
+    /// Sets edge overlay.
+    pub fn edges(self, edges: EdgeOverlay<'a, A::In>) -> Self {
+        RendererBuilder {edges: Some(edges), ..self}
+    }
+
+    /// Sets selection highlight.
+    pub fn selection(self, selection: Selection<'a>) -> Self {
+        RendererBuilder {selection: Some(selection), ..self}
+    }
+
+    /// Sets post-processing effects.
+    pub fn post(self, post: &'a [PostEffect]) -> Self {
+        RendererBuilder {post, ..self}
+    }
+
+    /// Sets dithering when quantizing colors to `u8`.
+    pub fn dither(self, dither: Dither) -> Self {
+        RendererBuilder {dither: Some(dither), ..self}
+    }
+
+    /// Sets fog volumes with varying density.
+    pub fn fog_volumes(self, fog_volumes: FogVolumes<'a, A::In>) -> Self {
+        RendererBuilder {fog_volumes: Some(fog_volumes), ..self}
+    }
+
+    /// Sets reflection and refraction ray tracing.
+    ///
+    /// Requires `ray_query`, such that the broad phase is not rebuilt every frame.
+    pub fn reflections(self, reflections: Reflections<'a, A::In>) -> Self {
+        RendererBuilder {reflections: Some(reflections), ..self}
+    }
+
+    /// Sets objects with bounds in world coordinates, culled before triangles.
+    pub fn cull_objects(self, cull_objects: &'a [CullObject]) -> Self {
+        RendererBuilder {cull_objects: Some(cull_objects), ..self}
+    }
+
+    /// Sets cached chunk AABBs of the producer in world coordinates.
+    ///
+    /// The ray query must be built over the producer.
+    pub fn ray_query(self, ray_query: &'a RayQuery) -> Self {
+        RendererBuilder {ray_query: Some(ray_query), ..self}
+    }
+
+    /// Enables profiling with profile data and hooks.
+    pub fn profile<Q>(
+        self,
+        profile: &'a mut Q,
+        profile_render: impl FnMut(&mut Q, Option<f64>) + 'a,
+        profile_compress: impl FnMut(&mut Q, ProfileCompressData, Option<f64>) + 'a,
+    ) -> RendererBuilder<'a, Scene, Prod, Img, A, ShaderArgs, Q> {
+        let RendererBuilder {
+            scene, producer, img, persp, cam, scene_ray_color, shader, is_transparent,
+            acc_to_linear_rgba, size, pxl, acc_data, flip_xyz, tile_size,
+            sub_tile_triangle_limit, sub_masks, quad_min_size, exact_masks, pre_masks,
+            acc_limit, scale_to_pre_tile_size, edges, selection, post, dither, fog_volumes,
+            reflections, cull_objects, ray_query,
+            profile: _, profile_render: _, profile_compress: _, profile_enabled: _,
+        } = self;
+        RendererBuilder {
+            scene, producer, img, persp, cam, scene_ray_color, shader, is_transparent,
+            acc_to_linear_rgba, size, pxl, acc_data, flip_xyz, tile_size,
+            sub_tile_triangle_limit, sub_masks, quad_min_size, exact_masks, pre_masks,
+            acc_limit, scale_to_pre_tile_size, edges, selection, post, dither, fog_volumes,
+            reflections, cull_objects, ray_query,
+            profile,
+            profile_render: Box::new(profile_render),
+            profile_compress: Box::new(profile_compress),
+            profile_enabled: true,
+        }
+    }
+
+    /// Checks settings for errors.
+    pub fn validate(&self) -> Result<(), RendererError> {
+        use RendererError::*;
+
+        if self.scene_ray_color.is_none() {return Err(Missing("scene_ray_color"))};
+        if self.is_transparent.is_none() {return Err(Missing("is_transparent"))};
+        if self.acc_to_linear_rgba.is_none() {return Err(Missing("acc_to_linear_rgba"))};
+        if self.size.is_none() {return Err(Missing("size"))};
+        if self.pxl.is_none() {return Err(Missing("pxl"))};
+        if self.acc_data.is_none() {return Err(Missing("acc_data"))};
+        if self.reflections.is_some() && self.ray_query.is_none() {return Err(Missing("ray_query"))};
+        if self.tile_size == 0 {return Err(ZeroTileSize)};
+        if self.sub_masks {
+            if self.quad_min_size.is_none() && optimal_sub_tile_size(self.tile_size) != self.tile_size {
+                return Err(UnsupportedSubTileSize(self.tile_size));
+            }
+            if self.sub_tile_triangle_limit == 0 {return Err(ZeroSubTileTriangleLimit)};
+        }
+        if self.pre_masks && self.scale_to_pre_tile_size == 0 {return Err(ZeroPreTileScale)};
+        if self.acc_limit == 0 {return Err(ZeroAccLimit)};
+        if let Some(capacity) = self.acc_data.as_ref().and_then(A::tile_capacity) &&
+           capacity < self.tile_size
+        {
+            return Err(AccTileCapacity {capacity, tile_size: self.tile_size});
+        }
+        Ok(())
+    }
+
+    /// Builds the renderer, allocating mask buffers from image size and tile size.
+    ///
+    /// Returns the renderer and the tile size to render with.
+    pub fn build(
+        self,
+        buffers: &'a mut RenderBuffers,
+    ) -> Result<BuiltRenderer<'a, Scene, Prod, Img, A, ShaderArgs, P>, RendererError> {
+        self.validate()?;
+
+        let RendererBuilder {
+            scene, producer, img, persp, cam, scene_ray_color, shader, is_transparent,
+            acc_to_linear_rgba, size, pxl, acc_data, flip_xyz, tile_size,
+            sub_tile_triangle_limit, sub_masks, quad_min_size, exact_masks, pre_masks,
+            acc_limit, scale_to_pre_tile_size, edges, selection, post, dither, fog_volumes,
+            reflections, cull_objects, ray_query,
+            profile, profile_render, profile_compress, profile_enabled,
+        } = self;
+        // Unwrapping is safe, since required settings are validated.
+        let size = size.unwrap();
+        buffers.resize(size(img), tile_size, scale_to_pre_tile_size.max(1));
+        let RenderBuffers {compr_masks, pre_compr_masks, sub_compr_masks, quad_compr_masks} = buffers;
+        Ok((Renderer {
+            scene,
+            scene_ray_color: scene_ray_color.unwrap(),
+            shader,
+            is_transparent: is_transparent.unwrap(),
+            acc_to_linear_rgba: acc_to_linear_rgba.unwrap(),
+            producer,
+            img,
+            size,
+            pxl: pxl.unwrap(),
+            acc_data: acc_data.unwrap(),
+            persp,
+            cam,
+            flip_xyz,
+            compr_masks,
+            pre_compr_masks,
+            sub_compr_masks,
+            quad_compr_masks: quad_min_size.map(|_| quad_compr_masks),
+            sub_tile_triangle_limit,
+            profile,
+            profile_render,
+            profile_compress,
+            sub_masks,
+            quad_min_size,
+            exact_masks,
+            pre_masks,
+            profile_enabled,
+            acc_limit,
+            scale_to_pre_tile_size,
+            edges,
+            selection,
+            post,
+            dither,
+            fog_volumes,
+            reflections,
+            cull_objects,
+            ray_query,
+        }, tile_size))
+    }
+
+    /// Builds the renderer and renders.
+    pub fn render(self, buffers: &'a mut RenderBuffers) -> Result<(), RendererError> {
+        let (renderer, tile_size) = self.build(buffers)?;
+        renderer.render_with_tile_size(tile_size);
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! # Selection highlight
+//!
+//! Highlights selected objects in rendered images, e.g. in editors.
+//!
+//! The selection is a set of internal addresses of the producer,
+//! stored as compressed masks with one bit per internal address.
+//! For example, when the producer generates triangles from voxels,
+//! each bit selects a voxel.
+//!
+//! The highlight is a post-process on the whole image in linear color space,
+//! using the internal address of the closest visible hit per pixel.
+//! Since outlines might cross render tiles, this runs after all tiles are accumulated.
+
+use crate::{PixelPos, Rgba};
+use crate::mask::CompressedMasks;
+
+/// Selection highlight settings.
+#[derive(Copy, Clone, Debug)]
+pub struct Selection<'a> {
+    /// Selected internal addresses, one bit per internal address.
+    pub set: &'a CompressedMasks,
+    /// The outline color (`None` to disable outline).
+    pub outline: Option<Rgba>,
+    /// The outline width in pixels.
+    pub outline_width: f32,
+    /// The fill tint color, where alpha is the tint strength (`None` to disable fill).
+    pub fill: Option<Rgba>,
+}
+
+impl<'a> Selection<'a> {
+    /// Creates selection with an outline color and width.
+    pub fn outline(set: &'a CompressedMasks, color: Rgba, width: f32) -> Selection<'a> {
+        Selection {set, outline: Some(color), outline_width: width, fill: None}
+    }
+}
+
+/// Decompresses selection set into words, for fast lookup.
+pub fn selection_words(set: &CompressedMasks) -> Vec<u64> {
+    let mut words = vec![0; set.len()];
+    for (i, w) in set.iter() {words[i] = w}
+    words
+}
+
+/// Returns `true` if internal address is selected, using decompressed words.
+pub fn selection_contains(words: &[u64], internal_offset: usize) -> bool {
+    words.get(internal_offset / 64).is_some_and(|w| (w >> (internal_offset % 64)) & 1 == 1)
+}
+
+/// Highlights selection in image of linear colors.
+///
+/// `ids` stores the internal address of the closest visible hit per pixel.
+/// Both `ids` and `colors` are stored row by row, with size `dim`.
+///
+/// Outlines are drawn outside selected pixels with anti-aliasing,
+/// using the distance to the closest selected pixel.
+pub fn selection_highlight(
+    dim: PixelPos,
+    ids: &[Option<usize>],
+    colors: &mut [Rgba],
+    selection: &Selection,
+) {
+    let [w, h] = dim;
+    let words = selection_words(selection.set);
+    let selected: Vec<bool> = ids.iter()
+        .map(|id| id.is_some_and(|id| selection_contains(&words, id)))
+        .collect();
+
+    if let Some(fill) = selection.fill {
+        for (c, _) in colors.iter_mut().zip(&selected).filter(|(_, s)| **s) {
+            for i in 0..3 {c[i] += (fill[i] - c[i]) * fill[3]}
+        }
+    }
+
+    let Some(outline) = selection.outline else {return};
+    let width = selection.outline_width;
+    let r = (width + 1.0).ceil() as i64;
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            let k = (y * w as i64 + x) as usize;
+            if selected[k] {continue};
+
+            // Find distance to the closest selected pixel.
+            let mut d2 = f32::INFINITY;
+            for j in (y - r).max(0)..(y + r + 1).min(h as i64) {
+                for i in (x - r).max(0)..(x + r + 1).min(w as i64) {
+                    if !selected[(j * w as i64 + i) as usize] {continue};
+
+                    let (dx, dy) = ((i - x) as f32, (j - y) as f32);
+                    d2 = d2.min(dx * dx + dy * dy);
+                }
+            }
+            // The selection boundary is half a pixel from selected pixel centers.
+            let coverage = (width + 1.0 - d2.sqrt()).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                let c = &mut colors[k];
+                for i in 0..4 {c[i] += (outline[i] - c[i]) * coverage}
+            }
+        }
+    }
+}
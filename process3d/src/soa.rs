@@ -0,0 +1,339 @@
+//! # Structure-of-arrays algorithms
+//!
+//! A triangle chunk is stored as an array of triangles,
+//! which is convenient for producers, but slow when testing many triangles at once.
+//! By converting a chunk into a structure of arrays,
+//! the same component of neighboring triangles is stored next to each other in memory.
+//!
+//! The algorithms in this module process `LANES` triangles at a time,
+//! using straight-line code without branches, so the compiler can vectorize
+//! on any target without relying on platform specific instructions.
+//! When a mask has fewer bits enabled than `LANES`, a scalar fallback is used.
+//!
+//! The results are identical to the scalar algorithms,
+//! since the same floating point operations are performed in the same order.
+//! This preserves determinism for distributed computing.
+//!
+//! Converting a chunk costs about the same as testing a single ray against it,
+//! so it pays off when many rays are tested against the same chunk,
+//! e.g. when rendering a tile.
+
+use crate::{Chunk, IndexFlag, Point, Ray, RayHit, RayHitAll, Triangle, Vector};
+use crate::frustrum::FrustumPlanes;
+use crate::ray::{ray_hit_all_chunk_mask, ray_hit_offset, ray_triangle_edges_hit};
+
+/// The number of triangles processed at a time.
+pub const LANES: usize = 8;
+
+/// Triangle chunk in structure-of-arrays layout.
+///
+/// The edges are pre-computed, since they do not depend on the ray.
+#[derive(Clone, Debug)]
+pub struct TriangleChunkSoa {
+    /// The first corner of triangles, per axis.
+    pub a: [Chunk<f32>; 3],
+    /// The edge from first to second corner of triangles, per axis.
+    pub e1: [Chunk<f32>; 3],
+    /// The edge from first to third corner of triangles, per axis.
+    pub e2: [Chunk<f32>; 3],
+}
+
+/// AABB chunk in structure-of-arrays layout.
+#[derive(Clone, Debug)]
+pub struct AabbChunkSoa {
+    /// The minimum corner of AABBs, per axis.
+    pub min: [Chunk<f32>; 3],
+    /// The maximum corner of AABBs, per axis.
+    pub max: [Chunk<f32>; 3],
+}
+
+/// Converts triangle chunk into structure-of-arrays layout.
+pub fn triangle_chunk_soa(chunk: &Chunk<Triangle>) -> TriangleChunkSoa {
+    use vecmath::vec3_sub as sub;
+
+    let mut soa = TriangleChunkSoa {
+        a: [[0.0; 64]; 3],
+        e1: [[0.0; 64]; 3],
+        e2: [[0.0; 64]; 3],
+    };
+    for (i, &(a, b, c)) in chunk.iter().enumerate() {
+        let e1 = sub(b, a);
+        let e2 = sub(c, a);
+        for k in 0..3 {
+            soa.a[k][i] = a[k];
+            soa.e1[k][i] = e1[k];
+            soa.e2[k][i] = e2[k];
+        }
+    }
+    soa
+}
+
+/// Converts triangle chunk into AABB chunk in structure-of-arrays layout.
+pub fn triangle_chunk_aabb_soa(chunk: &Chunk<Triangle>) -> AabbChunkSoa {
+    use crate::triangle::triangle_aabb;
+
+    let mut soa = AabbChunkSoa {
+        min: [[0.0; 64]; 3],
+        max: [[0.0; 64]; 3],
+    };
+    for (i, &tri) in chunk.iter().enumerate() {
+        let (mi, ma) = triangle_aabb(tri);
+        for k in 0..3 {
+            soa.min[k][i] = mi[k];
+            soa.max[k][i] = ma[k];
+        }
+    }
+    soa
+}
+
+/// Gets triangle at index as a corner and two edges.
+#[inline(always)]
+fn soa_edges(soa: &TriangleChunkSoa, i: usize) -> (Point, Vector, Vector) {
+    (
+        [soa.a[0][i], soa.a[1][i], soa.a[2][i]],
+        [soa.e1[0][i], soa.e1[1][i], soa.e1[2][i]],
+        [soa.e2[0][i], soa.e2[1][i], soa.e2[2][i]],
+    )
+}
+
+/// Ray intersection of `LANES` triangles starting at `start`,
+/// using Möller-Trumbore algorithm.
+///
+/// Writes the ray parameter to `t` and returns a bit mask of hits.
+///
+/// The comparisons are written the same way as in `ray_triangle_edges_hit`,
+/// such that not-a-number values are treated the same.
+#[inline(always)]
+#[allow(clippy::manual_range_contains)]
+fn ray_triangle_lanes_hit(
+    (origin, dir): Ray,
+    soa: &TriangleChunkSoa,
+    start: usize,
+    t: &mut [f32; LANES],
+) -> u8 {
+    let eps = f32::EPSILON;
+    let [ax, ay, az] = &soa.a;
+    let [e1x, e1y, e1z] = &soa.e1;
+    let [e2x, e2y, e2z] = &soa.e2;
+    let mut hits: u8 = 0;
+    for (k, t) in t.iter_mut().enumerate() {
+        let i = start + k;
+
+        // `cross(dir, e2)`.
+        let px = dir[1] * e2z[i] - dir[2] * e2y[i];
+        let py = dir[2] * e2x[i] - dir[0] * e2z[i];
+        let pz = dir[0] * e2y[i] - dir[1] * e2x[i];
+        let det = e1x[i] * px + e1y[i] * py + e1z[i] * pz;
+        let inv_det = 1.0 / det;
+
+        // `sub(origin, a)`.
+        let sx = origin[0] - ax[i];
+        let sy = origin[1] - ay[i];
+        let sz = origin[2] - az[i];
+        let u = inv_det * (sx * px + sy * py + sz * pz);
+
+        // `cross(s, e1)`.
+        let qx = sy * e1z[i] - sz * e1y[i];
+        let qy = sz * e1x[i] - sx * e1z[i];
+        let qz = sx * e1y[i] - sy * e1x[i];
+        let v = inv_det * (dir[0] * qx + dir[1] * qy + dir[2] * qz);
+        let tk = inv_det * (e2x[i] * qx + e2y[i] * qy + e2z[i] * qz);
+
+        let parallel = (det > -eps) & (det < eps);
+        let outside_u = (u < 0.0) | (u > 1.0);
+        let outside_v = (v < 0.0) | (u + v > 1.0);
+        let hit = !parallel & !outside_u & !outside_v & (tk > eps);
+        *t = tk;
+        hits |= (hit as u8) << k;
+    }
+    hits
+}
+
+/// Ray intersection against a triangle chunk in structure-of-arrays layout with a mask.
+///
+/// Gives the same result as `ray_triangle_chunk_hit`.
+pub fn ray_triangle_chunk_soa_hit(
+    ray: Ray,
+    soa: &TriangleChunkSoa,
+    mask: u64,
+) -> RayHit {
+    if mask == 0 {return None};
+
+    let mut min: RayHit = None;
+    if mask.count_ones() < LANES as u32 {
+        let mut bits = mask;
+        while bits != 0 {
+            let i = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            let (a, e1, e2) = soa_edges(soa, i);
+            if let Some(t) = ray_triangle_edges_hit(ray, a, e1, e2) &&
+               (min.is_none() || t < min.unwrap().0) {min = Some((t, i))}
+        }
+        return min;
+    }
+
+    let mut t = [0.0; LANES];
+    for start in (0..64).step_by(LANES) {
+        let lane_mask = (mask >> start) as u8;
+        if lane_mask == 0 {continue};
+
+        let mut hits = ray_triangle_lanes_hit(ray, soa, start, &mut t) & lane_mask;
+        while hits != 0 {
+            let k = hits.trailing_zeros() as usize;
+            hits &= hits - 1;
+            if min.is_none() || t[k] < min.unwrap().0 {min = Some((t[k], start + k))}
+        }
+    }
+    min
+}
+
+/// Ray intersection against all triangles in chunk in structure-of-arrays layout with a mask.
+///
+/// Gives the same result as `ray_triangle_chunk_hit_all`.
+pub fn ray_triangle_chunk_soa_hit_all(
+    ray: Ray,
+    soa: &TriangleChunkSoa,
+    mask: u64,
+) -> RayHit {
+    if mask == 0 {return None};
+
+    if mask.count_ones() < LANES as u32 {
+        let mut bits = mask;
+        while bits != 0 {
+            let i = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            let (a, e1, e2) = soa_edges(soa, i);
+            if let Some(t) = ray_triangle_edges_hit(ray, a, e1, e2) {
+                return Some((t, i));
+            }
+        }
+        return None;
+    }
+
+    let mut t = [0.0; LANES];
+    for start in (0..64).step_by(LANES) {
+        let lane_mask = (mask >> start) as u8;
+        if lane_mask == 0 {continue};
+
+        let hits = ray_triangle_lanes_hit(ray, soa, start, &mut t) & lane_mask;
+        if hits != 0 {
+            let k = hits.trailing_zeros() as usize;
+            return Some((t[k], start + k));
+        }
+    }
+    None
+}
+
+/// Ray hit of triangle chunk in structure-of-arrays layout with mask, updating hit.
+///
+/// Gives the same result as `ray_triangle_chunk_hit_update`.
+pub fn ray_triangle_chunk_soa_hit_update(
+    ray: Ray,
+    soa: &TriangleChunkSoa,
+    mask: u64,
+    off: usize,
+    res: &mut RayHit,
+) {
+    *res = match (*res, ray_hit_offset(ray_triangle_chunk_soa_hit(ray, soa, mask), off)) {
+        (None, x) | (x, None) => x,
+        (Some((ti, mi)), Some((tj, mj))) => {
+            if tj < ti {Some((tj, mj))} else {Some((ti, mi))}
+        }
+    }
+}
+
+/// Ray hit of all triangles in chunk in structure-of-arrays layout with mask, updating hit.
+///
+/// Gives the same result as `ray_triangle_chunk_hit_all_update`.
+pub fn ray_triangle_chunk_soa_hit_all_update(
+    ray: Ray,
+    soa: &TriangleChunkSoa,
+    mask: u64,
+    off: usize,
+    res: &mut RayHitAll,
+) {
+    let mask = ray_hit_all_chunk_mask(*res, mask, off);
+    if mask == 0 {return};
+    if let Some((tj, mj)) = ray_hit_offset(ray_triangle_chunk_soa_hit_all(ray, soa, mask), off) {
+        *res = Some((tj, IndexFlag::from_parts(mj, true)));
+    }
+}
+
+/// Generate bit mask for AABB chunk in structure-of-arrays layout
+/// where AABB intersects frustum planes.
+///
+/// Uses existing bits to avoid processing AABBs that are not needed.
+///
+/// Gives the same result as `frustum_planes_triangle_chunk_mask`,
+/// when the AABB chunk is computed from the triangle chunk.
+pub fn frustum_planes_aabb_chunk_soa_mask(
+    fr: &FrustumPlanes,
+    soa: &AabbChunkSoa,
+    bits: u64
+) -> u64 {
+    if bits == 0 {return 0};
+
+    let planes = [fr.near, fr.far, fr.left, fr.right, fr.top, fr.bottom];
+    let mut mask: u64 = 0;
+    for start in (0..64).step_by(LANES) {
+        if (bits >> start) as u8 == 0 {continue};
+
+        let mut inside: u8 = !0;
+        for (n, d) in planes {
+            // The corner furthest along the plane normal decides whether
+            // any corner of the AABB is in front of the plane.
+            let px = if n[0] >= 0.0 {&soa.max[0]} else {&soa.min[0]};
+            let py = if n[1] >= 0.0 {&soa.max[1]} else {&soa.min[1]};
+            let pz = if n[2] >= 0.0 {&soa.max[2]} else {&soa.min[2]};
+            let mut front: u8 = 0;
+            for k in 0..LANES {
+                let i = start + k;
+                let d2 = n[0] * px[i] + n[1] * py[i] + n[2] * pz[i] + d;
+                front |= ((d2 >= 0.0) as u8) << k;
+            }
+            inside &= front;
+        }
+        mask |= (inside as u64) << start;
+    }
+    mask & bits
+}
+
+/// Generate bit mask for triangle chunk where AABB intersects frustum planes.
+///
+/// Uses existing bits to avoid processing triangles that are not needed.
+///
+/// This is used when the triangle chunk is tested against a single frustum.
+/// The conversion is only worth it when most bits are enabled,
+/// otherwise the scalar algorithm is used.
+/// Gives the same result as `frustum_planes_triangle_chunk_mask`.
+pub fn frustum_planes_triangle_chunk_soa_mask(
+    fr: &FrustumPlanes,
+    chunk: &Chunk<Triangle>,
+    bits: u64
+) -> u64 {
+    use crate::frustrum::frustum_planes_triangle_chunk_mask;
+    use crate::triangle::triangle_aabb;
+
+    if bits.count_ones() < 32 {
+        return frustum_planes_triangle_chunk_mask(fr, chunk, bits);
+    }
+
+    // Only convert the lanes that are tested.
+    let mut soa = AabbChunkSoa {
+        min: [[0.0; 64]; 3],
+        max: [[0.0; 64]; 3],
+    };
+    for start in (0..64).step_by(LANES) {
+        if (bits >> start) as u8 == 0 {continue};
+
+        for (i, &tri) in chunk[start..start + LANES].iter().enumerate() {
+            let i = start + i;
+            let (mi, ma) = triangle_aabb(tri);
+            for k in 0..3 {
+                soa.min[k][i] = mi[k];
+                soa.max[k][i] = ma[k];
+            }
+        }
+    }
+    frustum_planes_aabb_chunk_soa_mask(fr, &soa, bits)
+}
@@ -0,0 +1,151 @@
+//! # STL format
+//!
+//! Reads triangles into any consumer and writes triangles from any producer,
+//! using either binary or ASCII STL.
+//!
+//! STL has no standard for materials.
+//! Binary STL stores a 16 bit attribute per triangle,
+//! which some tools use for colors, so this is used as the material.
+//! ASCII STL has no attribute, so the material is always zero.
+
+use std::io::{self, Read, Write};
+
+use crate::{Triangle, Vector};
+use crate::consume::Consumer;
+use crate::produce::Produce;
+
+fn invalid(msg: String) -> io::Error {io::Error::new(io::ErrorKind::InvalidData, msg)}
+
+/// Reads triangles from binary or ASCII STL format into consumer.
+///
+/// The format is detected automatically.
+/// The material is the attribute of each triangle in binary STL,
+/// or zero in ASCII STL.
+pub fn stl_read<R, C>(mut r: R, consumer: &mut C) -> io::Result<()>
+    where R: Read, C: Consumer<(Triangle, u16)>
+{
+    let mut data = vec![];
+    r.read_to_end(&mut data)?;
+
+    // Binary files might start with `solid` too, so check the size first.
+    let binary = data.len() >= 84 && {
+        let n = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        data.len() == 84 + 50 * n
+    };
+    if binary || !data.starts_with(b"solid") {
+        stl_read_binary(&data, consumer)
+    } else {
+        let text = std::str::from_utf8(&data)
+            .map_err(|_| invalid("Expected UTF-8 in ASCII STL".into()))?;
+        stl_read_ascii(text, consumer)
+    }
+}
+
+fn stl_read_binary<C>(data: &[u8], consumer: &mut C) -> io::Result<()>
+    where C: Consumer<(Triangle, u16)>
+{
+    if data.len() < 84 {return Err(invalid("Expected 84 byte header in binary STL".into()))};
+
+    let n = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    if data.len() < 84 + 50 * n {
+        return Err(invalid(format!("Expected {} triangles in binary STL", n)));
+    }
+    let f = consumer.consumer();
+    for rec in data[84..84 + 50 * n].chunks_exact(50) {
+        let float = |i: usize| {
+            let i = 12 + i * 4;
+            f32::from_le_bytes([rec[i], rec[i + 1], rec[i + 2], rec[i + 3]])
+        };
+        let tri = (
+            [float(0), float(1), float(2)],
+            [float(3), float(4), float(5)],
+            [float(6), float(7), float(8)],
+        );
+        f(consumer, (tri, u16::from_le_bytes([rec[48], rec[49]])));
+    }
+    Ok(())
+}
+
+fn stl_read_ascii<C>(text: &str, consumer: &mut C) -> io::Result<()>
+    where C: Consumer<(Triangle, u16)>
+{
+    let f = consumer.consumer();
+    let mut vertices = vec![];
+    for (line_nr, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let mut p = [0.0; 3];
+                for x in &mut p {
+                    *x = words.next().and_then(|w| w.parse().ok()).ok_or_else(||
+                        invalid(format!("Line {}: Expected vertex coordinate", line_nr + 1)))?;
+                }
+                vertices.push(p);
+            }
+            Some("endfacet") => {
+                if vertices.len() != 3 {
+                    return Err(invalid(format!("Line {}: Expected 3 vertices", line_nr + 1)));
+                }
+                f(consumer, ((vertices[0], vertices[1], vertices[2]), 0));
+                vertices.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Calculates the normal of triangle for STL, using zero for degenerate triangles.
+fn stl_normal(tri: Triangle) -> Vector {
+    use crate::triangle::triangle_plane;
+
+    let (n, _) = triangle_plane(tri);
+    if n.iter().all(|x| x.is_finite()) {n} else {[0.0; 3]}
+}
+
+/// Writes triangles from producer in binary STL format.
+///
+/// The attribute is computed from the internal address of each triangle.
+pub fn stl_write_binary<W, T>(
+    w: &mut W,
+    list: &T,
+    attribute: impl Fn(Option<usize>) -> u16,
+) -> io::Result<()>
+    where W: Write, T: Produce<Triangle> + ?Sized
+{
+    use crate::produce::produce_iter;
+
+    let n = u32::try_from(list.virtual_length())
+        .map_err(|_| invalid("Too many triangles for binary STL".into()))?;
+    w.write_all(&[0; 80])?;
+    w.write_all(&n.to_le_bytes())?;
+    for (off, tri) in produce_iter(list) {
+        let (a, b, c) = tri;
+        for p in [stl_normal(tri), a, b, c] {
+            for x in p {w.write_all(&x.to_le_bytes())?}
+        }
+        w.write_all(&attribute(list.to_internal(off)).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes triangles from producer in ASCII STL format.
+pub fn stl_write_ascii<W, T>(w: &mut W, name: &str, list: &T) -> io::Result<()>
+    where W: Write, T: Produce<Triangle> + ?Sized
+{
+    use crate::produce::produce_iter;
+
+    writeln!(w, "solid {}", name)?;
+    for (_, tri) in produce_iter(list) {
+        let (a, b, c) = tri;
+        let n = stl_normal(tri);
+        writeln!(w, "  facet normal {} {} {}", n[0], n[1], n[2])?;
+        writeln!(w, "    outer loop")?;
+        for p in [a, b, c] {
+            writeln!(w, "      vertex {} {} {}", p[0], p[1], p[2])?;
+        }
+        writeln!(w, "    endloop")?;
+        writeln!(w, "  endfacet")?;
+    }
+    writeln!(w, "endsolid {}", name)
+}
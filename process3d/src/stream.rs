@@ -0,0 +1,342 @@
+//! # Out-of-core streaming
+//!
+//! Streams triangles from disk, e.g. for photogrammetry scenes that do not fit in memory.
+//!
+//! The chunk file format stores triangles in chunks of 64, matching `Chunk<Triangle>`,
+//! such that producing a chunk reads one contiguous block of the file.
+//!
+//! - An 8 byte magic `TURBCHK1`
+//! - The number of triangles as little endian `u64`
+//! - Chunks of 64 triangles, each triangle as 9 little endian `f32`
+//! - The chunk index, with the AABB per chunk as 6 little endian `f32`, minimum before maximum
+//!
+//! The last chunk is padded with zeroes, which are not included in its AABB.
+//! The index follows the chunks, such that files can be written in one pass.
+//!
+//! A `ChunkFile` produces triangles from a chunk file,
+//! keeping the least recently used chunks in a cache of bounded size.
+//! Since producing can not fail, the first read error is stored,
+//! while failed chunks produce degenerate triangles.
+//! Check for errors with `ChunkFile::error` after processing.
+//!
+//! The index is kept in memory when opening the file.
+//! Use `ChunkFile::ray_query` to cull chunks with the renderer, see `Renderer::ray_query`,
+//! such that triangles are only read for chunks that overlap a tile.
+//! Without it, computing tile masks produces every chunk once per tile,
+//! and with a cache smaller than the scene, every tile reads the whole file in turn.
+//! Rendering produces each chunk once per tile that overlaps it,
+//! so the cache should hold at least the chunks visible in a tile row.
+//! Reads share one lock on the reader, since seeking and reading is not atomic,
+//! so cache misses are serialized across threads.
+//! Triangles that are near in space should be near in the file,
+//! which makes chunks more likely to be culled as a whole.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::{Aabb, Chunk, Triangle};
+use crate::consume::Consumer;
+use crate::produce::Produce;
+use crate::query::RayQuery;
+
+/// The magic bytes at the start of chunk files.
+pub const CHUNK_FILE_MAGIC: [u8; 8] = *b"TURBCHK1";
+/// The size of chunk file header in bytes.
+pub const CHUNK_FILE_HEADER_BYTES: u64 = 16;
+/// The size of a chunk of 64 triangles in chunk files, in bytes.
+pub const CHUNK_FILE_CHUNK_BYTES: usize = 64 * 9 * 4;
+/// The size of the AABB per chunk in the chunk file index, in bytes.
+pub const CHUNK_FILE_INDEX_BYTES: usize = 6 * 4;
+
+fn invalid(msg: String) -> io::Error {io::Error::new(io::ErrorKind::InvalidData, msg)}
+
+fn encode_chunk(chunk: &Chunk<Triangle>, buf: &mut [u8]) {
+    let floats = chunk.iter().flat_map(|&(a, b, c)| a.into_iter().chain(b).chain(c));
+    for (bytes, x) in buf.chunks_exact_mut(4).zip(floats) {bytes.copy_from_slice(&x.to_le_bytes())}
+}
+
+fn decode_f32(buf: &[u8], i: usize) -> f32 {
+    f32::from_le_bytes([buf[i * 4], buf[i * 4 + 1], buf[i * 4 + 2], buf[i * 4 + 3]])
+}
+
+fn decode_chunk(buf: &[u8]) -> Chunk<Triangle> {
+    let p = |i: usize| [decode_f32(buf, i), decode_f32(buf, i + 1), decode_f32(buf, i + 2)];
+    std::array::from_fn(|i| (p(i * 9), p(i * 9 + 3), p(i * 9 + 6)))
+}
+
+fn write_index<W: Write>(w: &mut W, aabbs: &[Aabb]) -> io::Result<()> {
+    for &(mi, ma) in aabbs {
+        for x in mi.into_iter().chain(ma) {w.write_all(&x.to_le_bytes())?}
+    }
+    Ok(())
+}
+
+fn decode_index(buf: &[u8]) -> Vec<Aabb> {
+    buf.chunks_exact(CHUNK_FILE_INDEX_BYTES)
+        .map(|b| ([0, 1, 2].map(|i| decode_f32(b, i)), [3, 4, 5].map(|i| decode_f32(b, i))))
+        .collect()
+}
+
+/// Writes triangles from producer in chunk file format.
+pub fn chunk_file_write<W, T>(w: &mut W, list: &T) -> io::Result<()>
+    where W: Write, T: Produce<Triangle> + ?Sized
+{
+    use crate::produce::init_chunk_mask;
+    use crate::triangle::triangle_chunk_aabb;
+
+    let n = list.virtual_length();
+    w.write_all(&CHUNK_FILE_MAGIC)?;
+    w.write_all(&(n as u64).to_le_bytes())?;
+    let mut buf = vec![0; CHUNK_FILE_CHUNK_BYTES];
+    let mut aabbs = Vec::with_capacity(n.div_ceil(64));
+    for off in (0..n).step_by(64) {
+        let mut chunk = list.produce(off);
+        let mask = init_chunk_mask(n, off);
+        for (i, tri) in chunk.iter_mut().enumerate() {
+            if (mask >> i) & 1 == 0 {*tri = Default::default()};
+        }
+        aabbs.extend(triangle_chunk_aabb(&chunk, mask));
+        encode_chunk(&chunk, &mut buf);
+        w.write_all(&buf)?;
+    }
+    write_index(w, &aabbs)
+}
+
+/// Writes consumed triangles in chunk file format, one chunk at a time.
+///
+/// Materials are kept in memory, indexed by the internal address of each triangle,
+/// which is the offset in the chunk file.
+/// Use `()` as material type to discard materials.
+///
+/// Since consumers can not fail, the first write error is stored and returned by `finish`.
+pub struct ChunkWriter<W, Material = ()> {
+    w: W,
+    chunk: Chunk<Triangle>,
+    len: usize,
+    aabbs: Vec<Aabb>,
+    error: Option<io::Error>,
+    /// The materials of consumed triangles.
+    pub materials: Vec<Material>,
+}
+
+impl<W: Write + Seek, Material> ChunkWriter<W, Material> {
+    /// Creates a new chunk writer, writing the header.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        w.write_all(&CHUNK_FILE_MAGIC)?;
+        w.write_all(&0_u64.to_le_bytes())?;
+        Ok(ChunkWriter {
+            w,
+            chunk: [Default::default(); 64],
+            len: 0,
+            aabbs: vec![],
+            error: None,
+            materials: vec![],
+        })
+    }
+
+    /// Writes a triangle.
+    pub fn write(&mut self, tri: Triangle) -> io::Result<()> {
+        self.chunk[self.len % 64] = tri;
+        self.len += 1;
+        if self.len.is_multiple_of(64) {self.flush_chunk()?};
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        use crate::produce::init_chunk_mask;
+        use crate::triangle::triangle_chunk_aabb;
+
+        let mask = init_chunk_mask(self.len, (self.len - 1) / 64 * 64);
+        self.aabbs.extend(triangle_chunk_aabb(&self.chunk, mask));
+        let mut buf = vec![0; CHUNK_FILE_CHUNK_BYTES];
+        encode_chunk(&self.chunk, &mut buf);
+        self.chunk = [Default::default(); 64];
+        self.w.write_all(&buf)
+    }
+
+    /// Writes the last chunk, the chunk index and the number of triangles,
+    /// returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {return Err(err)};
+        if !self.len.is_multiple_of(64) {self.flush_chunk()?};
+        write_index(&mut self.w, &self.aabbs)?;
+        self.w.seek(SeekFrom::Start(CHUNK_FILE_MAGIC.len() as u64))?;
+        self.w.write_all(&(self.len as u64).to_le_bytes())?;
+        self.w.seek(SeekFrom::End(0))?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<W: Write + Seek, Material> Consumer<(Triangle, Material)> for ChunkWriter<W, Material> {
+    fn consumer(&self) -> crate::Consume<Self, (Triangle, Material)> {
+        |s, (tri, mat)| {
+            if s.error.is_some() {return};
+            s.materials.push(mat);
+            if let Err(err) = s.write(tri) {s.error = Some(err)};
+        }
+    }
+}
+
+/// Stores cached chunks, with least recently used order.
+struct ChunkCache {
+    chunks: HashMap<usize, (u64, Chunk<Triangle>)>,
+    order: BTreeMap<u64, usize>,
+    tick: u64,
+    stats: ChunkCacheStats,
+}
+
+/// Stores statistics of chunk cache.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkCacheStats {
+    /// The number of chunks produced from cache.
+    pub hits: usize,
+    /// The number of chunks read from disk.
+    pub misses: usize,
+    /// The number of chunks removed from cache.
+    pub evictions: usize,
+}
+
+/// Produces triangles streamed from a chunk file, with a bounded chunk cache.
+///
+/// The internal address of each triangle is the offset in the chunk file.
+/// Reads from disk are serialized by a lock on the reader.
+pub struct ChunkFile<R = File> {
+    len: usize,
+    aabbs: Vec<Aabb>,
+    capacity: usize,
+    source: Mutex<R>,
+    cache: Mutex<ChunkCache>,
+    error: Mutex<Option<io::Error>>,
+}
+
+impl ChunkFile {
+    /// Opens chunk file, caching up to some number of chunks.
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<Self> {
+        ChunkFile::new(File::open(path)?, capacity)
+    }
+}
+
+impl<R: Read + Seek> ChunkFile<R> {
+    /// Creates a new chunk file producer from reader, caching up to some number of chunks.
+    ///
+    /// Reads the header and the chunk index,
+    /// and checks that the reader contains all chunks.
+    pub fn new(mut source: R, capacity: usize) -> io::Result<Self> {
+        let mut header = [0; CHUNK_FILE_HEADER_BYTES as usize];
+        source.seek(SeekFrom::Start(0))?;
+        source.read_exact(&mut header)?;
+        if header[..8] != CHUNK_FILE_MAGIC {return Err(invalid("Expected chunk file magic".into()))};
+
+        let len = u64::from_le_bytes(header[8..].try_into().unwrap());
+        let expected = || invalid(format!("Expected {} triangles in chunk file", len));
+        let chunks = len.div_ceil(64);
+        let index_pos = chunks.checked_mul(CHUNK_FILE_CHUNK_BYTES as u64)
+            .and_then(|n| n.checked_add(CHUNK_FILE_HEADER_BYTES)).ok_or_else(expected)?;
+        let index_bytes = chunks.checked_mul(CHUNK_FILE_INDEX_BYTES as u64).ok_or_else(expected)?;
+        let min_size = index_pos.checked_add(index_bytes).ok_or_else(expected)?;
+        let size = source.seek(SeekFrom::End(0))?;
+        if size < min_size {return Err(expected())};
+
+        let mut index = vec![0; usize::try_from(index_bytes).map_err(|_| expected())?];
+        source.seek(SeekFrom::Start(index_pos))?;
+        source.read_exact(&mut index)?;
+        Ok(ChunkFile {
+            len: usize::try_from(len).map_err(|_| expected())?,
+            aabbs: decode_index(&index),
+            capacity: capacity.max(1),
+            source: Mutex::new(source),
+            cache: Mutex::new(ChunkCache {
+                chunks: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                stats: ChunkCacheStats::default(),
+            }),
+            error: Mutex::new(None),
+        })
+    }
+
+    /// Gets the AABB per chunk of 64 triangles, from the chunk index.
+    pub fn chunk_aabbs(&self) -> &[Aabb] {&self.aabbs}
+
+    /// Builds a ray query from the chunk index, without reading triangles.
+    ///
+    /// Use it with the renderer to read only chunks that overlap tiles,
+    /// see `Renderer::ray_query`.
+    pub fn ray_query(&self) -> RayQuery {RayQuery::from_chunk_aabbs(self.aabbs.iter().copied().map(Some))}
+
+    /// Gets the maximum number of cached chunks.
+    pub fn capacity(&self) -> usize {self.capacity}
+
+    /// Gets the number of cached chunks.
+    pub fn cached(&self) -> usize {self.cache.lock().unwrap().chunks.len()}
+
+    /// Gets cache statistics.
+    pub fn stats(&self) -> ChunkCacheStats {self.cache.lock().unwrap().stats}
+
+    /// Removes all cached chunks and resets statistics.
+    pub fn clear_cache(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.chunks.clear();
+        cache.order.clear();
+        cache.stats = ChunkCacheStats::default();
+    }
+
+    /// Takes the first read error since the last call.
+    pub fn error(&self) -> Option<io::Error> {self.error.lock().unwrap().take()}
+
+    fn read_chunk(&self, offset: usize) -> io::Result<Chunk<Triangle>> {
+        let mut buf = vec![0; CHUNK_FILE_CHUNK_BYTES];
+        let mut source = self.source.lock().unwrap();
+        let pos = CHUNK_FILE_HEADER_BYTES + (offset / 64 * CHUNK_FILE_CHUNK_BYTES) as u64;
+        source.seek(SeekFrom::Start(pos))?;
+        source.read_exact(&mut buf)?;
+        Ok(decode_chunk(&buf))
+    }
+}
+
+impl<R: Read + Seek> Produce<Triangle> for ChunkFile<R> {
+    fn virtual_length(&self) -> usize {self.len}
+    fn produce(&self, offset: usize) -> Chunk<Triangle> {
+        let offset = offset - offset % 64;
+        {
+            let cache = &mut *self.cache.lock().unwrap();
+            cache.tick += 1;
+            if let Some((tick, chunk)) = cache.chunks.get_mut(&offset) {
+                cache.order.remove(tick);
+                *tick = cache.tick;
+                cache.order.insert(cache.tick, offset);
+                cache.stats.hits += 1;
+                return *chunk;
+            }
+        }
+
+        // Read without locking the cache, such that other threads can use it.
+        let chunk = match self.read_chunk(offset) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                self.error.lock().unwrap().get_or_insert(err);
+                return [Default::default(); 64];
+            }
+        };
+        let cache = &mut *self.cache.lock().unwrap();
+        cache.stats.misses += 1;
+        if cache.chunks.contains_key(&offset) {return chunk};
+
+        while cache.chunks.len() >= self.capacity {
+            let Some((_, old)) = cache.order.pop_first() else {break};
+            cache.chunks.remove(&old);
+            cache.stats.evictions += 1;
+        }
+        cache.tick += 1;
+        cache.chunks.insert(offset, (cache.tick, chunk));
+        cache.order.insert(cache.tick, offset);
+        chunk
+    }
+    fn to_internal(&self, offset: usize) -> Option<usize> {
+        if offset < self.len {Some(offset)} else {None}
+    }
+}
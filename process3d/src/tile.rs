@@ -2,13 +2,21 @@
 
 use crate::{PixelPos, Point, RayHit, RayHitAll, TilePos, Triangle, Uv};
 use crate::cam::CameraPerspective;
+use crate::cull::CullObject;
 use crate::frustrum::{
     frustum_planes_tile,
-    frustum_planes_triangle_chunk_mask,
+    frustum_planes_triangle_chunk_exact_mask,
     near_dim,
 };
 use crate::mask::CompressedMasks;
-use crate::ray::{ray_dir, ray_triangle_chunk_hit_update, ray_triangle_chunk_hit_all_update};
+use crate::query::QueryView;
+use crate::ray::ray_dir;
+use crate::soa::{
+    frustum_planes_triangle_chunk_soa_mask,
+    ray_triangle_chunk_soa_hit_all_update,
+    ray_triangle_chunk_soa_hit_update,
+    triangle_chunk_soa,
+};
 use crate::triangle::{chunk_iter, triangle_chunk};
 use crate::produce::Produce;
 
@@ -253,7 +261,7 @@ pub fn tile_mask<T: Produce<Triangle> + ?Sized>(
     loop {
         if i >= n {break}
         let (chunk, bits) = triangle_chunk(list, i);
-        masks.push(frustum_planes_triangle_chunk_mask(&fr, &chunk, bits));
+        masks.push(frustum_planes_triangle_chunk_soa_mask(&fr, &chunk, bits));
         i += 64;
     }
 }
@@ -277,11 +285,152 @@ pub fn tile_mask_with_pre_mask<T: Produce<Triangle> + ?Sized>(
     let mut last_off = 0;
     for (off, (chunk, mask)) in iter {
         for _ in (last_off..off).step_by(64) {masks.push(0)};
-        masks.push(frustum_planes_triangle_chunk_mask(&fr, &chunk, mask));
+        masks.push(frustum_planes_triangle_chunk_soa_mask(&fr, &chunk, mask));
+        last_off = off + 64;
+    }
+}
+
+/// From camera perspective, tile and a list of triangles, get mask of triangles
+/// that intersect the tile, using exact triangle tests.
+///
+/// Only triangles in the pre-masks are tested.
+/// See `frustum_planes_triangle_chunk_exact_mask`.
+///
+/// The algorithm does not clear the masks before pushing new ones.
+pub fn tile_mask_exact<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: Uv,
+    tile_pos: Uv,
+    tile_size: Uv,
+    list: &T,
+    masks: &mut CompressedMasks,
+    pre_masks: &CompressedMasks,
+) {
+    let fr = frustum_planes_tile(persp, dim, tile_pos, tile_size);
+    let iter = chunk_iter(list, pre_masks);
+    let mut last_off = 0;
+    for (off, (chunk, mask)) in iter {
+        for _ in (last_off..off).step_by(64) {masks.push(0)};
+        masks.push(frustum_planes_triangle_chunk_exact_mask(&fr, &chunk, mask));
         last_off = off + 64;
     }
 }
 
+/// From camera perspective, tile and a list of triangles, get mask of intersecting triangles.
+///
+/// Uses the cached AABBs of a ray query to skip whole chunks and groups of chunks
+/// that do not intersect the tile.
+/// The list is in camera coordinates, while the ray query is built over
+/// the same producer in world coordinates, see `QueryView`.
+///
+/// This is used as a preparation stage before sampling each tile in parallel.
+///
+/// The algorithm does not clear the masks before pushing new ones.
+pub fn tile_mask_with_query<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: Uv,
+    tile_pos: Uv,
+    tile_size: Uv,
+    list: &T,
+    query: QueryView,
+    masks: &mut CompressedMasks,
+) {
+    use crate::frustrum::{frustum_planes_aabb_intersect, frustum_planes_transform_inv};
+    use crate::query::GROUP_SIZE;
+
+    let fr = frustum_planes_tile(persp, dim, tile_pos, tile_size);
+    // Cached AABBs are tested against the tile in world coordinates.
+    let world_fr = frustum_planes_transform_inv(&fr, &query.view);
+    let QueryView {query, ..} = query;
+    let n = query.chunks.len();
+    for (g, group) in query.groups.iter().enumerate() {
+        let start = g * GROUP_SIZE;
+        let end = (start + GROUP_SIZE).min(n);
+        let visible = group.is_some_and(|aabb| frustum_planes_aabb_intersect(&world_fr, aabb));
+        for c in start..end {
+            let visible = visible &&
+                query.chunks[c].is_some_and(|aabb| frustum_planes_aabb_intersect(&world_fr, aabb));
+            if visible {
+                let (chunk, bits) = triangle_chunk(list, c * 64);
+                masks.push(frustum_planes_triangle_chunk_soa_mask(&fr, &chunk, bits));
+            } else {
+                masks.push(0);
+            }
+        }
+    }
+}
+
+/// From camera perspective, tile and a list of triangles, get mask of intersecting triangles.
+///
+/// Tests object bounds first, emitting whole zero or one runs for objects that are
+/// outside or inside the tile, see `cull`.
+/// Gives the same masks as `tile_mask` when object bounds contain their triangles.
+///
+/// This is used as a preparation stage before sampling each tile in parallel.
+///
+/// The algorithm does not clear the masks before pushing new ones.
+pub fn tile_mask_with_objects<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: Uv,
+    tile_pos: Uv,
+    tile_size: Uv,
+    list: &T,
+    objects: &[CullObject],
+    masks: &mut CompressedMasks,
+) {
+    use crate::cull::cull_range_bits;
+    use crate::frustrum::{frustum_planes_aabb_inside, frustum_planes_aabb_intersect};
+    use crate::produce::init_chunk_mask;
+
+    let fr = frustum_planes_tile(persp, dim, tile_pos, tile_size);
+    // `None` when the object intersects the tile, otherwise whether it is inside.
+    let inside: Vec<Option<bool>> = objects.iter().map(|obj| {
+        if !frustum_planes_aabb_intersect(&fr, obj.aabb) {Some(false)}
+        else if frustum_planes_aabb_inside(&fr, obj.aabb) {Some(true)}
+        else {None}
+    }).collect();
+
+    let n = list.virtual_length();
+    let mut k = 0;
+    let mut off = 0;
+    while off < n {
+        while k < objects.len() && objects[k].range.end <= off {k += 1};
+
+        // Emit whole runs of chunks covered by a single object.
+        if let Some(obj) = objects.get(k) && obj.range.start <= off && let Some(inside) = inside[k] {
+            let chunks = (obj.range.end.min(n) - off) / 64;
+            if chunks > 0 {
+                masks.push_repeat(if inside {!0} else {0}, chunks);
+                off += chunks * 64;
+                continue;
+            }
+        }
+
+        let valid = init_chunk_mask(n, off);
+        let mut word = 0;
+        let mut covered = 0;
+        let mut test = 0;
+        for (obj, inside) in objects[k..].iter().zip(&inside[k..]) {
+            if obj.range.start >= off + 64 {break};
+
+            let bits = cull_range_bits(&obj.range, off);
+            covered |= bits;
+            match inside {
+                Some(true) => word |= bits,
+                Some(false) => {}
+                None => test |= bits,
+            }
+        }
+        test = (test | !covered) & valid;
+        if test != 0 {
+            let chunk = list.produce(off);
+            word |= frustum_planes_triangle_chunk_soa_mask(&fr, &chunk, test);
+        }
+        masks.push(word & valid);
+        off += 64;
+    }
+}
+
 /// Calculate the normalized tile position.
 ///
 /// Dimension is the size of image in pixels.
@@ -402,6 +551,124 @@ pub fn row_sub_masks<T: Produce<Triangle> + ?Sized + Sync>(
     });
 }
 
+/// Settings of quadtree sub-tiling.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QuadSubTiling {
+    /// The minimum width and height of leaves in pixels.
+    pub min_size: u32,
+    /// The number of triangles at which a node is split.
+    pub triangle_limit: u32,
+}
+
+/// Leaf of quadtree sub-tiling.
+#[derive(Clone, Debug)]
+pub struct QuadTile {
+    /// The position in pixels, relative to the tile.
+    pub pos: PixelPos,
+    /// The size in pixels.
+    pub size: PixelPos,
+    /// Stores compressed masks of triangles intersecting the leaf.
+    pub masks: CompressedMasks,
+}
+
+/// Stores data shared by all nodes when splitting a tile.
+struct QuadSplit<'a, T: ?Sized> {
+    persp: &'a CameraPerspective,
+    ndim: Uv,
+    dim: PixelPos,
+    pos: PixelPos,
+    quad: QuadSubTiling,
+    list: &'a T,
+}
+
+impl<T: Produce<Triangle> + ?Sized> QuadSplit<'_, T> {
+    fn can_split(&self, size: PixelPos) -> bool {
+        let min_size = self.quad.min_size.max(1);
+        size[0] >= 2 * min_size || size[1] >= 2 * min_size
+    }
+
+    fn split(&self, [pos, size]: [PixelPos; 2], masks: &CompressedMasks, leaves: &mut Vec<QuadTile>) {
+        let min_size = self.quad.min_size.max(1);
+        let halves = |n: u32| if n >= 2 * min_size {[(0, n / 2), (n / 2, n - n / 2)]}
+            else {[(0, n), (n, 0)]};
+        let [w, h] = self.dim.map(|n| n as f32);
+        for (y, sh) in halves(size[1]) {
+            for (x, sw) in halves(size[0]) {
+                if sw == 0 || sh == 0 {continue};
+
+                let node_pos = [pos[0] + x, pos[1] + y];
+                let px = [self.pos[0] + node_pos[0], self.pos[1] + node_pos[1]];
+                let tpos = [px[0] as f32 / w * 2.0 - 1.0, px[1] as f32 / h * 2.0 - 1.0];
+                let tsize = [sw as f32 / w * 2.0, sh as f32 / h * 2.0];
+                let mut node_masks = CompressedMasks::new();
+                tile_mask_with_pre_mask(self.persp, self.ndim, tpos, tsize, self.list,
+                    &mut node_masks, masks);
+                if node_masks.count_ones() >= self.quad.triangle_limit as u64 &&
+                   self.can_split([sw, sh])
+                {
+                    self.split([node_pos, [sw, sh]], &node_masks, leaves);
+                } else {
+                    leaves.push(QuadTile {pos: node_pos, size: [sw, sh], masks: node_masks});
+                }
+            }
+        }
+    }
+}
+
+/// Splits a tile recursively into quadtree leaves, from camera perspective,
+/// image resolution, tile position in pixels, tile size and masks of the tile.
+///
+/// A node is split in half along each axis while it intersects at least
+/// `triangle_limit` triangles, down to the minimum size.
+/// Masks of each node are derived from the masks of its parent,
+/// such that dense regions are refined only where needed.
+///
+/// The leaves cover the tile without overlapping.
+/// Returns no leaves when the tile is not split.
+pub fn quad_sub_tiles<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    pos: PixelPos,
+    n_tile_size: u32,
+    quad: QuadSubTiling,
+    list: &T,
+    masks: &CompressedMasks,
+) -> Vec<QuadTile> {
+    let size = [n_tile_size; 2];
+    let split = QuadSplit {persp, ndim: near_dim(persp), dim, pos, quad, list};
+    let mut leaves = vec![];
+    if masks.count_ones() >= quad.triangle_limit as u64 && split.can_split(size) {
+        split.split([[0, 0], size], masks, &mut leaves);
+    }
+    leaves
+}
+
+/// Prepare quadtree sub-tile leaves.
+pub fn pre_quad_masks(dim: PixelPos, n_tile_size: u32) -> Vec<Vec<QuadTile>> {
+    let [w, h] = tile_grid(dim, n_tile_size);
+    vec![vec![]; (w * h) as usize]
+}
+
+/// Collect quadtree sub-tile leaves per tile.
+pub fn quad_masks<T: Produce<Triangle> + ?Sized + Sync>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    n_tile_size: u32,
+    quad: QuadSubTiling,
+    list: &T,
+    masks: &[CompressedMasks],
+    quad_masks: &mut Vec<Vec<QuadTile>>,
+) {
+    use rayon::prelude::*;
+
+    let [w, h] = tile_grid(dim, n_tile_size);
+    quad_masks.resize((w * h) as usize, vec![]);
+    quad_masks.par_iter_mut().enumerate().for_each(|(k, leaves)| {
+        let pos = [k as u32 % w * n_tile_size, k as u32 / w * n_tile_size];
+        *leaves = quad_sub_tiles(persp, dim, pos, n_tile_size, quad, list, &masks[k]);
+    });
+}
+
 /// Collect all masks per tile, using pre-masks at lower resolution.
 ///
 /// This speeds up compression, because one can iterate faster over
@@ -434,6 +701,59 @@ pub fn masks_with_pre_masks<T: Produce<Triangle> + ?Sized + Sync>(
     });
 }
 
+/// Stores statistics of refining masks with exact triangle tests.
+///
+/// Counts are the number of triangles per tile, summed over all tiles.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExactMaskStats {
+    /// The number of triangles using AABB tests.
+    pub aabb: u64,
+    /// The number of triangles using exact tests.
+    pub exact: u64,
+}
+
+impl ExactMaskStats {
+    /// Gets the number of false positives of AABB tests.
+    pub fn false_positives(&self) -> u64 {self.aabb - self.exact}
+
+    /// Gets the ratio of false positives of AABB tests, in range `0.0` to `1.0`.
+    pub fn false_positive_ratio(&self) -> f64 {
+        if self.aabb == 0 {0.0} else {self.false_positives() as f64 / self.aabb as f64}
+    }
+}
+
+/// Refine all masks per tile using exact triangle tests, returning statistics.
+///
+/// The masks should be collected with AABB tests first, e.g. using `masks`.
+/// This removes triangles whose AABB intersects the tile while the triangle does not,
+/// e.g. long diagonal triangles.
+pub fn masks_exact<T: Produce<Triangle> + ?Sized + Sync>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    n_tile_size: u32,
+    list: &T,
+    masks: &mut [CompressedMasks],
+) -> ExactMaskStats {
+    use rayon::prelude::*;
+
+    let w = tile_grid(dim, n_tile_size)[0];
+    let ndim = near_dim(persp);
+    masks.par_iter_mut().enumerate().map(|(k, masks)| {
+        let i = k as u32 % w;
+        let j = k as u32 / w;
+        let tpos = tile_pos(dim, [i, j], n_tile_size);
+        let tsize = tile_size(dim, [i, j], n_tile_size);
+        let mut exact = CompressedMasks::new();
+        tile_mask_exact(persp, ndim, tpos, tsize, list, &mut exact, masks);
+        let stats = ExactMaskStats {aabb: masks.count_ones(), exact: exact.count_ones()};
+        *masks = exact;
+        stats
+    }).reduce(ExactMaskStats::default, |a, b| ExactMaskStats {
+        aabb: a.aabb + b.aabb,
+        exact: a.exact + b.exact,
+    })
+}
+
 /// Collect all masks per tile.
 pub fn masks<T: Produce<Triangle> + ?Sized + Sync>(
     persp: &CameraPerspective,
@@ -456,6 +776,56 @@ pub fn masks<T: Produce<Triangle> + ?Sized + Sync>(
     });
 }
 
+/// Collect all masks per tile, using the cached AABBs of a ray query.
+///
+/// The list is in camera coordinates, see `tile_mask_with_query`.
+pub fn masks_with_query<T: Produce<Triangle> + ?Sized + Sync>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    n_tile_size: u32,
+    list: &T,
+    query: QueryView,
+    masks: &mut [CompressedMasks]
+) {
+    use rayon::prelude::*;
+
+    let w = tile_grid(dim, n_tile_size)[0];
+    let ndim = near_dim(persp);
+    masks.par_iter_mut().enumerate().for_each(|(k,  masks)| {
+        masks.clear();
+        let i = k as u32 % w;
+        let j = k as u32 / w;
+        let tpos = tile_pos(dim, [i, j], n_tile_size);
+        let tsize = tile_size(dim, [i, j], n_tile_size);
+        tile_mask_with_query(persp, ndim, tpos, tsize, list, query, masks);
+    });
+}
+
+/// Collect all masks per tile, testing object bounds first.
+///
+/// Object bounds must be in the coordinates of the producer.
+pub fn masks_with_objects<T: Produce<Triangle> + ?Sized + Sync>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    n_tile_size: u32,
+    list: &T,
+    objects: &[CullObject],
+    masks: &mut [CompressedMasks]
+) {
+    use rayon::prelude::*;
+
+    let w = tile_grid(dim, n_tile_size)[0];
+    let ndim = near_dim(persp);
+    masks.par_iter_mut().enumerate().for_each(|(k,  masks)| {
+        masks.clear();
+        let i = k as u32 % w;
+        let j = k as u32 / w;
+        let tpos = tile_pos(dim, [i, j], n_tile_size);
+        let tsize = tile_size(dim, [i, j], n_tile_size);
+        tile_mask_with_objects(persp, ndim, tpos, tsize, list, objects, masks);
+    });
+}
+
 /// Render depth of a tile using a camera perspective, image resolution,
 /// tile position, tile size and triangle list with mask, into a tile depth and index buffer.
 ///
@@ -472,15 +842,31 @@ pub fn render_tile_depth<T: Produce<Triangle> + ?Sized, const TILE_SIZE: usize>(
     masks: &CompressedMasks,
     tile: &mut [[RayHit; TILE_SIZE]; TILE_SIZE],
 ) {
-    let n_tile_size = TILE_SIZE as u32;
+    render_tile_depth_flat(persp, dim, pos, TILE_SIZE as u32, list, masks, tile.as_flattened_mut())
+}
+
+/// Same as `render_tile_depth`, but with tile size at runtime.
+///
+/// The tile depth and index buffer is stored row by row,
+/// with a length of at least `n_tile_size * n_tile_size`.
+pub fn render_tile_depth_flat<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    pos: PixelPos,
+    n_tile_size: u32,
+    list: &T,
+    masks: &CompressedMasks,
+    tile: &mut [RayHit],
+) {
     let eye = [0.0; 3];
     let iter = chunk_iter(list, masks);
     for (off, (chunk, mask)) in iter {
+        let soa = triangle_chunk_soa(&chunk);
         for j in 0..n_tile_size {
             for i in 0..n_tile_size {
                 let dir: Point = ray_dir(persp, eye, [pos[0] + i, pos[1] + j], dim);
-                ray_triangle_chunk_hit_update((eye, dir), &chunk, mask, off,
-                    &mut tile[j as usize][i as usize]);
+                ray_triangle_chunk_soa_hit_update((eye, dir), &soa, mask, off,
+                    &mut tile[(j * n_tile_size + i) as usize]);
             }
         }
     }
@@ -542,10 +928,38 @@ pub fn render_row_sub_tile_depth_all<T: Produce<Triangle> + ?Sized, const TILE_S
     list: &T,
     sub_masks: &[CompressedMasks],
     tile: &mut [[RayHitAll; TILE_SIZE]; TILE_SIZE],
+) -> bool {
+    let view = TileView {persp, dim, pos, n_tile_size: TILE_SIZE as u32};
+    render_row_sub_tile_depth_all_flat(view, sub_tile_size, list, sub_masks, tile.as_flattened_mut())
+}
+
+/// Render tile with size at runtime.
+#[derive(Copy, Clone, Debug)]
+pub struct TileView<'a> {
+    /// The camera perspective.
+    pub persp: &'a CameraPerspective,
+    /// The image resolution in pixels.
+    pub dim: PixelPos,
+    /// The tile position in pixels.
+    pub pos: PixelPos,
+    /// The tile size in pixels.
+    pub n_tile_size: u32,
+}
+
+/// Same as `render_row_sub_tile_depth_all`, but with tile size at runtime.
+///
+/// The tile depth and index buffer is stored row by row,
+/// with a length of at least `n_tile_size * n_tile_size`.
+pub fn render_row_sub_tile_depth_all_flat<T: Produce<Triangle> + ?Sized>(
+    view: TileView,
+    sub_tile_size: u32,
+    list: &T,
+    sub_masks: &[CompressedMasks],
+    tile: &mut [RayHitAll],
 ) -> bool {
     use crate::IndexFlag;
 
-    let n_tile_size = TILE_SIZE as u32;
+    let TileView {persp, dim, pos, n_tile_size} = view;
     let n = n_tile_size / sub_tile_size;
     let eye = [0.0; 3];
     let mut alive = false;
@@ -558,15 +972,16 @@ pub fn render_row_sub_tile_depth_all<T: Produce<Triangle> + ?Sized, const TILE_S
             let iter = chunk_iter(list, &sub_masks[k as usize]);
             let sub_tile_pos = [ki * sub_tile_size, kj * sub_tile_size];
             for (off, (chunk, mask)) in iter {
+                let soa = triangle_chunk_soa(&chunk);
                 // For each ray in the sub-tile.
                 for j in 0..sub_tile_size {
                     for i in 0..sub_tile_size {
                         let i = sub_tile_pos[0] + i;
                         let j = sub_tile_pos[1] + j;
 
-                        let hit = &mut tile[j as usize][i as usize];
+                        let hit = &mut tile[(j * n_tile_size + i) as usize];
                         let dir: Point = ray_dir(persp, eye, [pos[0] + i, pos[1] + j], dim);
-                        ray_triangle_chunk_hit_all_update((eye, dir), &chunk, mask, off, hit);
+                        ray_triangle_chunk_soa_hit_all_update((eye, dir), &soa, mask, off, hit);
                         if let Some((d, index_flag)) = hit {
                             if !index_flag.flag() {
                                 let ind = index_flag.index();
@@ -581,20 +996,74 @@ pub fn render_row_sub_tile_depth_all<T: Produce<Triangle> + ?Sized, const TILE_S
         }
     }
 
-    // Terminate rays when not hitting anything new.
-    let len = list.virtual_length();
-    for j in 0..TILE_SIZE {
-        for i in 0..TILE_SIZE {
-            let hit = &mut tile[j][i];
-            if let Some((_, index_flag)) = hit {
-                if !index_flag.flag() || index_flag.index() >= len {*hit = None};
+    terminate_rays(list.virtual_length(), &mut tile[..(n_tile_size * n_tile_size) as usize]);
+    alive
+}
+
+/// Render depth of quadtree sub-tiles using a camera perspective, image resolution,
+/// tile position, tile size and triangle list with leaves,
+/// into a tile depth and index buffer.
+///
+/// Works like `render_row_sub_tile_depth_all_flat`,
+/// but each leaf has its own position and size, see `quad_sub_tiles`.
+///
+/// The tile depth and index buffer is stored row by row,
+/// with a length of at least `n_tile_size * n_tile_size`.
+///
+/// Returns `true` if there is something to render.
+/// You can use a loop and break when this is `false`.
+pub fn render_quad_tile_depth_all_flat<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    pos: PixelPos,
+    n_tile_size: u32,
+    list: &T,
+    leaves: &[QuadTile],
+    tile: &mut [RayHitAll],
+) -> bool {
+    use crate::IndexFlag;
+
+    let eye = [0.0; 3];
+    let mut alive = false;
+
+    // For each leaf.
+    for leaf in leaves {
+        let iter = chunk_iter(list, &leaf.masks);
+        for (off, (chunk, mask)) in iter {
+            let soa = triangle_chunk_soa(&chunk);
+            let mut inner_alive = false;
+            // For each ray in the leaf.
+            for j in leaf.pos[1]..leaf.pos[1] + leaf.size[1] {
+                for i in leaf.pos[0]..leaf.pos[0] + leaf.size[0] {
+                    let hit = &mut tile[(j * n_tile_size + i) as usize];
+                    let dir: Point = ray_dir(persp, eye, [pos[0] + i, pos[1] + j], dim);
+                    ray_triangle_chunk_soa_hit_all_update((eye, dir), &soa, mask, off, hit);
+                    if let Some((d, index_flag)) = hit && !index_flag.flag() {
+                        let new_ind = index_flag.index().max(off + 64);
+                        *hit = Some((*d, IndexFlag::from_parts(new_ind, false)));
+                    }
+                    inner_alive |= hit.is_some();
+                }
             }
+            alive |= inner_alive;
+            // Skip the rest of the leaf if there are no rays alive in it.
+            if !inner_alive {break}
         }
     }
 
+    terminate_rays(list.virtual_length(), &mut tile[..(n_tile_size * n_tile_size) as usize]);
     alive
 }
 
+/// Terminate rays when not hitting anything new.
+fn terminate_rays(len: usize, tile: &mut [RayHitAll]) {
+    for hit in tile {
+        if let Some((_, index_flag)) = hit {
+            if !index_flag.flag() || index_flag.index() >= len {*hit = None};
+        }
+    }
+}
+
 /// Render depth of a tile using a camera perspective, image resolution,
 /// tile position, tile size and triangle list with mask, into a tile depth and index buffer.
 ///
@@ -623,20 +1092,36 @@ pub fn render_tile_depth_all<T: Produce<Triangle> + ?Sized, const TILE_SIZE: usi
     list: &T,
     masks: &CompressedMasks,
     tile: &mut [[RayHitAll; TILE_SIZE]; TILE_SIZE],
+) -> bool {
+    render_tile_depth_all_flat(persp, dim, pos, TILE_SIZE as u32, list, masks, tile.as_flattened_mut())
+}
+
+/// Same as `render_tile_depth_all`, but with tile size at runtime.
+///
+/// The tile depth and index buffer is stored row by row,
+/// with a length of at least `n_tile_size * n_tile_size`.
+pub fn render_tile_depth_all_flat<T: Produce<Triangle> + ?Sized>(
+    persp: &CameraPerspective,
+    dim: PixelPos,
+    pos: PixelPos,
+    n_tile_size: u32,
+    list: &T,
+    masks: &CompressedMasks,
+    tile: &mut [RayHitAll],
 ) -> bool {
     use crate::IndexFlag;
 
-    let n_tile_size = TILE_SIZE as u32;
     let eye = [0.0; 3];
     let iter = chunk_iter(list, masks);
     let mut alive = false;
     for (off, (chunk, mask)) in iter {
+        let soa = triangle_chunk_soa(&chunk);
         let mut inner_alive = false;
         for j in 0..n_tile_size {
             for i in 0..n_tile_size {
-                let hit = &mut tile[j as usize][i as usize];
+                let hit = &mut tile[(j * n_tile_size + i) as usize];
                 let dir: Point = ray_dir(persp, eye, [pos[0] + i, pos[1] + j], dim);
-                ray_triangle_chunk_hit_all_update((eye, dir), &chunk, mask, off, hit);
+                ray_triangle_chunk_soa_hit_all_update((eye, dir), &soa, mask, off, hit);
                 if let Some((d, index_flag)) = hit {
                     if !index_flag.flag() {
                         let ind = index_flag.index();
@@ -652,16 +1137,6 @@ pub fn render_tile_depth_all<T: Produce<Triangle> + ?Sized, const TILE_SIZE: usi
         if !inner_alive {return alive}
     }
 
-    // Terminate rays when not hitting anything new.
-    let len = list.virtual_length();
-    for j in 0..TILE_SIZE {
-        for i in 0..TILE_SIZE {
-            let hit = &mut tile[j][i];
-            if let Some((_, index_flag)) = hit {
-                if !index_flag.flag() || index_flag.index() >= len {*hit = None};
-            }
-        }
-    }
-
+    terminate_rays(list.virtual_length(), &mut tile[..(n_tile_size * n_tile_size) as usize]);
     alive
 }
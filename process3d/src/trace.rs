@@ -0,0 +1,270 @@
+//! # Ray tracing
+//!
+//! Traces reflection and refraction rays recursively, e.g. for mirrors and glass.
+//!
+//! A surface function gives the material of each hit,
+//! with weights for mirror reflection and transparency.
+//! Transparent surfaces split into reflection and refraction by the Fresnel equations,
+//! using the index of refraction (IOR) of the surface.
+//! Rays that miss the scene get their color from a miss function, e.g. sky.
+//!
+//! Rays are traced against the whole producer, using the cached AABBs of a `RayQuery`
+//! as broad phase.
+//! The ray query can be built once and shared, e.g. in world coordinates with the renderer.
+//! Ray directions need not be normalized, since rays are normalized when hitting surfaces.
+//! The recursion depth limits the number of bounces,
+//! where the last bounce uses the surface color without secondary rays.
+//!
+//! Normals are geometric and face against the incoming ray,
+//! so triangles are double-sided.
+//! A ray enters a transparent volume when it hits the front face,
+//! where the front face is given by the winding order, see `triangle_plane`.
+
+use crate::{Point, Ray, Rgba, Triangle, Vector};
+use crate::produce::Produce;
+use crate::query::RayQuery;
+use std::borrow::Cow;
+
+/// Surface hit by a ray.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TraceHit {
+    /// The ray that hit the surface.
+    pub ray: Ray,
+    /// The ray depth to the hit.
+    pub depth: f32,
+    /// The index of hit triangle in the virtual list of producer.
+    pub index: usize,
+    /// The internal address of hit triangle in producer.
+    pub internal_offset: Option<usize>,
+    /// The hit position.
+    pub pos: Point,
+    /// The normalized geometric normal, facing against the ray.
+    pub normal: Vector,
+    /// Whether the ray hit the front face of the triangle.
+    pub front_face: bool,
+}
+
+impl TraceHit {
+    /// Creates hit from ray, depth, triangle index and triangle.
+    pub fn new(ray: Ray, depth: f32, index: usize, internal_offset: Option<usize>, tri: Triangle) -> TraceHit {
+        use crate::triangle::triangle_plane;
+        use vecmath::vec3_dot as dot;
+        use vecmath::vec3_neg as neg;
+
+        let (origin, dir) = ray;
+        let pos = [0, 1, 2].map(|i| origin[i] + dir[i] * depth);
+        let n = triangle_plane(tri).0;
+        let front_face = dot(dir, n) < 0.0;
+        let normal = if front_face {n} else {neg(n)};
+        TraceHit {ray, depth, index, internal_offset, pos, normal, front_face}
+    }
+}
+
+/// Surface material for ray tracing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TraceSurface {
+    /// The linear surface color.
+    pub color: Rgba,
+    /// The weight of mirror reflection, in range `0.0` to `1.0`.
+    pub mirror: f32,
+    /// The weight of transparency, in range `0.0` to `1.0`.
+    ///
+    /// Transparency is split into reflection and refraction by the Fresnel equations.
+    pub transparency: f32,
+    /// The index of refraction.
+    pub ior: f32,
+}
+
+impl TraceSurface {
+    /// Creates opaque surface without secondary rays.
+    pub fn opaque(color: Rgba) -> TraceSurface {
+        TraceSurface {color, mirror: 0.0, transparency: 0.0, ior: 1.0}
+    }
+
+    /// Creates mirror surface.
+    pub fn mirror(color: Rgba, mirror: f32) -> TraceSurface {
+        TraceSurface {color, mirror, transparency: 0.0, ior: 1.0}
+    }
+
+    /// Creates fully transparent surface, e.g. glass with IOR `1.5`.
+    pub fn glass(ior: f32) -> TraceSurface {
+        TraceSurface {color: [0.0, 0.0, 0.0, 1.0], mirror: 0.0, transparency: 1.0, ior}
+    }
+
+    /// Returns `true` if the surface needs secondary rays.
+    pub fn has_secondary_rays(&self) -> bool {self.mirror > 0.0 || self.transparency > 0.0}
+}
+
+/// The type of function that gives surface material of a hit.
+pub type TraceSurfaceFn<'a> = Box<dyn Fn(&TraceHit) -> TraceSurface + Sync + 'a>;
+
+/// The type of function that gives linear color of rays that miss the scene.
+pub type TraceMiss<'a> = Box<dyn Fn(Ray) -> Rgba + Sync + 'a>;
+
+/// The type of function that blends traced linear color into color, using weight.
+pub type TraceBlend<'a, Color> = Box<dyn Fn(&mut Color, Rgba, f32) + Sync + 'a>;
+
+/// Ray tracing settings for the renderer.
+///
+/// Primary hits keep the color from the shader,
+/// while the surface function decides which secondary rays to trace.
+pub struct Reflections<'a, Color> {
+    /// The maximum number of bounces.
+    pub max_depth: u32,
+    /// The distance to offset secondary ray origins, to avoid self-intersection.
+    pub bias: f32,
+    /// Gives surface material of a hit, in world coordinates.
+    pub surface: TraceSurfaceFn<'a>,
+    /// Gives linear color of rays that miss the scene, in world coordinates.
+    pub miss: TraceMiss<'a>,
+    /// Blends traced linear color into shaded color, using weight in range `0.0` to `1.0`.
+    pub blend: TraceBlend<'a, Color>,
+}
+
+impl<'a> Reflections<'a, Rgba> {
+    /// Creates reflections for linear colors.
+    pub fn rgba(
+        max_depth: u32,
+        surface: impl Fn(&TraceHit) -> TraceSurface + Sync + 'a,
+        miss: impl Fn(Ray) -> Rgba + Sync + 'a,
+    ) -> Self {
+        Reflections {
+            max_depth,
+            bias: 1e-4,
+            surface: Box::new(surface),
+            miss: Box::new(miss),
+            blend: Box::new(|c, traced, w| {
+                for i in 0..4 {c[i] += (traced[i] - c[i]) * w}
+            }),
+        }
+    }
+}
+
+/// Reflects direction about normal.
+pub fn reflect_dir(dir: Vector, normal: Vector) -> Vector {
+    use vecmath::vec3_dot as dot;
+
+    let d = 2.0 * dot(dir, normal);
+    [dir[0] - d * normal[0], dir[1] - d * normal[1], dir[2] - d * normal[2]]
+}
+
+/// Refracts normalized direction through surface with normal facing against it,
+/// using the ratio of IOR on the incoming side to IOR on the outgoing side.
+///
+/// Returns `None` for total internal reflection.
+pub fn refract_dir(dir: Vector, normal: Vector, eta: f32) -> Option<Vector> {
+    use vecmath::vec3_dot as dot;
+
+    let cos_i = -dot(dir, normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {return None};
+
+    let k = eta * cos_i - (1.0 - sin2_t).sqrt();
+    Some([0, 1, 2].map(|i| eta * dir[i] + k * normal[i]))
+}
+
+/// Calculates the fraction of reflected light for dielectric surfaces,
+/// using the Fresnel equations for unpolarized light.
+///
+/// The cosine is of the angle between the incoming ray and the normal,
+/// and `eta` is the ratio of IOR on the incoming side to IOR on the outgoing side.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {return 1.0};
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Traces rays recursively against a producer.
+pub struct Tracer<'a, P: ?Sized> {
+    /// The producer of triangles.
+    pub list: &'a P,
+    /// The cached broad phase of the producer.
+    pub query: Cow<'a, RayQuery>,
+    /// The maximum number of bounces.
+    pub max_depth: u32,
+    /// The distance to offset secondary ray origins, to avoid self-intersection.
+    pub bias: f32,
+}
+
+impl<'a, P: Produce<Triangle> + ?Sized> Tracer<'a, P> {
+    /// Creates a new tracer, building the broad phase of producer.
+    pub fn new(list: &'a P, max_depth: u32) -> Tracer<'a, P> {
+        Tracer {list, query: Cow::Owned(RayQuery::new(list)), max_depth, bias: 1e-4}
+    }
+
+    /// Creates a new tracer, using a broad phase built over the producer.
+    pub fn with_query(list: &'a P, query: &'a RayQuery, max_depth: u32) -> Tracer<'a, P> {
+        Tracer {list, query: Cow::Borrowed(query), max_depth, bias: 1e-4}
+    }
+
+    /// Finds the closest surface hit by ray.
+    ///
+    /// The hit has the ray with normalized direction, such that depth is the distance.
+    pub fn hit(&self, (origin, dir): Ray) -> Option<TraceHit> {
+        let ray = (origin, vecmath::vec3_normalized(dir));
+        let (depth, index) = self.query.closest_hit(self.list, ray)?;
+        let tri = self.list.produce(index - index % 64)[index % 64];
+        Some(TraceHit::new(ray, depth, index, self.list.to_internal(index), tri))
+    }
+
+    /// Traces ray recursively, returning linear color.
+    ///
+    /// The level is the number of bounces so far.
+    pub fn trace(
+        &self,
+        ray: Ray,
+        level: u32,
+        surface: &dyn Fn(&TraceHit) -> TraceSurface,
+        miss: &dyn Fn(Ray) -> Rgba,
+    ) -> Rgba {
+        let Some(hit) = self.hit(ray) else {return miss(ray)};
+        let s = surface(&hit);
+        if level >= self.max_depth || !s.has_secondary_rays() {return s.color};
+
+        let (traced, w) = self.secondary(&hit, &s, level, surface, miss);
+        [0, 1, 2, 3].map(|i| s.color[i] + (traced[i] - s.color[i]) * w)
+    }
+
+    /// Traces secondary rays of a surface hit.
+    ///
+    /// The ray direction of the hit need not be normalized.
+    /// Returns the traced linear color and its weight relative to the surface color.
+    pub fn secondary(
+        &self,
+        hit: &TraceHit,
+        s: &TraceSurface,
+        level: u32,
+        surface: &dyn Fn(&TraceHit) -> TraceSurface,
+        miss: &dyn Fn(Ray) -> Rgba,
+    ) -> (Rgba, f32) {
+        use vecmath::vec3_dot as dot;
+
+        let w = (s.mirror + s.transparency).min(1.0);
+        if w <= 0.0 {return ([0.0; 4], 0.0)};
+
+        let dir = vecmath::vec3_normalized(hit.ray.1);
+        let n = hit.normal;
+        let offset = |sign: f32| [0, 1, 2].map(|i| hit.pos[i] + sign * self.bias * n[i]);
+        let reflected = || self.trace((offset(1.0), reflect_dir(dir, n)), level + 1, surface, miss);
+
+        let mut color = [0.0; 4];
+        let mut add = |c: Rgba, k: f32| for i in 0..4 {color[i] += c[i] * k};
+        let mut reflection = s.mirror;
+        if s.transparency > 0.0 {
+            let eta = if hit.front_face {1.0 / s.ior} else {s.ior};
+            let kr = fresnel_dielectric(-dot(dir, n), eta);
+            reflection += s.transparency * kr;
+            if let Some(refracted) = refract_dir(dir, n, eta) && kr < 1.0 {
+                add(self.trace((offset(-1.0), refracted), level + 1, surface, miss),
+                    s.transparency * (1.0 - kr));
+            }
+        }
+        if reflection > 0.0 {add(reflected(), reflection)};
+        ([0, 1, 2, 3].map(|i| color[i] / w), w)
+    }
+}
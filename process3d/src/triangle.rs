@@ -29,6 +29,31 @@ pub fn triangle_aabb((a, b, c): Triangle) -> Aabb {
     ([minx, miny, minz], [maxx, maxy, maxz])
 }
 
+/// Triangle chunk AABB, using the triangles enabled in mask.
+///
+/// Returns `None` if the mask is zero.
+pub fn triangle_chunk_aabb(chunk: &Chunk<Triangle>, mask: u64) -> Option<Aabb> {
+    let mut res: Option<Aabb> = None;
+    for (i, &tri) in chunk.iter().enumerate() {
+        if (mask >> i) & 1 != 1 {continue};
+
+        let (mi, ma) = triangle_aabb(tri);
+        res = Some(match res {
+            None => (mi, ma),
+            Some(aabb) => aabb_union(aabb, (mi, ma)),
+        });
+    }
+    res
+}
+
+/// Smallest AABB containing two AABBs.
+pub fn aabb_union((a0, a1): Aabb, (b0, b1): Aabb) -> Aabb {
+    (
+        [a0[0].min(b0[0]), a0[1].min(b0[1]), a0[2].min(b0[2])],
+        [a1[0].max(b1[0]), a1[1].max(b1[1]), a1[2].max(b1[2])],
+    )
+}
+
 /// Returns true if point is front of triangle.
 pub fn triangle_point_front(tri: Triangle, p: Point) -> bool {
     use crate::frustrum::plane_point_front;
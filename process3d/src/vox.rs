@@ -0,0 +1,159 @@
+//! # MagicaVoxel format
+//!
+//! Reads palette-indexed voxels into any consumer and writes voxel lists,
+//! using the `.vox` format of MagicaVoxel.
+//!
+//! The material of a voxel is its palette index, in range `1..=255`.
+//! Palette index `0` means empty space and is never used by voxels.
+//! The palette is indexed directly by the material,
+//! such that `palette[mat]` is the color of a voxel.
+//!
+//! When a file contains multiple models, the voxels of all models are read
+//! in the model coordinates, since scene graph transforms are ignored.
+//!
+//! Since any consumer of `(Point, Material)` consumes `(Point<u8>, Material)`,
+//! voxels can be read directly into e.g. `Vec<(Triangle, u8)>`.
+
+use std::io::{self, Read, Write};
+
+use crate::{Point, Rgba};
+use crate::consume::Consumer;
+
+fn invalid(msg: String) -> io::Error {io::Error::new(io::ErrorKind::InvalidData, msg)}
+
+/// Stores the palette of a `.vox` file, indexed by material.
+pub type VoxPalette = [Rgba<u8>; 256];
+
+/// Returns the default palette of MagicaVoxel.
+///
+/// This is used when a file has no palette.
+pub fn vox_default_palette() -> VoxPalette {
+    let mut palette = [[0; 4]; 256];
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let mut i = 1;
+    // Color cube, without black.
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if i == 216 {break};
+                palette[i] = [r, g, b, 255];
+                i += 1;
+            }
+        }
+    }
+    // Red, green, blue and gray ramps.
+    for ch in 0..4 {
+        for x in ramp {
+            palette[i] = if ch < 3 {
+                let mut c = [0, 0, 0, 255];
+                c[ch] = x;
+                c
+            } else {[x, x, x, 255]};
+            i += 1;
+        }
+    }
+    palette
+}
+
+fn vox_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("Unexpected end of `.vox` file".into()))
+}
+
+/// Reads voxels from `.vox` format into consumer.
+///
+/// Returns the palette, or the default palette if the file has none.
+pub fn vox_read<R, C>(mut r: R, consumer: &mut C) -> io::Result<VoxPalette>
+    where R: Read, C: Consumer<(Point<u8>, u8)>
+{
+    let mut data = vec![];
+    r.read_to_end(&mut data)?;
+
+    if !data.starts_with(b"VOX ") {return Err(invalid("Expected `VOX ` at start of file".into()))};
+    if data.get(8..12) != Some(b"MAIN") {return Err(invalid("Expected `MAIN` chunk".into()))};
+
+    let main_content = vox_u32(&data, 12)? as usize;
+    let main_children = vox_u32(&data, 16)? as usize;
+    let start = 20 + main_content;
+    let end = start + main_children;
+    if end > data.len() {return Err(invalid("Unexpected end of `.vox` file".into()))};
+
+    let f = consumer.consumer();
+    let mut palette = None;
+    let mut pos = start;
+    while pos < end {
+        let id = &data[pos..(pos + 4).min(end)];
+        let content = vox_u32(&data, pos + 4)? as usize;
+        let children = vox_u32(&data, pos + 8)? as usize;
+        let content_start = pos + 12;
+        pos = content_start + content + children;
+        if pos > end {return Err(invalid("Unexpected end of `.vox` chunk".into()))};
+
+        let content = &data[content_start..content_start + content];
+        match id {
+            b"XYZI" => {
+                let n = vox_u32(content, 0)? as usize;
+                let voxels = content.get(4..4 + 4 * n)
+                    .ok_or_else(|| invalid(format!("Expected {} voxels", n)))?;
+                for v in voxels.chunks_exact(4) {
+                    f(consumer, ([v[0], v[1], v[2]], v[3]));
+                }
+            }
+            b"RGBA" => {
+                if content.len() < 1024 {return Err(invalid("Expected 256 palette colors".into()))};
+                let mut p = [[0; 4]; 256];
+                // Color `i` in file is used by palette index `i + 1`.
+                for (i, c) in content[..1020].chunks_exact(4).enumerate() {
+                    p[i + 1] = [c[0], c[1], c[2], c[3]];
+                }
+                palette = Some(p);
+            }
+            _ => {}
+        }
+    }
+    Ok(palette.unwrap_or_else(vox_default_palette))
+}
+
+/// Writes voxels in `.vox` format as a single model.
+///
+/// The model size is the smallest size that contains all voxels.
+/// Returns an error if some voxel uses palette index `0`.
+pub fn vox_write<W: Write>(
+    w: &mut W,
+    voxels: &[(Point<u8>, u8)],
+    palette: &VoxPalette,
+) -> io::Result<()> {
+    if voxels.iter().any(|&(_, mat)| mat == 0) {
+        return Err(invalid("Palette index `0` can not be used by voxels".into()));
+    }
+    let n = u32::try_from(voxels.len()).ok()
+        .filter(|&n| n <= u32::MAX / 8)
+        .ok_or_else(|| invalid("Too many voxels for `.vox`".into()))?;
+
+    let mut size = [1_u32; 3];
+    for &(p, _) in voxels {
+        for i in 0..3 {size[i] = size[i].max(p[i] as u32 + 1)}
+    }
+
+    let chunk = |w: &mut W, id: &[u8; 4], content: u32, children: u32| -> io::Result<()> {
+        w.write_all(id)?;
+        w.write_all(&content.to_le_bytes())?;
+        w.write_all(&children.to_le_bytes())
+    };
+    let size_len = 12;
+    let xyzi_len = 4 + 4 * n;
+    let rgba_len = 1024;
+    w.write_all(b"VOX ")?;
+    w.write_all(&150_u32.to_le_bytes())?;
+    chunk(w, b"MAIN", 0, 3 * 12 + size_len + xyzi_len + rgba_len)?;
+    chunk(w, b"SIZE", size_len, 0)?;
+    for x in size {w.write_all(&x.to_le_bytes())?}
+    chunk(w, b"XYZI", xyzi_len, 0)?;
+    w.write_all(&n.to_le_bytes())?;
+    for &(p, mat) in voxels {w.write_all(&[p[0], p[1], p[2], mat])?}
+    chunk(w, b"RGBA", rgba_len, 0)?;
+    for c in &palette[1..] {w.write_all(c)?}
+    w.write_all(&[0; 4])
+}
@@ -1,15 +1,17 @@
 //! Helper methods for colors
+//!
+//! Color spaces and conversions are shared with `turbine_process3d` through `turbine_color`.
 
 use crate::types::{Color, ColorComponent};
+use turbine_color::{
+    rgba_convert,
+    rgba_gamma_linear_to_srgb,
+    rgba_gamma_srgb_to_linear,
+    rgba_linear_to_space,
+    rgba_space_to_linear,
+};
 
-/// Color space.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum ColorSpace {
-    /// Linear color space.
-    Linear,
-    /// sRGB color space.
-    SRGB,
-}
+pub use turbine_color::{ColorSpace, Premultiplied};
 
 /// White color.
 pub const WHITE: Color = [1.0; 4];
@@ -44,33 +46,12 @@ pub fn hex(hex: &str) -> Color {
      color[3] as f32 * inv_255]
 }
 
-#[inline(always)]
-fn component_srgb_to_linear(f: ColorComponent) -> ColorComponent {
-    if f <= 0.04045 {
-        f / 12.92
-    } else {
-        ((f + 0.055) / 1.055).powf(2.4)
-    }
-}
-
 /// Converts gamma (brightness) from sRGB to linear color space.
 ///
 /// sRGB is the default color space for image editors, pictures, internet etc.
 /// Linear gamma yields better results when doing math with colors.
 pub fn gamma_srgb_to_linear(c: Color) -> Color {
-    [component_srgb_to_linear(c[0]),
-     component_srgb_to_linear(c[1]),
-     component_srgb_to_linear(c[2]),
-     c[3]]
-}
-
-#[inline(always)]
-fn component_linear_to_srgb(f: ColorComponent) -> ColorComponent {
-    if f <= 0.0031308 {
-        f * 12.92
-    } else {
-        1.055 * f.powf(1.0 / 2.4) - 0.055
-    }
+    rgba_gamma_srgb_to_linear(c)
 }
 
 /// Converts gamma (brightness) of a color from linear color space to sRGB.
@@ -78,8 +59,20 @@ fn component_linear_to_srgb(f: ColorComponent) -> ColorComponent {
 /// sRGB is the default color space for image editors, pictures, internet etc.
 /// Linear gamma yields better results when doing math with colors.
 pub fn gamma_linear_to_srgb(c: Color) -> Color {
-    [component_linear_to_srgb(c[0]),
-     component_linear_to_srgb(c[1]),
-     component_linear_to_srgb(c[2]),
-     c[3]]
+    rgba_gamma_linear_to_srgb(c)
+}
+
+/// Converts color from linear color space to some color space.
+pub fn linear_to_space(c: Color, space: ColorSpace) -> Color {
+    rgba_linear_to_space(c, space)
+}
+
+/// Converts color from some color space to linear color space.
+pub fn space_to_linear(c: Color, space: ColorSpace) -> Color {
+    rgba_space_to_linear(c, space)
+}
+
+/// Converts color between color spaces.
+pub fn convert(c: Color, from: ColorSpace, to: ColorSpace) -> Color {
+    rgba_convert(c, from, to)
 }
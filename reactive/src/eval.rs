@@ -890,15 +890,11 @@ impl<T> Reactor<T> {
     ) -> Color
         where T: Float + Cast<f32>, f64: Cast<T>
     {
-        use color::gamma_linear_to_srgb;
+        use color::linear_to_space;
         use fns::Color::*;
-        use color::ColorSpace::*;
 
         match self[id] {
-            Data(data) => match space {
-                Linear => data,
-                SRGB => gamma_linear_to_srgb(data)
-            },
+            Data(data) => linear_to_space(data, space),
             Time1(f, t) => {
                 let t = self.eval1(t, env);
                 self.eval_color_spline(f, t, space, env)
@@ -916,7 +912,7 @@ impl<T> Reactor<T> {
     ) -> Color
         where T: Float + Cast<f32>, f64: Cast<T>
     {
-        use color::gamma_linear_to_srgb;
+        use color::linear_to_space;
         use color::ColorSpace::*;
         use fns::ColorSpline::*;
         use vecmath::vec4_add as add;
@@ -928,10 +924,7 @@ impl<T> Reactor<T> {
                 let a = self.eval_color(a, Linear, env);
                 let b = self.eval_color(b, Linear, env);
                 let ab = add(scale(a, 1.0 - t), scale(b, t));
-                match space {
-                    Linear => ab,
-                    SRGB => gamma_linear_to_srgb(ab)
-                }
+                linear_to_space(ab, space)
             }
         }
     }
@@ -1,6 +1,7 @@
 #![deny(missing_docs)]
 #![doc = include_str!("../README.md")]
 
+pub use turbine_color as color;
 pub use turbine_process3d as process3d;
 pub use turbine_reactive as reactive;
 pub use turbine_scene3d as scene3d;